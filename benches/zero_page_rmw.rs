@@ -0,0 +1,47 @@
+// Throughput of the zero-page read-modify-write instructions, run with
+// `cargo bench --bench zero_page_rmw`
+// Each loop runs INC, DEC, ASL, LSR, ROL and ROR twice, once plain and once indexed
+// by X, then jumps back. The absolute loop does the same work through the generic
+// addressing path, for comparison with the zero-page fast path
+use nes::hardware::CPU;
+use std::hint::black_box;
+use std::time::Instant;
+
+const INSTRUCTIONS: u32 = 2_000_000;
+const ROUNDS: u32 = 5;
+
+// INC $10, DEC $11, ASL $12, LSR $13, ROL $14, ROR $15, the same on $10,X..$15,X,
+// then JMP $8000
+const ZERO_PAGE_LOOP: [u8; 27] = [
+    0xe6, 0x10, 0xc6, 0x11, 0x06, 0x12, 0x46, 0x13, 0x26, 0x14, 0x66, 0x15, 0xf6, 0x10, 0xd6, 0x11,
+    0x16, 0x12, 0x56, 0x13, 0x36, 0x14, 0x76, 0x15, 0x4c, 0x00, 0x80,
+];
+
+// The same loop on $0010..$0015 and $0010,X..$0015,X
+const ABSOLUTE_LOOP: [u8; 39] = [
+    0xee, 0x10, 0x00, 0xce, 0x11, 0x00, 0x0e, 0x12, 0x00, 0x4e, 0x13, 0x00, 0x2e, 0x14, 0x00, 0x6e,
+    0x15, 0x00, 0xfe, 0x10, 0x00, 0xde, 0x11, 0x00, 0x1e, 0x12, 0x00, 0x5e, 0x13, 0x00, 0x3e, 0x14,
+    0x00, 0x7e, 0x15, 0x00, 0x4c, 0x00, 0x80,
+];
+
+// Best time per instruction over a few rounds, in nanoseconds
+fn bench(name: &str, program: &[u8]) {
+    let mut best = f64::MAX;
+    for _ in 0..ROUNDS {
+        let mut cpu = CPU::new();
+        cpu.load(program.to_vec());
+        cpu.reset();
+        let start = Instant::now();
+        for _ in 0..INSTRUCTIONS {
+            black_box(cpu.step().unwrap());
+        }
+        let elapsed = start.elapsed().as_nanos() as f64 / INSTRUCTIONS as f64;
+        best = best.min(elapsed);
+    }
+    println!("{:<10} {:>7.1} ns/instruction", name, best);
+}
+
+fn main() {
+    bench("zero page", &ZERO_PAGE_LOOP);
+    bench("absolute", &ABSOLUTE_LOOP);
+}
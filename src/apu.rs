@@ -0,0 +1,1095 @@
+use crate::audio::AudioSink;
+use crate::error::EmuError;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+// CPU clock (NTSC) and the rate that mixed samples are collected at
+const CPU_CLOCK_RATE: u32 = 1_789_773;
+pub const SAMPLE_RATE: u32 = 44_100;
+
+// Samples kept when the frontend does not drain them, about a second of sound
+const SAMPLE_CAPACITY: usize = SAMPLE_RATE as usize;
+
+// Samples gathered before they are handed to an installed audio sink
+const SINK_BATCH: usize = 1024;
+
+// Timer periods of the DMC in CPU cycles, indexed by the rate bits of 0x4010 (NTSC)
+const DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+// CPU cycles lost each time the DMC takes the bus to fetch a sample byte
+pub const DMC_FETCH_STALL: u16 = 4;
+
+// Note lengths shared by every length counter, indexed by the top five bits
+// written to a channel's length register
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Output waveform of each pulse duty cycle: 12.5%, 25%, 50% and 25% negated
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// CPU cycles between frame sequencer steps (NTSC); the 4-step sequence ends
+// after the fourth entry, the 5-step sequence after the fifth
+const FRAME_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+
+// Combine channel levels through the NES's nonlinear DACs into a sample in 0.0-1.0
+// Pulse, triangle and noise levels are 0-15; the DMC level is 0-127
+fn nonlinear_mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse_sum = (pulse1 + pulse2) as f32;
+    let pulse_out = if pulse_sum == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse_sum + 100.0)
+    };
+
+    let tnd_sum = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    let tnd_out = if tnd_sum == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd_sum + 100.0)
+    };
+
+    pulse_out + tnd_out
+}
+
+// Channel levels packed into one integer, with no mixing or resampling, so the
+// stream is identical on every platform: pulse 1 in bits 0-3, pulse 2 in bits 4-7
+// and the DMC in bits 8-14
+fn raw_sample(pulse1: &Pulse, pulse2: &Pulse, dmc: &Dmc) -> i16 {
+    pulse1.output() as i16 | (pulse2.output() as i16) << 4 | (dmc.output_level as i16) << 8
+}
+
+// Counts a note down to silence, clocked on half frames
+pub struct LengthCounter {
+    enabled: bool,   // Channel enable bit in 0x4015
+    halt: bool,      // Freeze the counter, so the note plays until stopped
+    pub counter: u8, // Half frames left before the channel is silenced
+}
+
+impl LengthCounter {
+    fn new() -> Self {
+        LengthCounter {
+            enabled: false,
+            halt: false,
+            counter: 0,
+        }
+    }
+
+    // Load a note length from LENGTH_TABLE; ignored while the channel is disabled
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[index as usize];
+        }
+    }
+
+    // Disabling a channel silences it immediately
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+// Volume of a channel: either constant or a sawtooth decaying 15 to 0
+struct Envelope {
+    start: bool,    // Restart the decay on the next quarter frame
+    looping: bool,  // Restart at 15 once the decay reaches 0
+    constant: bool, // Output `volume` directly instead of the decay level
+    volume: u8,     // Constant volume, or the divider period
+    divider: u8,    // Quarter frames until the decay level next drops
+    decay: u8,      // Current decay level
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            start: false,
+            looping: false,
+            constant: false,
+            volume: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+// Bends a pulse channel's period up or down every few half frames (0x4001/0x4005)
+struct Sweep {
+    enabled: bool,         // Bit 7
+    period: u8,            // Bits 4-6: half frames between adjustments, minus one
+    negate: bool,          // Bit 3: lower the period (raise the pitch) instead
+    shift: u8,             // Bits 0-2: the change is the period shifted right by this
+    ones_complement: bool, // Pulse 1 negates with one's complement, subtracting one more
+    divider: u8,           // Half frames until the next adjustment
+    reload: bool,          // Reset the divider on the next half frame
+}
+
+impl Sweep {
+    fn new(ones_complement: bool) -> Self {
+        Sweep {
+            enabled: false,
+            period: 0,
+            negate: false,
+            shift: 0,
+            ones_complement,
+            divider: 0,
+            reload: false,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        self.enabled = data & 0b1000_0000 != 0;
+        self.period = (data >> 4) & 0b111;
+        self.negate = data & 0b0000_1000 != 0;
+        self.shift = data & 0b111;
+        self.reload = true;
+    }
+
+    // Period the sweep is heading towards, computed continuously
+    fn target_period(&self, timer_period: u16) -> u16 {
+        let change = timer_period >> self.shift;
+        if !self.negate {
+            timer_period + change
+        } else if self.ones_complement {
+            timer_period.saturating_sub(change + 1)
+        } else {
+            timer_period.saturating_sub(change)
+        }
+    }
+
+    // The channel is silenced when the period is below 8 or the target is out of range,
+    // whether or not the sweep is enabled
+    fn mutes(&self, timer_period: u16) -> bool {
+        timer_period < 8 || self.target_period(timer_period) > 0x7ff
+    }
+
+    // Clock the divider on a half frame, returning the new timer period
+    fn clock(&mut self, timer_period: u16) -> u16 {
+        let mut period = timer_period;
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.mutes(timer_period) {
+            period = self.target_period(timer_period);
+        }
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+        period
+    }
+}
+
+// Square wave channel (0x4000-0x4003 and 0x4004-0x4007)
+pub struct Pulse {
+    duty: u8,                  // Row of DUTY_TABLE
+    duty_step: u8,             // Position within the waveform
+    timer_period: u16,         // 11-bit period in APU cycles
+    timer: u16,                // APU cycles until the next step
+    envelope: Envelope,        // Volume
+    sweep: Sweep,              // Period bending
+    pub length: LengthCounter, // Note length
+}
+
+impl Pulse {
+    // Constructor to create a new, silent pulse channel; `channel` is 1 or 2,
+    // which differ in how the sweep negates
+    pub fn new(channel: u8) -> Self {
+        Pulse {
+            duty: 0,
+            duty_step: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::new(),
+            sweep: Sweep::new(channel == 1),
+            length: LengthCounter::new(),
+        }
+    }
+
+    // Handle a write to one of the channel's four registers (0-3)
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0b0010_0000 != 0;
+                self.envelope.looping = data & 0b0010_0000 != 0;
+                self.envelope.constant = data & 0b0001_0000 != 0;
+                self.envelope.volume = data & 0x0f;
+            }
+            1 => self.sweep.write(data),
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00ff) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                // Starting a note restarts the waveform and the envelope
+                self.duty_step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // Advance the timer by one APU cycle (two CPU cycles)
+    fn tick(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.duty_step = (self.duty_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // 11-bit timer period, which sets the pitch
+    pub fn timer_period(&self) -> u16 {
+        self.timer_period
+    }
+
+    fn clock_sweep(&mut self) {
+        self.timer_period = self.sweep.clock(self.timer_period);
+    }
+
+    // Current level, 0-15
+    pub fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.sweep.mutes(self.timer_period)
+            || DUTY_TABLE[self.duty as usize][self.duty_step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    // State reported by APU::apu_status
+    fn status(&self) -> PulseStatus {
+        PulseStatus {
+            enabled: self.length.enabled,
+            length: self.length.counter,
+            output: self.output(),
+        }
+    }
+}
+
+// Delta modulation channel: plays 1-bit delta samples read from CPU memory
+pub struct Dmc {
+    irq_enabled: bool,         // 0x4010 bit 7
+    looping: bool,             // 0x4010 bit 6: restart the sample when it ends
+    period: u16,               // Timer period in CPU cycles, from 0x4010 bits 0-3
+    timer: u16,                // CPU cycles until the output unit is next clocked
+    pub output_level: u8,      // 7-bit DAC level, set directly by 0x4011
+    sample_address: u16,       // Start of the sample, 0xC000 + 64 * 0x4012
+    sample_length: u16,        // Length of the sample in bytes, 16 * 0x4013 + 1
+    current_address: u16,      // Next byte for the memory reader to fetch
+    pub bytes_remaining: u16,  // Bytes of the sample not yet fetched
+    sample_buffer: Option<u8>, // Fetched byte waiting for the output unit
+    shift_register: u8,        // Byte being played, least significant bit first
+    bits_remaining: u8,        // Bits left in the shift register
+    silence: bool,             // The output unit ran out of data
+    irq_flag: bool,            // Raised when a non-looping sample finishes
+}
+
+impl Dmc {
+    // Constructor to create a new, silent DMC
+    pub fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            period: DMC_RATES[0],
+            timer: DMC_RATES[0],
+            output_level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq_flag: false,
+        }
+    }
+
+    // Handle a write to one of the DMC registers (0x4010-0x4013)
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x4010 => {
+                self.irq_enabled = data & 0b1000_0000 != 0;
+                self.looping = data & 0b0100_0000 != 0;
+                self.period = DMC_RATES[(data & 0x0f) as usize];
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
+            }
+            0x4011 => self.output_level = data & 0x7f,
+            0x4012 => self.sample_address = 0xc000 + data as u16 * 64,
+            0x4013 => self.sample_length = data as u16 * 16 + 1,
+            _ => {}
+        }
+    }
+
+    // Start or stop the sample, as set by bit 4 of 0x4015
+    // Any write to 0x4015 also acknowledges the DMC interrupt
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Whether the interrupt line is held low by the end of a sample
+    pub fn irq(&self) -> bool {
+        self.irq_flag
+    }
+
+    // Address the memory reader wants to fetch, if the sample buffer is empty
+    pub fn fetch_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    // Hand the memory reader the byte it fetched from fetch_address
+    pub fn fill_sample_buffer(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // The address wraps from 0xFFFF back to 0x8000
+        self.current_address = match self.current_address {
+            0xffff => 0x8000,
+            address => address + 1,
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    // Advance the timer by one CPU cycle
+    fn tick(&mut self) {
+        self.timer -= 1;
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.clock_output();
+        }
+    }
+
+    // Move the DAC two steps up or down for the next bit of the sample
+    fn clock_output(&mut self) {
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.shift_register = data;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Live state of a pulse channel, for debuggers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PulseStatus {
+    pub enabled: bool, // Channel enable bit in 0x4015
+    pub length: u8,    // Length counter value
+    pub output: u8,    // Current level, 0-15
+}
+
+// Live state of every channel, read without the side effects of 0x4015
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApuStatus {
+    pub pulse1: PulseStatus,
+    pub pulse2: PulseStatus,
+    pub dmc_bytes_remaining: u16, // Sample bytes not yet fetched; the DMC has no length counter
+    pub dmc_output: u8,           // DMC DAC level, 0-127
+    pub frame_irq: bool,          // Frame interrupt raised and not yet acknowledged
+    pub dmc_irq: bool,            // DMC interrupt raised and not yet acknowledged
+}
+
+// Define the APU struct holding the sound channels
+pub struct APU {
+    pub pulse1: Pulse,                // First square wave channel
+    pub pulse2: Pulse,                // Second square wave channel
+    pub dmc: Dmc,                     // Delta modulation channel
+    frame_cycle: u32,                 // CPU cycles into the frame sequence
+    five_step: bool,                  // 0x4017 bit 7: use the 5-step sequence
+    irq_inhibit: bool,                // 0x4017 bit 6: the frame sequencer raises no IRQ
+    frame_irq: bool,                  // Raised at the end of each 4-step sequence
+    odd_cycle: bool,                  // Pulse timers run on every second CPU cycle
+    sample_clock: u32,                // Gains SAMPLE_RATE a cycle; sample due at CPU_CLOCK_RATE
+    samples: Vec<f32>,                // Mixed output not yet taken by the frontend
+    sink: Option<Box<dyn AudioSink>>, // Receives samples in batches, if installed
+    raw_samples: Option<Vec<i16>>,    // Channel levels every CPU cycle, while recording
+}
+
+impl APU {
+    // Constructor to create a new APU instance
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(1),
+            pulse2: Pulse::new(2),
+            dmc: Dmc::new(),
+            frame_cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            odd_cycle: false,
+            sample_clock: 0,
+            samples: Vec::with_capacity(SAMPLE_CAPACITY), // Never grows past this
+            sink: None,
+            raw_samples: None,
+        }
+    }
+
+    // Handle a CPU write to an APU register
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse1.write_register(address & 0b11, data),
+            0x4004..=0x4007 => self.pulse2.write_register(address & 0b11, data),
+            0x4010..=0x4013 => self.dmc.write_register(address, data),
+            // Channel enables: bit 0 pulse 1, bit 1 pulse 2, bit 4 DMC
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0b0000_0001 != 0);
+                self.pulse2.length.set_enabled(data & 0b0000_0010 != 0);
+                self.dmc.set_enabled(data & 0b0001_0000 != 0);
+            }
+            0x4017 => {
+                self.five_step = data & 0b1000_0000 != 0;
+                self.irq_inhibit = data & 0b0100_0000 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                // Selecting the 5-step sequence clocks the units straight away
+                if self.five_step {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+            }
+            _ => {} // Not emulated yet
+        }
+    }
+
+    // Handle a CPU read of 0x4015: bits 0 and 1 report running pulse length counters,
+    // bit 4 a DMC sample in progress, bit 6 the frame interrupt and bit 7 the DMC
+    // interrupt. The read acknowledges the frame interrupt
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        if self.pulse1.length.counter > 0 {
+            status |= 0b0000_0001;
+        }
+        if self.pulse2.length.counter > 0 {
+            status |= 0b0000_0010;
+        }
+        if self.dmc.bytes_remaining > 0 {
+            status |= 0b0001_0000;
+        }
+        if self.frame_irq {
+            status |= 0b0100_0000;
+        }
+        if self.dmc.irq() {
+            status |= 0b1000_0000;
+        }
+        self.frame_irq = false;
+        status
+    }
+
+    // Report every channel's state like read_status(), but without acknowledging
+    // the frame interrupt, so a debugger can show it live
+    pub fn apu_status(&self) -> ApuStatus {
+        ApuStatus {
+            pulse1: self.pulse1.status(),
+            pulse2: self.pulse2.status(),
+            dmc_bytes_remaining: self.dmc.bytes_remaining,
+            dmc_output: self.dmc.output_level,
+            frame_irq: self.frame_irq,
+            dmc_irq: self.dmc.irq(),
+        }
+    }
+
+    // Advance every channel by a number of CPU cycles
+    pub fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.dmc.tick();
+            if self.odd_cycle {
+                self.pulse1.tick();
+                self.pulse2.tick();
+            }
+            self.odd_cycle = !self.odd_cycle;
+            self.tick_frame_sequencer();
+            if let Some(raw) = &mut self.raw_samples {
+                raw.push(raw_sample(&self.pulse1, &self.pulse2, &self.dmc));
+            }
+            self.sample_clock += SAMPLE_RATE;
+            if self.sample_clock >= CPU_CLOCK_RATE {
+                self.sample_clock -= CPU_CLOCK_RATE;
+                if self.samples.len() < SAMPLE_CAPACITY {
+                    self.samples.push(self.mix());
+                }
+                if let Some(sink) = &mut self.sink {
+                    // At least, as samples may have piled up before the sink was installed
+                    if self.samples.len() >= SINK_BATCH {
+                        sink.push_samples(&self.samples);
+                        self.samples.clear();
+                    }
+                }
+            }
+        }
+    }
+
+    // Send samples to a sink in batches of SINK_BATCH instead of holding them
+    // for samples() and clear_samples()
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.sink = Some(sink);
+    }
+
+    // Samples at SAMPLE_RATE collected since the last clear_samples()
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    // Forget the collected samples once the frontend has queued them
+    pub fn clear_samples(&mut self) {
+        self.samples.clear();
+    }
+
+    // Take over the audio sink and raw sample recording of an APU this one replaces,
+    // so swapping the cartridge keeps the frontend's audio hooked up
+    pub fn take_outputs_from(&mut self, previous: &mut APU) {
+        self.sink = previous.sink.take();
+        self.raw_samples = previous.raw_samples.take();
+    }
+
+    // Start or stop recording raw samples for take_raw_samples()
+    // Meant for regression tests: the stream grows by one sample per CPU cycle
+    pub fn record_raw_samples(&mut self, record: bool) {
+        self.raw_samples = if record { Some(Vec::new()) } else { None };
+    }
+
+    // Hand over the raw samples recorded so far
+    pub fn take_raw_samples(&mut self) -> Vec<i16> {
+        self.raw_samples
+            .as_mut()
+            .map(core::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn tick_frame_sequencer(&mut self) {
+        self.frame_cycle += 1;
+        let last_step = if self.five_step { 4 } else { 3 };
+        match FRAME_STEPS
+            .iter()
+            .position(|&step| step == self.frame_cycle)
+        {
+            Some(3) if self.five_step => {} // Nothing happens on this step in 5-step mode
+            Some(step) if step == 1 || step == last_step => {
+                self.quarter_frame();
+                self.half_frame();
+                if !self.five_step && !self.irq_inhibit && step == last_step {
+                    self.frame_irq = true;
+                }
+            }
+            Some(_) => self.quarter_frame(),
+            None => {}
+        }
+        if self.frame_cycle > FRAME_STEPS[last_step] {
+            self.frame_cycle = 0;
+        }
+    }
+
+    // Envelope clock
+    fn quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+    }
+
+    // Length counter and sweep clock
+    fn half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse2.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
+    // Current output sample in 0.0-1.0
+    // The triangle and noise channels are not emulated yet
+    pub fn mix(&self) -> f32 {
+        nonlinear_mix(
+            self.pulse1.output(),
+            self.pulse2.output(),
+            0,
+            0,
+            self.dmc.output_level,
+        )
+    }
+
+    // Whether the frame sequencer or any channel is requesting an interrupt
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq()
+    }
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SaveState for LengthCounter {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        writer.bool(self.halt);
+        writer.u8(self.counter);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        self.enabled = reader.bool()?;
+        self.halt = reader.bool()?;
+        self.counter = reader.u8()?;
+        Ok(())
+    }
+}
+
+impl SaveState for Envelope {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.start);
+        writer.bool(self.looping);
+        writer.bool(self.constant);
+        writer.u8(self.volume);
+        writer.u8(self.divider);
+        writer.u8(self.decay);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        self.start = reader.bool()?;
+        self.looping = reader.bool()?;
+        self.constant = reader.bool()?;
+        self.volume = savestate::in_range("envelope volume", reader.u8()?, 0..=15)?;
+        self.divider = savestate::in_range("envelope divider", reader.u8()?, 0..=15)?;
+        self.decay = savestate::in_range("envelope decay", reader.u8()?, 0..=15)?;
+        Ok(())
+    }
+}
+
+impl SaveState for Sweep {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        writer.u8(self.period);
+        writer.bool(self.negate);
+        writer.u8(self.shift);
+        writer.u8(self.divider);
+        writer.bool(self.reload);
+    }
+
+    // ones_complement is fixed by the channel and not part of the state
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        self.enabled = reader.bool()?;
+        self.period = savestate::in_range("sweep period", reader.u8()?, 0..=7)?;
+        self.negate = reader.bool()?;
+        self.shift = savestate::in_range("sweep shift", reader.u8()?, 0..=7)?;
+        self.divider = savestate::in_range("sweep divider", reader.u8()?, 0..=7)?;
+        self.reload = reader.bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for Pulse {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.u8(self.duty);
+        writer.u8(self.duty_step);
+        writer.u16(self.timer_period);
+        writer.u16(self.timer);
+        self.envelope.write_state(writer);
+        self.sweep.write_state(writer);
+        self.length.write_state(writer);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        self.duty = reader.u8()? & 0b11;
+        self.duty_step = reader.u8()? & 0b111;
+        self.timer_period = savestate::in_range("pulse period", reader.u16()?, 0..=0x7ff)?;
+        self.timer = reader.u16()?;
+        self.envelope.read_state(reader)?;
+        self.sweep.read_state(reader)?;
+        self.length.read_state(reader)
+    }
+}
+
+impl SaveState for Dmc {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.irq_enabled);
+        writer.bool(self.looping);
+        writer.u16(self.period);
+        writer.u16(self.timer);
+        writer.u8(self.output_level);
+        writer.u16(self.sample_address);
+        writer.u16(self.sample_length);
+        writer.u16(self.current_address);
+        writer.u16(self.bytes_remaining);
+        writer.bool(self.sample_buffer.is_some());
+        writer.u8(self.sample_buffer.unwrap_or(0));
+        writer.u8(self.shift_register);
+        writer.u8(self.bits_remaining);
+        writer.bool(self.silence);
+        writer.bool(self.irq_flag);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        self.irq_enabled = reader.bool()?;
+        self.looping = reader.bool()?;
+        // The timer counts down to zero and reloads, so neither may start at zero
+        self.period = savestate::in_range("DMC period", reader.u16()?, 1..=u16::MAX)?;
+        self.timer = savestate::in_range("DMC timer", reader.u16()?, 1..=u16::MAX)?;
+        self.output_level = savestate::in_range("DMC level", reader.u8()?, 0..=127)?;
+        self.sample_address = reader.u16()?;
+        self.sample_length = reader.u16()?;
+        self.current_address = reader.u16()?;
+        self.bytes_remaining = reader.u16()?;
+        let buffered = reader.bool()?;
+        let sample = reader.u8()?;
+        self.sample_buffer = if buffered { Some(sample) } else { None };
+        self.shift_register = reader.u8()?;
+        self.bits_remaining = savestate::in_range("DMC bits", reader.u8()?, 1..=8)?;
+        self.silence = reader.bool()?;
+        self.irq_flag = reader.bool()?;
+        Ok(())
+    }
+}
+
+// The sample clock is saved so a restored console cuts samples at the same cycles as the
+// original run; mixed samples waiting for the frontend are output and are not saved
+impl SaveState for APU {
+    fn write_state(&self, writer: &mut StateWriter) {
+        self.pulse1.write_state(writer);
+        self.pulse2.write_state(writer);
+        self.dmc.write_state(writer);
+        writer.u32(self.frame_cycle);
+        writer.bool(self.five_step);
+        writer.bool(self.irq_inhibit);
+        writer.bool(self.frame_irq);
+        writer.bool(self.odd_cycle);
+        writer.u32(self.sample_clock);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        self.pulse1.read_state(reader)?;
+        self.pulse2.read_state(reader)?;
+        self.dmc.read_state(reader)?;
+        self.frame_cycle = reader.u32()?;
+        self.five_step = reader.bool()?;
+        self.irq_inhibit = reader.bool()?;
+        self.frame_irq = reader.bool()?;
+        self.odd_cycle = reader.bool()?;
+        let sample_clock = reader.u32()?;
+        self.sample_clock =
+            savestate::in_range("sample clock", sample_clock, 0..=CPU_CLOCK_RATE - 1)?;
+        Ok(())
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Test case for the DMC playing back a sample byte one bit per timer period
+    #[test]
+    fn test_dmc_output_follows_sample_bits() {
+        let mut apu = APU::new();
+        apu.write_register(0x4010, 0x0f); // Fastest rate, 54 cycles per bit
+        apu.write_register(0x4011, 0x40);
+        apu.write_register(0x4015, 0b0001_0000);
+        assert_eq!(apu.dmc.fetch_address(), Some(0xc000));
+        apu.dmc.fill_sample_buffer(0b0000_0001);
+
+        // The first output cycle is silent and ends by loading the shift register;
+        // its first bit still uses the power-on period
+        apu.tick(428 + 54 * 7);
+        assert_eq!(apu.dmc.output_level, 0x40);
+        apu.tick(54);
+        assert_eq!(apu.dmc.output_level, 0x42); // Bit 0 set: up two steps
+        apu.tick(54);
+        assert_eq!(apu.dmc.output_level, 0x40); // Bit 1 clear: down two steps
+    }
+
+    // Test case for the mixer against the reference values of the published formulas
+    #[test]
+    fn test_nonlinear_mix_reference_values() {
+        assert_eq!(nonlinear_mix(0, 0, 0, 0, 0), 0.0);
+        assert!((nonlinear_mix(15, 0, 0, 0, 0) - 0.149377).abs() < 1e-4);
+        assert!((nonlinear_mix(15, 15, 0, 0, 0) - 0.258483).abs() < 1e-4);
+        assert!((nonlinear_mix(0, 0, 0, 0, 64) - 0.352179).abs() < 1e-4);
+        assert!((nonlinear_mix(15, 15, 15, 15, 127) - 1.0).abs() < 1e-4); // Full scale
+    }
+
+    // Test case for the APU mixing in the DMC output level
+    #[test]
+    fn test_mix_uses_dmc_level() {
+        let mut apu = APU::new();
+        assert_eq!(apu.mix(), 0.0);
+        apu.write_register(0x4011, 64);
+        assert!((apu.mix() - 0.352179).abs() < 1e-4);
+    }
+
+    // Test case for disabling a channel through 0x4015 silencing it at once
+    #[test]
+    fn test_disabled_channel_is_silent() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1111_1111); // Duty 3, halted, constant volume 15
+        apu.write_register(0x4002, 0x00);
+        apu.write_register(0x4003, 0b0000_1001); // Length index 1 (254), timer high 1
+        assert_eq!(apu.pulse1.length.counter, 254);
+        assert_eq!(apu.pulse1.output(), 15);
+
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.pulse1.length.counter, 0);
+        assert_eq!(apu.pulse1.output(), 0);
+        apu.write_register(0x4003, 0b0000_1001); // Loads are ignored while disabled
+        assert_eq!(apu.pulse1.length.counter, 0);
+    }
+
+    // Test case for half frames counting lengths down unless halted
+    #[test]
+    fn test_length_counter_halt() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0000_0011);
+        apu.write_register(0x4000, 0b1101_1111); // Counting down
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x4004, 0b1111_1111); // Halted
+        apu.write_register(0x4007, 0b0000_1000);
+        apu.tick(29830); // One 4-step sequence: two half frames
+        assert_eq!(apu.pulse1.length.counter, 252);
+        assert_eq!(apu.pulse2.length.counter, 254);
+    }
+
+    // Start a note on both pulse channels with the same period and sweep
+    fn sweep_both(period: u16, sweep: u8) -> APU {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0000_0011);
+        for base in [0x4000, 0x4004] {
+            apu.write_register(base, 0b1111_1111);
+            apu.write_register(base + 1, sweep);
+            apu.write_register(base + 2, period as u8);
+            apu.write_register(base + 3, 0b0000_1000 | (period >> 8) as u8);
+        }
+        apu
+    }
+
+    // Test case for a downward sweep raising the pitch, with pulse 1 going one step further
+    #[test]
+    fn test_sweep_raises_pitch() {
+        let mut apu = sweep_both(0x100, 0b1000_1001); // Enabled, negate, shift 1
+        apu.tick(14913); // First half frame
+        assert_eq!(apu.pulse1.timer_period(), 0x7f);
+        assert_eq!(apu.pulse2.timer_period(), 0x80);
+        assert_eq!(apu.pulse1.output(), 15);
+    }
+
+    // Test case for a sweep driving the period below 8 silencing the channel
+    #[test]
+    fn test_sweep_below_eight_silences() {
+        let mut apu = sweep_both(0x10, 0b1000_1001);
+        assert_eq!(apu.pulse1.output(), 15);
+        apu.tick(14913);
+        assert_eq!(apu.pulse1.timer_period(), 7);
+        assert_eq!(apu.pulse1.output(), 0);
+        assert_eq!(apu.pulse2.timer_period(), 8);
+        assert_eq!(apu.pulse2.output(), 15);
+    }
+
+    // Test case for a target period above 0x7FF silencing the channel, even with the sweep off
+    #[test]
+    fn test_sweep_target_overflow_silences() {
+        let apu = sweep_both(0x600, 0b0000_0001); // Disabled, add, shift 1
+        assert_eq!(apu.pulse1.output(), 0);
+        assert_eq!(apu.pulse2.output(), 0);
+    }
+
+    // Test case for one NTSC frame of cycles producing a frame's worth of samples
+    #[test]
+    fn test_samples_collected_at_sample_rate() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111); // 50% duty, constant volume 15
+        apu.write_register(0x4002, 0xfd);
+        apu.write_register(0x4003, 0x08);
+        let cycles = CPU_CLOCK_RATE / 60; // One frame
+        apu.tick(cycles as u16);
+        let expected = cycles as u64 * SAMPLE_RATE as u64 / CPU_CLOCK_RATE as u64;
+        assert_eq!(apu.samples().len(), expected as usize);
+        assert!(apu.samples().iter().any(|&sample| sample > 0.0));
+        apu.clear_samples();
+        assert!(apu.samples().is_empty());
+    }
+
+    // Test case for an installed sink receiving the samples in whole batches
+    #[test]
+    fn test_samples_pushed_to_sink() {
+        let sink = crate::audio::VecSink::new();
+        let mut apu = APU::new();
+        apu.set_audio_sink(Box::new(sink.clone()));
+        let cycles = CPU_CLOCK_RATE / 60 * 2; // Two frames, as much as fits in a u16
+        for _ in 0..4 {
+            apu.tick(cycles as u16);
+        }
+        let produced = 4 * cycles as u64 * SAMPLE_RATE as u64 / CPU_CLOCK_RATE as u64;
+        let batches = produced as usize / SINK_BATCH;
+        assert_eq!(sink.samples().len(), batches * SINK_BATCH);
+        assert_eq!(apu.samples().len(), produced as usize % SINK_BATCH);
+    }
+
+    // Test case for a sink installed after more than a batch has piled up receiving the
+    // backlog along with later samples
+    #[test]
+    fn test_sink_installed_late() {
+        let sink = crate::audio::VecSink::new();
+        let mut apu = APU::new();
+        let cycles = CPU_CLOCK_RATE / 60 * 2;
+        apu.tick(cycles as u16);
+        apu.tick(cycles as u16);
+        let pending = apu.samples().len();
+        assert!(pending > SINK_BATCH);
+
+        apu.set_audio_sink(Box::new(sink.clone()));
+        apu.tick(100); // Enough for at least one more sample
+        assert!(sink.samples().len() > pending);
+        assert!(apu.samples().len() < SINK_BATCH);
+    }
+
+    // Test case for apu_status reporting two configured channels without clearing the IRQ
+    #[test]
+    fn test_apu_status() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0000_0011);
+        apu.write_register(0x4000, 0b1111_1010); // Duty 3, halted, constant volume 10
+        apu.write_register(0x4002, 0x00);
+        apu.write_register(0x4003, 0b0000_1001); // Length index 1 (254), timer high 1
+        apu.write_register(0x4007, 0b0001_1000); // Pulse 2: length index 3 (2)
+        apu.write_register(0x4011, 0x40);
+        apu.frame_irq = true;
+
+        let status = apu.apu_status();
+        assert_eq!(
+            status.pulse1,
+            PulseStatus {
+                enabled: true,
+                length: 254,
+                output: 10,
+            }
+        );
+        assert_eq!(
+            status.pulse2,
+            PulseStatus {
+                enabled: true,
+                length: 2,
+                output: 0, // Volume 0
+            }
+        );
+        assert_eq!(status.dmc_output, 0x40);
+        assert_eq!(status.dmc_bytes_remaining, 0);
+        assert!(status.frame_irq);
+        assert!(apu.apu_status().frame_irq); // Still raised after being reported
+
+        apu.write_register(0x4015, 0b0000_0001);
+        let status = apu.apu_status();
+        assert!(!status.pulse2.enabled);
+        assert_eq!(status.pulse2.length, 0);
+    }
+
+    // Test case for the raw sample stream of a fixed configuration matching its known hash
+    #[test]
+    fn test_raw_samples_are_deterministic() {
+        let mut apu = APU::new();
+        apu.record_raw_samples(true);
+        apu.write_register(0x4015, 0b0000_0011);
+        apu.write_register(0x4000, 0b1011_1111); // Duty 2, halted, constant volume 15
+        apu.write_register(0x4002, 0x40);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x4004, 0b0101_1000); // Pulse 2: duty 1, decaying envelope
+        apu.write_register(0x4006, 0x90);
+        apu.write_register(0x4007, 0b0000_1000);
+        apu.write_register(0x4011, 0x20);
+        apu.tick(30_000);
+
+        let raw = apu.take_raw_samples();
+        assert_eq!(raw.len(), 30_000);
+        assert!(apu.take_raw_samples().is_empty());
+        let bytes: Vec<u8> = raw.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        assert_eq!(crate::savestate::rom_hash(&bytes), 0xbbe0c088006e85c5);
+        assert!(raw.contains(&0x200f) && raw.contains(&0x2080)); // Both pulses toggle
+    }
+}
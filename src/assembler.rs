@@ -0,0 +1,107 @@
+use crate::hardware::AddressingMode;
+use crate::opcodes;
+
+// Parse a hexadecimal operand such as "$1F" into its value and width in bytes
+fn parse_hex(text: &str) -> (u16, u8) {
+    let digits = text
+        .strip_prefix('$')
+        .unwrap_or_else(|| panic!("operand {:?} must be hexadecimal", text));
+    let value = u16::from_str_radix(digits, 16)
+        .unwrap_or_else(|_| panic!("operand {:?} is not a valid number", text));
+    let width = if digits.len() <= 2 { 1 } else { 2 };
+    (value, width)
+}
+
+// Work out the addressing mode and operand value from the operand text
+fn parse_operand(operand: &str) -> (AddressingMode, u16) {
+    if operand.is_empty() {
+        return (AddressingMode::NoneAddressing, 0);
+    }
+    if let Some(value) = operand.strip_prefix('#') {
+        return (AddressingMode::Immediate, parse_hex(value).0);
+    }
+    if let Some(pointer) = operand
+        .strip_prefix('(')
+        .and_then(|o| o.strip_suffix(",X)"))
+    {
+        return (AddressingMode::IndirectX, parse_hex(pointer).0);
+    }
+    if let Some(pointer) = operand
+        .strip_prefix('(')
+        .and_then(|o| o.strip_suffix("),Y"))
+    {
+        return (AddressingMode::IndirectY, parse_hex(pointer).0);
+    }
+
+    let (address, index) = match operand.split_once(',') {
+        Some((address, index)) => (address, Some(index)),
+        None => (operand, None),
+    };
+    let (value, width) = parse_hex(address);
+    let mode = match (width, index) {
+        (1, None) => AddressingMode::ZeroPage,
+        (1, Some("X")) => AddressingMode::ZeroPageX,
+        (1, Some("Y")) => AddressingMode::ZeroPageY,
+        (2, None) => AddressingMode::Absolute,
+        (2, Some("X")) => AddressingMode::AbsoluteX,
+        (2, Some("Y")) => AddressingMode::AbsoluteY,
+        _ => panic!("unsupported operand {:?}", operand),
+    };
+    (mode, value)
+}
+
+// Branches take their signed offset as a one-byte operand, e.g. "BNE $FC"
+fn relative_branch(mnemonic: &str, mode: &AddressingMode) -> Option<&'static opcodes::OpCode> {
+    if *mode != AddressingMode::ZeroPage {
+        return None;
+    }
+    opcodes::find_by_mnemonic(mnemonic, &AddressingMode::NoneAddressing).filter(|op| op.len == 2)
+}
+
+// Assemble one instruction per line into machine code using the opcode table
+pub fn assemble(lines: &[&str]) -> Vec<u8> {
+    let mut program = vec![];
+    for line in lines {
+        let line = line.trim();
+        let (mnemonic, operand) = match line.split_once(' ') {
+            Some((mnemonic, operand)) => (mnemonic, operand.trim()),
+            None => (line, ""),
+        };
+        let mnemonic = mnemonic.to_uppercase();
+        let (mode, value) = parse_operand(&operand.to_uppercase());
+        let op = opcodes::find_by_mnemonic(&mnemonic, &mode)
+            .or_else(|| relative_branch(&mnemonic, &mode))
+            .unwrap_or_else(|| panic!("no encoding for {:?}", line));
+
+        program.push(op.code);
+        match op.len {
+            2 => program.push(value as u8),
+            3 => program.extend_from_slice(&value.to_le_bytes()),
+            _ => {}
+        }
+    }
+    program
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Test case for assembling a short program into the hand-coded bytes
+    #[test]
+    fn test_assemble_three_line_program() {
+        let program = assemble(&["LDA #$05", "STA $10", "BRK"]);
+        assert_eq!(program, vec![0xa9, 0x05, 0x85, 0x10, 0x00]);
+    }
+
+    // Test case for the indexed and indirect operand forms
+    #[test]
+    fn test_assemble_indexed_and_indirect_operands() {
+        let program = assemble(&["LDA $1234,X", "LDA ($20),Y", "STA ($40,X)", "LDA $10,X"]);
+        assert_eq!(
+            program,
+            vec![0xbd, 0x34, 0x12, 0xb1, 0x20, 0x81, 0x40, 0xb5, 0x10]
+        );
+    }
+}
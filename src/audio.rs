@@ -0,0 +1,42 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+// Destination for mixed APU output, so a frontend can feed a file, a ring buffer or a
+// sound card without the core depending on any of them
+pub trait AudioSink {
+    fn push_samples(&mut self, samples: &[f32]);
+}
+
+// Sink that throws the sound away, for headless runs
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn push_samples(&mut self, _samples: &[f32]) {}
+}
+
+// Sink that keeps every sample in memory
+// Clones share one buffer, so a test can hand a clone to the APU and read the original
+#[derive(Debug, Default, Clone)]
+pub struct VecSink {
+    samples: Rc<RefCell<Vec<f32>>>,
+}
+
+impl VecSink {
+    // Constructor to create a new, empty sink
+    pub fn new() -> Self {
+        VecSink::default()
+    }
+
+    // Copy of everything pushed so far
+    pub fn samples(&self) -> Vec<f32> {
+        self.samples.borrow().clone()
+    }
+}
+
+impl AudioSink for VecSink {
+    fn push_samples(&mut self, samples: &[f32]) {
+        self.samples.borrow_mut().extend_from_slice(samples);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::hardware::{CPU, STACK_RESET};
+use crate::mapper::MapperFactory;
+use crate::ppu::Region;
+use crate::random::{RandomSource, XorShift};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+// Define the EmulatorBuilder struct collecting configuration before assembly
+pub struct EmulatorBuilder {
+    region: Region,                            // Television standard
+    decimal_enabled: bool,                     // Honour the decimal flag in arithmetic
+    ram_random: Option<Box<dyn RandomSource>>, // Fills work RAM at power-on, or zeroed RAM if None
+    strict_stack: bool,                        // Report stack overflow/underflow
+    stack_pointer: u8,                         // Stack pointer loaded by reset()
+    program_base: u16,                         // Address that load() places programs at
+    frame_skip: u8,                            // Frames left undrawn after each drawn frame
+    max_instructions: Option<u64>,             // Instruction budget of each run call
+    ram_mirroring: bool,                       // Mirror work RAM every 2KB like the NES
+    mappers: Vec<(u16, MapperFactory)>,        // Custom mappers, registered in order
+}
+
+impl EmulatorBuilder {
+    // Constructor with the same settings as CPU::new()
+    pub fn new() -> Self {
+        EmulatorBuilder {
+            region: Region::Ntsc,
+            decimal_enabled: false,
+            ram_random: None,
+            strict_stack: false,
+            stack_pointer: STACK_RESET,
+            program_base: 0x8000,
+            frame_skip: 0,
+            max_instructions: None,
+            ram_mirroring: true,
+            mappers: Vec::new(),
+        }
+    }
+
+    // Select the television standard
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = region;
+        self
+    }
+
+    // Enable decimal arithmetic for a general 6502
+    pub fn decimal_mode(mut self, enabled: bool) -> Self {
+        self.decimal_enabled = enabled;
+        self
+    }
+
+    // Fill work RAM from a seeded generator at power-on
+    pub fn randomize_ram(self, seed: u64) -> Self {
+        self.ram_random(XorShift::new(seed))
+    }
+
+    // Fill work RAM from a custom random source at power-on
+    pub fn ram_random<R: RandomSource + 'static>(mut self, source: R) -> Self {
+        self.ram_random = Some(Box::new(source));
+        self
+    }
+
+    // Report stack overflow/underflow instead of wrapping
+    pub fn strict_stack(mut self, strict: bool) -> Self {
+        self.strict_stack = strict;
+        self
+    }
+
+    // Set the stack pointer reset() starts from, for test suites that expect other
+    // than the 2A03's 0xFD
+    pub fn initial_stack_pointer(mut self, sp: u8) -> Self {
+        self.stack_pointer = sp;
+        self
+    }
+
+    // Set the address that load() places programs at
+    pub fn program_base(mut self, base: u16) -> Self {
+        self.program_base = base;
+        self
+    }
+
+    // Draw only one frame in every `skip + 1`, to save time on slow hosts
+    pub fn frame_skip(mut self, skip: u8) -> Self {
+        self.frame_skip = skip;
+        self
+    }
+
+    // Stop every run call with InstructionLimit after `limit` instructions
+    pub fn max_instructions(mut self, limit: u64) -> Self {
+        self.max_instructions = Some(limit);
+        self
+    }
+
+    // Turn off work RAM mirroring to make 0x0000-0x1FFF a flat 8KB, for homebrew
+    // and test programs that assume linear low memory
+    pub fn ram_mirroring(mut self, enabled: bool) -> Self {
+        self.ram_mirroring = enabled;
+        self
+    }
+
+    // Use a custom mapper implementation for cartridges with an iNES mapper number
+    // This CPU alone sees it, see Bus::register_mapper
+    pub fn mapper(mut self, number: u16, factory: MapperFactory) -> Self {
+        self.mappers.push((number, factory));
+        self
+    }
+
+    // Assemble the CPU and its bus with the chosen settings
+    pub fn build(self) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.bus.ppu.region = self.region;
+        cpu.decimal_enabled = self.decimal_enabled;
+        cpu.strict_stack = self.strict_stack;
+        cpu.reset_stack_pointer = self.stack_pointer;
+        cpu.stack_pointer = self.stack_pointer;
+        cpu.program_base = self.program_base;
+        cpu.bus.frame_skip = self.frame_skip;
+        cpu.max_instructions = self.max_instructions;
+        cpu.bus.ram_mirroring = self.ram_mirroring;
+        for (number, factory) in self.mappers {
+            cpu.bus.register_mapper(number, factory);
+        }
+        cpu.set_ram_random(self.ram_random);
+        cpu
+    }
+}
+
+impl Default for EmulatorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Test case for a custom region and RAM seed taking effect
+    #[test]
+    fn test_build_with_region_and_seed() {
+        let mut cpu = EmulatorBuilder::new()
+            .region(Region::Pal)
+            .randomize_ram(42)
+            .strict_stack(true)
+            .build();
+        let mut same_seed = EmulatorBuilder::new().randomize_ram(42).build();
+        assert_eq!(cpu.bus.ppu.region, Region::Pal);
+        assert!(cpu.strict_stack);
+
+        let ram: Vec<u8> = (0..0x800).map(|a| cpu.bus.mem_read(a)).collect();
+        let same: Vec<u8> = (0..0x800).map(|a| same_seed.bus.mem_read(a)).collect();
+        assert!(ram.iter().any(|&byte| byte != 0));
+        assert_eq!(ram, same); // The seed makes power-on RAM reproducible
+    }
+
+    // Test case for an injected random source deciding the power-on RAM contents
+    #[test]
+    fn test_build_with_fixed_random_ram() {
+        let mut cpu = EmulatorBuilder::new()
+            .ram_random(crate::random::FixedRandom(0x5a))
+            .build();
+        assert!((0..0x800).all(|a| cpu.bus.mem_read(a) == 0x5a));
+    }
+
+    // Test case for a loaded cartridge keeping the RAM source and the bus setup made
+    // before it was loaded
+    #[test]
+    fn test_load_rom_keeps_setup() {
+        let mut cpu = EmulatorBuilder::new()
+            .ram_random(crate::random::FixedRandom(0x5a))
+            .ram_mirroring(false)
+            .build();
+        let sink = crate::audio::VecSink::new();
+        cpu.bus.apu.set_audio_sink(Box::new(sink.clone()));
+        cpu.bus.add_write_watchpoint(0x0010);
+        cpu.bus.start_mem_trace();
+        cpu.load_rom(&crate::cartridge::test::test_rom_bytes(0x00, 0x00, 1, 1))
+            .unwrap();
+
+        assert!((0..0x2000).all(|a| cpu.peek(a) == 0x5a));
+        assert!(!cpu.bus.ram_mirroring);
+        cpu.bus.mem_write(0x0010, 0x42);
+        assert_eq!(cpu.bus.watch_hits, vec![(0x0010, 0x42)]);
+        assert!(!cpu.bus.take_mem_trace().is_empty());
+        for _ in 0..3 {
+            cpu.bus.tick(29_781);
+        }
+        assert!(!sink.samples().is_empty());
+    }
+
+    // Test case for reset() loading a configured stack pointer
+    #[test]
+    fn test_build_with_initial_stack_pointer() {
+        let mut cpu = EmulatorBuilder::new().initial_stack_pointer(0xff).build();
+        cpu.stack_pointer = 0x10;
+        cpu.reset();
+        assert_eq!(cpu.stack_pointer, 0xff);
+        assert_eq!(EmulatorBuilder::new().build().stack_pointer, STACK_RESET);
+    }
+
+    // Test case for the program base moving where load() places code
+    #[test]
+    fn test_build_with_program_base() {
+        let mut cpu = EmulatorBuilder::new().program_base(0x0600).build();
+        cpu.load(vec![0xa9, 0x07, 0x00]);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x0600);
+        cpu.interpret().unwrap();
+        assert_eq!(cpu.accumulator, 0x07);
+    }
+
+    // Test case for flat work RAM keeping 0x0000 and 0x0800 apart
+    #[test]
+    fn test_build_without_ram_mirroring() {
+        let mut flat = EmulatorBuilder::new().ram_mirroring(false).build();
+        flat.bus.mem_write(0x0000, 0x42);
+        flat.bus.mem_write(0x1fff, 0x24);
+        assert_eq!(flat.bus.mem_read(0x0800), 0x00);
+        assert_eq!(flat.bus.mem_read(0x07ff), 0x00);
+        assert_eq!(flat.bus.mem_read(0x1fff), 0x24);
+
+        let mut mirrored = EmulatorBuilder::new().build();
+        mirrored.bus.mem_write(0x0000, 0x42);
+        assert_eq!(mirrored.bus.mem_read(0x0800), 0x42);
+    }
+}
@@ -0,0 +1,841 @@
+use crate::apu::{APU, DMC_FETCH_STALL};
+use crate::cartridge::Rom;
+use crate::error::EmuError;
+use crate::frame::Frame;
+use crate::joypad::Joypad;
+use crate::mapper::{self, CustomMappers, Mapper, MapperFactory, Nrom};
+use crate::ppu::PPU;
+use crate::random::{RandomSource, XorShift};
+use crate::render;
+use crate::savestate::{SaveState, StateReader, StateWriter};
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+//  CPU memory map
+//  _______________ $10000  _______________
+// | PRG-ROM       |       |               |
+// | Upper Bank    |       |               |
+// |_ _ _ _ _ _ _ _| $C000 | PRG-ROM       |
+// | PRG-ROM       |       |               |
+// | Lower Bank    |       |               |
+// |_______________| $8000 |_______________|
+// | PRG-RAM       |       | PRG-RAM       |
+// |_______________| $6000 |_______________|
+// | Expansion ROM |       | Expansion ROM |
+// |_______________| $4020 |_______________|
+// | APU and I/O   |       |               |
+// |_ _ _ _ _ _ _ _| $4000 |               |
+// | Mirrors       |       | I/O Registers |
+// | $2000-$2007   |       |               |
+// |_ _ _ _ _ _ _ _| $2008 |               |
+// | PPU Registers |       |               |
+// |_______________| $2000 |_______________|
+// | Mirrors       |       |               |
+// | $0000-$07FF   |       |               |
+// |_ _ _ _ _ _ _ _| $0800 | RAM           |
+// | RAM           |       |               |
+// |_______________| $0000 |_______________|
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+
+// CPU cycles after power-on during which the PPU ignores writes to PPUCTRL,
+// PPUMASK, PPUSCROLL and PPUADDR
+pub const PPU_WARMUP_CYCLES: u64 = 29658;
+
+// CPU cycles an OAM DMA halts the CPU for when it starts on an even cycle:
+// 256 reads and 256 writes plus a halt cycle
+pub const OAM_DMA_CYCLES: u16 = 513;
+
+// Accesses recorded by the memory trace are capped at this many
+const MEM_TRACE_LIMIT: usize = 1 << 16;
+
+// Direction of a recorded bus access
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+// (address, value, kind) of one recorded bus access
+pub type MemAccess = (u16, u8, AccessKind);
+
+// Define the Bus struct connecting the CPU to the rest of the console
+pub struct Bus {
+    cpu_vram: [u8; 0x2000],           // Work RAM: 2KB on a real NES, 8KB when flat
+    pub ram_mirroring: bool,          // Repeat the 2KB of work RAM up to 0x1FFF
+    prg_ram: [u8; 0x2000],            // Battery-backed/work RAM on the cartridge
+    mapper: Box<dyn Mapper>,          // Cartridge board holding PRG ROM
+    custom_mappers: CustomMappers,    // Mappers registered for this console, by number
+    pub ppu: PPU,                     // Picture processing unit
+    pub apu: APU,                     // Audio processing unit
+    pub joypad1: Joypad,              // Controller in port 1
+    out_latch: u8,                    // OUT0-OUT2 from the last write to 0x4016
+    last_bus_value: u8,               // Last value on the data bus, returned for open-bus reads
+    pub frame: Frame,                 // Most recently rendered picture
+    pub frame_count: u64,             // Number of frames the PPU has completed
+    pub frame_skip: u8,               // Skip drawing this many frames after each drawn one
+    pub frames_rendered: u64,         // Number of frames actually drawn into `frame`
+    pub ppu_warmup: bool,             // Drop early PPU register writes like the hardware
+    cycles_since_power_on: u64,       // CPU cycles ticked, stalls included
+    master_cycles: u64,               // Master clock cycles ticked, which the CPU and PPU divide
+    write_watchpoints: BTreeSet<u16>, // Addresses whose writes are recorded
+    pub watch_hits: Vec<(u16, u8)>,   // (address, value) of each watched write
+    mem_trace: Option<Vec<MemAccess>>, // Every access while tracing
+    irq_requested: bool,              // IRQ forced by request_irq, dropped once taken
+    oam_dma_pending: bool,            // A write to 0x4014 whose stall tick() has not charged
+}
+
+impl Bus {
+    // Constructor to create a new Bus around a cartridge, failing if its mapper is unknown
+    // Mappers registered on the thread with mapper::register_mapper are included
+    pub fn new(rom: Rom) -> Result<Self, EmuError> {
+        Bus::with_custom_mappers(rom, mapper::thread_mappers())
+    }
+
+    // Constructor like new() that can also build the cartridge with custom mappers
+    pub fn with_custom_mappers(rom: Rom, custom_mappers: CustomMappers) -> Result<Self, EmuError> {
+        let ppu = PPU::new(rom.chr_rom.clone(), rom.screen_mirroring);
+        let mapper = mapper::create_mapper_with(&custom_mappers, rom.mapper as u16, rom)?;
+        let mut bus = Bus::with_mapper(mapper, ppu);
+        bus.custom_mappers = custom_mappers;
+        Ok(bus)
+    }
+
+    // Plug in a mapper implementation for an iNES mapper number, for boards this crate
+    // does not implement; it replaces a built-in mapper with the same number
+    // Applies to cartridges loaded afterwards, not to the one already mounted
+    pub fn register_mapper(&mut self, number: u16, factory: MapperFactory) {
+        self.custom_mappers.insert(number, Rc::from(factory));
+    }
+
+    // Mappers registered with register_mapper
+    pub fn custom_mappers(&self) -> &CustomMappers {
+        &self.custom_mappers
+    }
+
+    // Take over the setup of a bus this one replaces on a cartridge change: region,
+    // frame skip, RAM mirroring, watchpoints, the memory trace and the audio outputs
+    // Memory and device state stay at power-on, as a console starts with a new cartridge
+    pub fn take_setup_from(&mut self, previous: &mut Bus) {
+        self.ppu.region = previous.ppu.region;
+        self.frame_skip = previous.frame_skip;
+        self.ram_mirroring = previous.ram_mirroring;
+        self.write_watchpoints = core::mem::take(&mut previous.write_watchpoints);
+        self.watch_hits = core::mem::take(&mut previous.watch_hits);
+        self.mem_trace = previous.mem_trace.take();
+        self.apu.take_outputs_from(&mut previous.apu);
+    }
+
+    fn with_mapper(mapper: Box<dyn Mapper>, ppu: PPU) -> Self {
+        let mut bus = Bus {
+            cpu_vram: [0; 0x2000],
+            ram_mirroring: true,
+            prg_ram: [0; 0x2000],
+            mapper,
+            custom_mappers: mapper::thread_mappers(),
+            ppu,
+            apu: APU::new(),
+            joypad1: Joypad::new(),
+            out_latch: 0,
+            last_bus_value: 0,
+            frame: Frame::new(),
+            frame_count: 0,
+            frame_skip: 0,
+            frames_rendered: 0,
+            ppu_warmup: true,
+            cycles_since_power_on: 0,
+            master_cycles: 0,
+            write_watchpoints: BTreeSet::new(),
+            watch_hits: vec![],
+            mem_trace: None,
+            irq_requested: false,
+            oam_dma_pending: false,
+        };
+        bus.sync_mapper();
+        bus
+    }
+
+    // Pass the mapper's CHR banks and mirroring on to the PPU
+    fn sync_mapper(&mut self) {
+        self.ppu.chr_banks = self.mapper.chr_banks();
+        if let Some(mirroring) = self.mapper.mirroring() {
+            self.ppu.mirroring = mirroring;
+        }
+    }
+
+    // Advance the rest of the console by a number of CPU cycles, returning the
+    // extra cycles the CPU spent stalled by OAM DMA or while the DMC fetched a
+    // sample byte
+    pub fn tick(&mut self, cycles: u16) -> u16 {
+        self.cycles_since_power_on += cycles as u64;
+        self.apu.tick(cycles);
+        let mut stall = 0;
+        // OAM DMA takes one more cycle when it has to wait for an even cycle to begin
+        if core::mem::take(&mut self.oam_dma_pending) {
+            stall = OAM_DMA_CYCLES + (self.cycles_since_power_on % 2) as u16;
+            self.apu.tick(stall);
+        }
+        if let Some(address) = self.apu.dmc.fetch_address() {
+            let data = self.mem_read(address);
+            self.apu.dmc.fill_sample_buffer(data);
+            stall += DMC_FETCH_STALL;
+        }
+        self.cycles_since_power_on += stall as u64;
+
+        // Both chips divide the master clock: the PPU gets every dot that starts while
+        // the CPU runs, three per CPU cycle on NTSC and 3.2 on average on PAL
+        let region = self.ppu.region;
+        let start = self.master_cycles;
+        self.master_cycles += (cycles + stall) as u64 * region.cpu_clock_divider();
+        let dots =
+            self.master_cycles / region.ppu_clock_divider() - start / region.ppu_clock_divider();
+
+        // The PPU draws each line as it starts so mid-frame register writes show up
+        // from the next line on
+        // Skipped frames keep their timing and NMI but are not drawn
+        let draw = self.frame_count.is_multiple_of(self.frame_skip as u64 + 1);
+        let frame = &mut self.frame;
+        let frame_complete = self.ppu.tick_drawing(dots as u16, |ppu, y| {
+            if draw {
+                render::render_scanline(ppu, frame, y);
+            }
+        });
+        if frame_complete {
+            if draw {
+                self.frames_rendered += 1;
+            }
+            self.frame_count += 1;
+        }
+        stall
+    }
+
+    // Copy page `page` of CPU memory into OAM, starting at OAMADDR
+    // The CPU is halted for the copy, which tick() charges once the write's
+    // instruction has finished
+    fn oam_dma(&mut self, page: u8) {
+        let base = (page as u16) << 8;
+        for offset in 0..256 {
+            let data = self.mem_read(base + offset);
+            self.ppu.write_to_oam_data(data);
+        }
+        self.oam_dma_pending = true;
+    }
+
+    // Master clock cycles since power-on
+    pub fn master_cycles(&self) -> u64 {
+        self.master_cycles
+    }
+
+    // Whether the PPU has warmed up and accepts writes to its control registers
+    pub fn ppu_ready(&self) -> bool {
+        !self.ppu_warmup || self.cycles_since_power_on >= PPU_WARMUP_CYCLES
+    }
+
+    // iNES mapper number of the mounted cartridge
+    pub fn mapper(&self) -> u16 {
+        self.mapper.number()
+    }
+
+    // Selected PRG bank of the mounted cartridge, for debuggers
+    pub fn prg_bank(&self) -> usize {
+        self.mapper.prg_bank()
+    }
+
+    // Selected CHR bank of the mounted cartridge, for debuggers
+    pub fn chr_bank(&self) -> usize {
+        self.mapper.chr_bank()
+    }
+
+    // Copy data straight into PRG ROM, for loading programs without a cartridge image
+    // Data running past 0xFFFF wraps around to RAM, as the CPU would read it
+    pub fn load_prg(&mut self, address: u16, data: &[u8]) {
+        for (offset, byte) in data.iter().enumerate() {
+            self.poke(address.wrapping_add(offset as u16), *byte);
+        }
+    }
+
+    // Store a byte in RAM, PRG RAM or PRG ROM without touching I/O or watchpoints
+    // Addresses with no memory behind them (registers, expansion space) are ignored
+    pub fn poke(&mut self, address: u16, data: u8) {
+        match address {
+            RAM..=RAM_MIRRORS_END => {
+                let index = self.ram_index(address);
+                self.cpu_vram[index] = data
+            }
+            0x6000..=0x7FFF => self.prg_ram[(address - 0x6000) as usize] = data,
+            0x8000..=0xFFFF => self.mapper.load_prg(address, data),
+            _ => {}
+        }
+    }
+
+    // Fill work RAM with pseudo-random bytes, as on a console that was just powered on
+    pub fn randomize_ram(&mut self, seed: u64) {
+        self.fill_ram(&mut XorShift::new(seed));
+    }
+
+    // Fill work RAM from any random source
+    pub fn fill_ram(&mut self, source: &mut dyn RandomSource) {
+        for byte in self.cpu_vram.iter_mut() {
+            *byte = source.next_u8();
+        }
+    }
+
+    // Output lines last written to 0x4016: bit 0 (OUT0) is the controller strobe,
+    // bits 1-2 (OUT1, OUT2) drive the expansion port for accessories
+    pub fn output_latch(&self) -> u8 {
+        self.out_latch
+    }
+
+    // Take an NMI raised by the PPU, clearing it
+    pub fn poll_nmi(&mut self) -> bool {
+        self.ppu.poll_nmi()
+    }
+
+    // Force an NMI without driving the PPU to VBlank, for tests and debuggers
+    pub fn request_nmi(&mut self) {
+        self.ppu.request_nmi();
+    }
+
+    // Force an IRQ without an interrupting device; it is held until the CPU takes it
+    pub fn request_irq(&mut self) {
+        self.irq_requested = true;
+    }
+
+    // Level of the IRQ line: held while the APU has an unacknowledged interrupt
+    pub fn irq_pending(&self) -> bool {
+        self.apu.irq() || self.irq_requested
+    }
+
+    // Take the IRQ line if it is held, dropping a request_irq() request
+    // The APU keeps its own interrupt asserted until the program acknowledges it
+    pub fn poll_irq(&mut self) -> bool {
+        let pending = self.irq_pending();
+        self.irq_requested = false;
+        pending
+    }
+
+    // Start recording every read and write; accesses past MEM_TRACE_LIMIT are dropped
+    pub fn start_mem_trace(&mut self) {
+        self.mem_trace = Some(Vec::new());
+    }
+
+    // Stop tracing and return each recorded access in order
+    pub fn take_mem_trace(&mut self) -> Vec<MemAccess> {
+        self.mem_trace.take().unwrap_or_default()
+    }
+
+    fn trace_access(&mut self, address: u16, data: u8, kind: AccessKind) {
+        if let Some(trace) = &mut self.mem_trace {
+            if trace.len() < MEM_TRACE_LIMIT {
+                trace.push((address, data, kind));
+            }
+        }
+    }
+
+    // Record every write to an address in watch_hits
+    pub fn add_write_watchpoint(&mut self, address: u16) {
+        self.write_watchpoints.insert(address);
+    }
+
+    // Offset into work RAM of an address in 0x0000-0x1FFF
+    // With mirroring off the whole range is flat RAM, as simple 6502 teaching programs assume
+    fn ram_index(&self, address: u16) -> usize {
+        if self.ram_mirroring {
+            (address & 0b0000_0111_1111_1111) as usize
+        } else {
+            address as usize
+        }
+    }
+
+    // Read a byte, dispatching by address range
+    // Addresses with no device behind them return the open-bus value
+    pub fn mem_read(&mut self, address: u16) -> u8 {
+        let data = match address {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[self.ram_index(address)],
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let data = match address & 0x2007 {
+                    0x2002 => self.ppu.read_status(),
+                    0x2004 => self.ppu.read_oam_data(),
+                    0x2007 => self.ppu.read_data(),
+                    _ => self.ppu.io_latch(), // Write-only
+                };
+                self.ppu.set_io_latch(data);
+                data
+            }
+            0x4015 => self.apu.read_status(),
+            0x4016 => self.joypad1.read(),
+            0x4000..=0x401F => self.last_bus_value, // Write-only, or the unemulated second controller
+            0x4020..=0x5FFF => self.last_bus_value, // No expansion hardware
+            0x6000..=0x7FFF => self.prg_ram[(address - 0x6000) as usize],
+            0x8000..=0xFFFF => self.mapper.read_prg(address),
+        };
+        self.last_bus_value = data;
+        self.trace_access(address, data, AccessKind::Read);
+        data
+    }
+
+    // Zero-page accesses for the CPU's read-modify-write fast path: page zero is always
+    // work RAM, so these skip the address decoding but keep the open-bus value, the
+    // memory trace and the watchpoints the same as mem_read and mem_write
+    pub fn read_zero_page(&mut self, address: u8) -> u8 {
+        let data = self.cpu_vram[address as usize];
+        self.last_bus_value = data;
+        self.trace_access(address as u16, data, AccessKind::Read);
+        data
+    }
+
+    pub fn write_zero_page(&mut self, address: u8, data: u8) {
+        self.last_bus_value = data;
+        self.trace_access(address as u16, data, AccessKind::Write);
+        if self.write_watchpoints.contains(&(address as u16)) {
+            self.watch_hits.push((address as u16, data));
+        }
+        self.cpu_vram[address as usize] = data;
+    }
+
+    // Read memory without side effects, for debuggers
+    // PPUSTATUS is reported without clearing VBlank or the latch and OAMDATA is read as
+    // usual; other I/O registers are not read and the open-bus value stands in for them
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[self.ram_index(address)],
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END if address & 0x2007 == 0x2002 => {
+                self.ppu.peek_status()
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END if address & 0x2007 == 0x2004 => {
+                self.ppu.read_oam_data()
+            }
+            0x6000..=0x7FFF => self.prg_ram[(address - 0x6000) as usize],
+            0x8000..=0xFFFF => self.mapper.read_prg(address),
+            _ => self.last_bus_value,
+        }
+    }
+
+    // Write a byte, dispatching by address range
+    pub fn mem_write(&mut self, address: u16, data: u8) {
+        self.last_bus_value = data;
+        self.trace_access(address, data, AccessKind::Write);
+        if self.write_watchpoints.contains(&address) {
+            self.watch_hits.push((address, data));
+        }
+        match address {
+            RAM..=RAM_MIRRORS_END => {
+                let index = self.ram_index(address);
+                self.cpu_vram[index] = data;
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                // Every write reaches the latch, even one the registers ignore
+                self.ppu.set_io_latch(data);
+                match address & 0x2007 {
+                    0x2000 | 0x2001 | 0x2005 | 0x2006 if !self.ppu_ready() => {} // Still warming up
+                    0x2000 => self.ppu.write_to_ctrl(data),
+                    0x2001 => self.ppu.write_to_mask(data),
+                    0x2003 => self.ppu.write_to_oam_addr(data),
+                    0x2004 => self.ppu.write_to_oam_data(data),
+                    0x2005 => self.ppu.write_to_scroll(data),
+                    0x2006 => self.ppu.write_to_ppu_addr(data),
+                    0x2007 => self.ppu.write_to_data(data),
+                    _ => {} // Read-only
+                }
+            }
+            0x4016 => {
+                self.out_latch = data & 0b111;
+                self.joypad1.write(data);
+            }
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(address, data),
+            0x4014 => self.oam_dma(data),
+            0x4000..=0x401F => {} // Unused APU and I/O test registers
+            0x4020..=0x5FFF => {} // No expansion hardware
+            0x6000..=0x7FFF => self.prg_ram[(address - 0x6000) as usize] = data,
+            0x8000..=0xFFFF => {
+                self.mapper.write_prg(address, data);
+                self.sync_mapper();
+            }
+        }
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        // A blank NROM cartridge, used when programs are loaded directly into memory
+        // Such programs set up the PPU straight away, so there is no warm-up
+        let rom = Rom::default();
+        let ppu = PPU::new(rom.chr_rom.clone(), rom.screen_mirroring);
+        let mut bus = Bus::with_mapper(Box::new(Nrom::new(rom)), ppu);
+        bus.ppu_warmup = false;
+        bus
+    }
+}
+
+// Watchpoints are debugging setup and stay as they are across a load
+impl SaveState for Bus {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.cpu_vram);
+        writer.bytes(&self.prg_ram);
+        writer.u16(self.mapper.number());
+        self.mapper.write_state(writer);
+        self.ppu.write_state(writer);
+        self.apu.write_state(writer);
+        self.joypad1.write_state(writer);
+        writer.u8(self.last_bus_value);
+        writer.u8(self.out_latch);
+        writer.bytes(&self.frame.data);
+        writer.u64(self.frame_count);
+        writer.u64(self.frames_rendered);
+        writer.u64(self.cycles_since_power_on);
+        writer.u64(self.master_cycles);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        reader.bytes_into(&mut self.cpu_vram)?;
+        reader.bytes_into(&mut self.prg_ram)?;
+        // The mapper number tags which concrete mapper the state belongs to
+        let number = reader.u16()?;
+        if number != self.mapper.number() {
+            return Err(EmuError::InvalidSaveState(format!(
+                "state is for mapper {}, the cartridge uses mapper {}",
+                number,
+                self.mapper.number()
+            )));
+        }
+        self.mapper.read_state(reader)?;
+        self.ppu.read_state(reader)?;
+        self.apu.read_state(reader)?;
+        self.joypad1.read_state(reader)?;
+        self.last_bus_value = reader.u8()?;
+        self.out_latch = reader.u8()?;
+        reader.bytes_into(&mut self.frame.data)?;
+        self.frame_count = reader.u64()?;
+        self.frames_rendered = reader.u64()?;
+        self.cycles_since_power_on = reader.u64()?;
+        self.master_cycles = reader.u64()?;
+        Ok(())
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::cartridge::Mirroring;
+    use crate::hardware::CPU;
+    use crate::joypad::JoypadButton;
+    use crate::palette::SYSTEM_PALETTE;
+    use crate::ppu::Region;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Mapper stub that records every register write it receives
+    struct WriteCountingMapper {
+        prg_rom: Vec<u8>,                    // 32KB mapped at 0x8000-0xFFFF
+        writes: Rc<RefCell<Vec<(u16, u8)>>>, // (address, value) of each write, shared with the test
+    }
+
+    impl Mapper for WriteCountingMapper {
+        fn number(&self) -> u16 {
+            0
+        }
+
+        fn read_prg(&self, address: u16) -> u8 {
+            self.prg_rom[(address - 0x8000) as usize]
+        }
+
+        fn write_prg(&mut self, address: u16, data: u8) {
+            self.writes.borrow_mut().push((address, data));
+        }
+
+        fn load_prg(&mut self, address: u16, data: u8) {
+            self.prg_rom[(address - 0x8000) as usize] = data;
+        }
+    }
+
+    impl SaveState for WriteCountingMapper {
+        fn write_state(&self, _writer: &mut StateWriter) {}
+
+        fn read_state(&mut self, _reader: &mut StateReader) -> Result<(), EmuError> {
+            Ok(())
+        }
+    }
+
+    // Test case for RAM mirroring every 2KB up to 0x1FFF
+    #[test]
+    fn test_ram_mirroring() {
+        let mut bus = Bus::default();
+        bus.mem_write(0x0001, 0x42);
+        assert_eq!(bus.mem_read(0x0801), 0x42);
+        assert_eq!(bus.mem_read(0x1801), 0x42);
+    }
+
+    // Test case for writes to 0x2006/0x2007 reaching the PPU
+    #[test]
+    fn test_ppu_register_writes_reach_ppu() {
+        let mut bus = Bus::default();
+        bus.mem_write(0x2006, 0x21);
+        bus.mem_write(0x2006, 0x08);
+        assert_eq!(bus.ppu.vram_addr(), 0x2108);
+        bus.mem_write(0x2007, 0x77);
+        assert_eq!(bus.ppu.vram[0x0108], 0x77);
+    }
+
+    // Test case for reads of 0x4016 reaching the controller
+    #[test]
+    fn test_controller_read_reaches_joypad() {
+        let mut bus = Bus::default();
+        bus.joypad1.set_button_pressed_status(JoypadButton::A, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        assert_eq!(bus.mem_read(0x4016), 1); // A
+        assert_eq!(bus.mem_read(0x4016), 0); // B
+    }
+
+    // Test case for 0x4016 writes keeping the expansion port bits beside the strobe
+    #[test]
+    fn test_controller_port_write_keeps_expansion_bits() {
+        let mut bus = Bus::default();
+        bus.joypad1.set_button_pressed_status(JoypadButton::A, true);
+        bus.mem_write(0x4016, 0b1111_1101);
+        assert_eq!(bus.output_latch(), 0b101);
+        // Strobe is high, so every read reports the live A button
+        assert_eq!(bus.mem_read(0x4016), 1);
+        assert_eq!(bus.mem_read(0x4016), 1);
+
+        bus.mem_write(0x4016, 0b100);
+        assert_eq!(bus.output_latch(), 0b100);
+        assert_eq!(bus.mem_read(0x4016), 1); // A, latched as strobe fell
+        assert_eq!(bus.mem_read(0x4016), 0); // B
+    }
+
+    // Test case for PRG RAM at 0x6000-0x7FFF
+    #[test]
+    fn test_prg_ram() {
+        let mut bus = Bus::default();
+        bus.mem_write(0x6000, 0x12);
+        assert_eq!(bus.mem_read(0x6000), 0x12);
+    }
+
+    // Test case for a program loaded near 0xFFFF wrapping around to RAM
+    #[test]
+    fn test_load_prg_wraps_at_0xffff() {
+        let mut bus = Bus::default();
+        bus.load_prg(0xFFFE, &[0x12, 0x34, 0x56]);
+        assert_eq!(bus.peek(0xFFFF), 0x34);
+        assert_eq!(bus.peek(0x0000), 0x56);
+    }
+
+    // Test case for write-only registers returning the open-bus value
+    #[test]
+    fn test_open_bus_on_write_only_register() {
+        let mut bus = Bus::default();
+        bus.mem_write(0x0010, 0x5A);
+        assert_eq!(bus.mem_read(0x4000), 0x5A); // The pulse 1 control register is write-only
+    }
+
+    // Test case for unmapped expansion space returning the last value read
+    #[test]
+    fn test_open_bus_on_unmapped_read() {
+        let mut bus = Bus::default();
+        bus.mem_write(0x0010, 0xC3);
+        bus.mem_write(0x0011, 0x00);
+        bus.mem_read(0x0010); // Reads drive the bus too
+        assert_eq!(bus.mem_read(0x5000), 0xC3);
+    }
+
+    // Test case for poke bypassing registers and storing into every kind of memory
+    #[test]
+    fn test_poke_bypasses_io() {
+        let mut bus = Bus::default();
+        bus.add_write_watchpoint(0x0800);
+        bus.poke(0x0800, 0x11); // RAM mirror
+        bus.poke(0x6001, 0x22);
+        bus.poke(0x9000, 0x33);
+        bus.poke(0x2006, 0x21); // Ignored instead of reaching PPUADDR
+        assert_eq!(bus.mem_read(0x0000), 0x11);
+        assert_eq!(bus.mem_read(0x6001), 0x22);
+        assert_eq!(bus.mem_read(0x9000), 0x33);
+        assert_eq!(bus.ppu.vram_addr(), 0);
+        assert!(bus.watch_hits.is_empty());
+    }
+
+    // Test case for four-screen VRAM keeping all four nametables independent
+    #[test]
+    fn test_four_screen_nametables_are_independent() {
+        let raw = crate::cartridge::test::test_rom_bytes(0b1000, 0x00, 1, 1);
+        let mut bus = Bus::new(Rom::new(&raw).unwrap()).unwrap();
+        bus.ppu_warmup = false; // Write VRAM straight after power-on
+        for (i, base) in [0x20u8, 0x24, 0x28, 0x2c].iter().enumerate() {
+            bus.mem_write(0x2006, *base);
+            bus.mem_write(0x2006, 0x00);
+            bus.mem_write(0x2007, i as u8 + 1);
+        }
+        for (i, base) in [0x20u8, 0x24, 0x28, 0x2c].iter().enumerate() {
+            bus.mem_write(0x2006, *base);
+            bus.mem_write(0x2006, 0x00);
+            bus.mem_read(0x2007); // Fill the read buffer
+            assert_eq!(bus.mem_read(0x2007), i as u8 + 1);
+        }
+    }
+
+    // Test case for peek leaving VBlank set where a read of PPUSTATUS clears it
+    #[test]
+    fn test_peek_status_has_no_side_effects() {
+        let mut bus = Bus::default();
+        for _ in 0..241 {
+            bus.ppu.tick(341); // Run to the start of VBlank one scanline at a time
+        }
+        for _ in 0..3 {
+            assert_eq!(bus.peek(0x2002) & 0b1000_0000, 0b1000_0000);
+        }
+        assert_eq!(bus.mem_read(0x2002) & 0b1000_0000, 0b1000_0000);
+        assert_eq!(bus.peek(0x2002) & 0b1000_0000, 0);
+    }
+
+    // Test case for OAMDATA writes filling consecutive bytes from OAMADDR
+    #[test]
+    fn test_oam_data_writes_increment_address() {
+        let mut bus = Bus::default();
+        bus.mem_write(0x2003, 0x10);
+        for byte in [0xaa, 0xbb, 0xcc] {
+            bus.mem_write(0x2004, byte);
+        }
+        assert_eq!(bus.ppu.oam_data[0x10..0x13], [0xaa, 0xbb, 0xcc]);
+
+        // Reads do not move OAMADDR
+        bus.mem_write(0x2003, 0x11);
+        assert_eq!(bus.mem_read(0x2004), 0xbb);
+        assert_eq!(bus.mem_read(0x2004), 0xbb);
+    }
+
+    // Test case for reading 0x4015 reporting and acknowledging the frame interrupt
+    #[test]
+    fn test_status_read_acknowledges_frame_irq() {
+        let mut bus = Bus::default();
+        bus.apu.tick(29830); // Past the last step of the 4-step sequence
+        assert!(bus.irq_pending());
+        assert_eq!(bus.mem_read(0x4015) & 0b0100_0000, 0b0100_0000);
+        assert!(!bus.irq_pending());
+        assert_eq!(bus.mem_read(0x4015) & 0b0100_0000, 0);
+
+        // With the inhibit bit set no frame interrupt is raised
+        bus.mem_write(0x4017, 0b0100_0000);
+        bus.apu.tick(29830);
+        assert!(!bus.irq_pending());
+    }
+
+    // Test case for a scroll write mid-frame moving only the lines drawn after it
+    #[test]
+    fn test_mid_frame_scroll_split() {
+        let mut bus = Bus::default();
+        bus.ppu.chr_rom[16..24].fill(0b1000_0000); // Tile 1: left column, colour 1
+        for row in 0..30 {
+            bus.ppu.vram[row * 32] = 1; // Tile 1 down the left edge of the nametable
+        }
+        bus.ppu.palette_table[0] = 0x0f;
+        bus.ppu.palette_table[1] = 0x30;
+        bus.mem_write(0x2001, 0b0000_1010); // Background on, including the left column
+
+        while bus.ppu.scanline < 120 {
+            bus.tick(1);
+        }
+        bus.mem_write(0x2005, 8); // Scroll one tile right for the rest of the frame
+        bus.mem_write(0x2005, 0);
+        while bus.frame_count == 0 {
+            bus.tick(1);
+        }
+
+        assert_eq!(bus.frame.pixel(0, 100), SYSTEM_PALETTE[0x30]);
+        assert_eq!(bus.frame.pixel(0, 200), SYSTEM_PALETTE[0x0f]);
+        assert_eq!(bus.frame.pixel(248, 200), SYSTEM_PALETTE[0x30]); // Next nametable's column 0
+        assert_eq!(bus.frame.pixel(248, 100), SYSTEM_PALETTE[0x0f]);
+    }
+
+    // Test case for both writes of an RMW instruction reaching the mapper registers
+    #[test]
+    fn test_rmw_writes_reach_mapper() {
+        let writes = Rc::new(RefCell::new(vec![]));
+        let mapper = WriteCountingMapper {
+            prg_rom: vec![0; 0x8000],
+            writes: writes.clone(),
+        };
+        let mut cpu = CPU::new();
+        cpu.bus = Bus::with_mapper(
+            Box::new(mapper),
+            PPU::new(vec![0; 0x2000], Mirroring::Horizontal),
+        );
+        cpu.load(assemble(&["INC $C000", "BRK"]));
+        cpu.reset();
+        cpu.poke(0xc000, 0x41);
+        cpu.step().unwrap();
+        // The unmodified value read from the mapper goes back first, then the result
+        assert_eq!(*writes.borrow(), vec![(0xc000, 0x41), (0xc000, 0x42)]);
+    }
+
+    // Test case for a cartridge ignoring PPUCTRL writes until the PPU has warmed up
+    #[test]
+    fn test_ppu_warmup_drops_early_writes() {
+        let mut bus = Bus::new(Rom::default()).unwrap();
+        bus.mem_write(0x2000, 0b01);
+        assert_eq!(bus.ppu.nametable_addr(), 0x2000);
+
+        while bus.cycles_since_power_on < PPU_WARMUP_CYCLES - 1 {
+            bus.tick(1);
+        }
+        bus.mem_write(0x2000, 0b01);
+        assert_eq!(bus.ppu.nametable_addr(), 0x2000);
+        bus.mem_write(0x2007, 0x55); // PPUDATA is not gated
+        assert_eq!(bus.ppu.vram_addr(), 0x0001);
+
+        bus.tick(1);
+        assert!(bus.ppu_ready());
+        bus.mem_write(0x2000, 0b01);
+        assert_eq!(bus.ppu.nametable_addr(), 0x2400);
+    }
+
+    // Test case for reads of write-only PPU registers returning the I/O latch
+    #[test]
+    fn test_write_only_ppu_registers_read_latch() {
+        let mut bus = Bus::new(Rom::default()).unwrap();
+        bus.mem_write(0x2000, 0x5a);
+        bus.mem_write(0x0000, 0x00); // The CPU's open bus no longer holds the value
+        assert_eq!(bus.mem_read(0x2000), 0x5a);
+        assert_eq!(bus.mem_read(0x2005), 0x5a);
+        assert_eq!(bus.mem_read(0x3ffb), 0x5a); // Mirror of 0x2003
+
+        // PPUSTATUS fills its low five bits from the latch and leaves its value there
+        bus.mem_write(0x2001, 0b0001_0111);
+        assert_eq!(bus.mem_read(0x2002), 0b0001_0111);
+        bus.ppu.write_to_oam_addr(0);
+        bus.ppu.oam_data[0] = 0xc3;
+        assert_eq!(bus.mem_read(0x2004), 0xc3);
+        assert_eq!(bus.mem_read(0x2006), 0xc3);
+    }
+
+    // Test case for the CPU and PPU both being derived from the master clock
+    #[test]
+    fn test_master_clock_dividers() {
+        let mut bus = Bus::new(Rom::default()).unwrap();
+        bus.tick(1);
+        assert_eq!(bus.master_cycles(), 12);
+        assert_eq!(bus.cycles_since_power_on, 1);
+        assert_eq!(bus.ppu.cycles, 3);
+
+        // PAL fits 16 dots into 5 CPU cycles, so one cycle in five carries a fourth dot
+        let mut bus = Bus::new(Rom::default()).unwrap();
+        bus.ppu.region = Region::Pal;
+        let dots: Vec<u16> = (0..5)
+            .map(|_| {
+                let before = bus.ppu.cycles;
+                bus.tick(1);
+                bus.ppu.cycles - before
+            })
+            .collect();
+        assert_eq!(dots, vec![3, 3, 3, 3, 4]);
+        assert_eq!(bus.master_cycles(), 80);
+    }
+}
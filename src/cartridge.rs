@@ -0,0 +1,355 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// iNES files start with the bytes "NES" followed by the MS-DOS end-of-file marker
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+
+// Nametable mirroring arrangement wired on the cartridge
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,        // Extra 2KB of nametable RAM on the cartridge, no mirroring
+    SingleScreenLower, // Mapper-selected: all four nametables show the first 1KB
+    SingleScreenUpper, // Mapper-selected: all four nametables show the second 1KB
+}
+
+// Define the Rom struct holding the parsed contents of a cartridge
+pub struct Rom {
+    pub prg_rom: Vec<u8>,            // Program ROM mapped at 0x8000-0xFFFF
+    pub chr_rom: Vec<u8>,            // Pattern tables seen by the PPU at 0x0000-0x1FFF
+    pub mapper: u8,                  // iNES mapper number
+    pub screen_mirroring: Mirroring, // Nametable mirroring
+    pub vs_unisystem: bool,          // Dumped from a VS Unisystem arcade board
+    pub playchoice_10: bool,         // Dumped from a PlayChoice-10 arcade board
+}
+
+impl Rom {
+    // Parse a raw iNES image
+    pub fn new(raw: &[u8]) -> Result<Rom, String> {
+        if raw.len() < 16 || raw[0..4] != NES_TAG {
+            return Err("File is not in iNES file format".to_string());
+        }
+
+        // The low bits of flags 7 hold console-type flags, not mapper bits
+        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+        let vs_unisystem = raw[7] & 0b01 != 0;
+        let playchoice_10 = raw[7] & 0b10 != 0;
+
+        // NES 2.0 headers (identifier 0b10) are read as iNES ones: the extended mapper,
+        // submapper and size bits in bytes 8-15 are ignored
+        let ines_ver = (raw[7] >> 2) & 0b11;
+        if ines_ver != 0 && ines_ver != 0b10 {
+            return Err("Unknown iNES header version".to_string());
+        }
+
+        // Bit 3 of flags 6 (four-screen VRAM) overrides the mirroring bit
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        // Every mapper needs a PRG bank to fetch the reset vector from
+        if raw[4] == 0 {
+            return Err("Header declares no PRG ROM".to_string());
+        }
+        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+
+        // A 512-byte trainer may sit between the header and the PRG data; any
+        // PlayChoice-10 hint ROM after the CHR data is ignored
+        let skip_trainer = raw[6] & 0b100 != 0;
+
+        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err("File is shorter than its header declares".to_string());
+        }
+
+        Ok(Rom {
+            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
+            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+            mapper,
+            screen_mirroring,
+            vs_unisystem,
+            playchoice_10,
+        })
+    }
+
+    // CRC32 of the PRG and CHR data, the key used by most game databases
+    // The header and any trainer are left out, so re-headered dumps still match
+    pub fn crc32(&self) -> u32 {
+        !self
+            .prg_rom
+            .iter()
+            .chain(&self.chr_rom)
+            .fold(0xFFFF_FFFF, |crc, &byte| {
+                (0..8).fold(crc ^ byte as u32, |crc, _| {
+                    (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+                })
+            })
+    }
+
+    // MD5 of the PRG and CHR data
+    pub fn md5(&self) -> [u8; 16] {
+        let mut data = [self.prg_rom.as_slice(), &self.chr_rom].concat();
+        md5(&mut data)
+    }
+}
+
+// Per-round shift amounts of MD5
+const MD5_SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+// Per-round constants of MD5: the integer part of 2^32 * |sin(i + 1)|
+const MD5_CONSTANTS: [u32; 64] = [
+    0xd76a_a478,
+    0xe8c7_b756,
+    0x2420_70db,
+    0xc1bd_ceee,
+    0xf57c_0faf,
+    0x4787_c62a,
+    0xa830_4613,
+    0xfd46_9501,
+    0x6980_98d8,
+    0x8b44_f7af,
+    0xffff_5bb1,
+    0x895c_d7be,
+    0x6b90_1122,
+    0xfd98_7193,
+    0xa679_438e,
+    0x49b4_0821,
+    0xf61e_2562,
+    0xc040_b340,
+    0x265e_5a51,
+    0xe9b6_c7aa,
+    0xd62f_105d,
+    0x0244_1453,
+    0xd8a1_e681,
+    0xe7d3_fbc8,
+    0x21e1_cde6,
+    0xc337_07d6,
+    0xf4d5_0d87,
+    0x455a_14ed,
+    0xa9e3_e905,
+    0xfcef_a3f8,
+    0x676f_02d9,
+    0x8d2a_4c8a,
+    0xfffa_3942,
+    0x8771_f681,
+    0x6d9d_6122,
+    0xfde5_380c,
+    0xa4be_ea44,
+    0x4bde_cfa9,
+    0xf6bb_4b60,
+    0xbebf_bc70,
+    0x289b_7ec6,
+    0xeaa1_27fa,
+    0xd4ef_3085,
+    0x0488_1d05,
+    0xd9d4_d039,
+    0xe6db_99e5,
+    0x1fa2_7cf8,
+    0xc4ac_5665,
+    0xf429_2244,
+    0x432a_ff97,
+    0xab94_23a7,
+    0xfc93_a039,
+    0x655b_59c3,
+    0x8f0c_cc92,
+    0xffef_f47d,
+    0x8584_5dd1,
+    0x6fa8_7e4f,
+    0xfe2c_e6e0,
+    0xa301_4314,
+    0x4e08_11a1,
+    0xf753_7e82,
+    0xbd3a_f235,
+    0x2ad7_d2bb,
+    0xeb86_d391,
+];
+
+// MD5 (RFC 1321) of a message, padding it in place
+fn md5(message: &mut Vec<u8>) -> [u8; 16] {
+    let bit_length = (message.len() as u64).wrapping_mul(8);
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&bit_length.to_le_bytes());
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+    for chunk in message.chunks_exact(64) {
+        let words: Vec<u32> = chunk
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(MD5_CONSTANTS[i])
+                .wrapping_add(words[g])
+                .rotate_left(MD5_SHIFTS[i]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 16];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+impl Default for Rom {
+    // A blank 32KB NROM cartridge, used when programs are loaded directly into memory
+    fn default() -> Self {
+        Rom {
+            prg_rom: vec![0; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![0; CHR_ROM_PAGE_SIZE],
+            mapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+            vs_unisystem: false,
+            playchoice_10: false,
+        }
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::error::EmuError;
+    use crate::hardware::CPU;
+
+    // Build a raw iNES image with the given header flags and page counts
+    pub fn test_rom_bytes(flags_6: u8, flags_7: u8, prg_pages: u8, chr_pages: u8) -> Vec<u8> {
+        let mut raw = vec![
+            0x4E, 0x45, 0x53, 0x1A, prg_pages, chr_pages, flags_6, flags_7,
+        ];
+        raw.resize(16, 0);
+        raw.resize(
+            16 + prg_pages as usize * PRG_ROM_PAGE_SIZE + chr_pages as usize * CHR_ROM_PAGE_SIZE,
+            0,
+        );
+        raw
+    }
+
+    // Test case for parsing a basic iNES header
+    #[test]
+    fn test_parse_ines_header() {
+        let rom = Rom::new(&test_rom_bytes(0x11, 0x00, 2, 1)).unwrap();
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+    }
+
+    // Test case for a NES 2.0 header giving the same mapper and sizes as iNES
+    #[test]
+    fn test_parse_nes_2_header() {
+        let mut raw = test_rom_bytes(0x21, 0x18, 2, 1);
+        raw[8] = 0x10; // Submapper 1
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 0x12);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+
+        assert!(Rom::new(&test_rom_bytes(0x00, 0x04, 1, 1)).is_err());
+    }
+
+    // Test case for rejecting a header with no PRG ROM instead of panicking on the reset vector
+    #[test]
+    fn test_reject_empty_prg_rom() {
+        assert!(Rom::new(&test_rom_bytes(0x00, 0x00, 0, 1)).is_err());
+        let mut cpu = CPU::new();
+        assert!(matches!(
+            cpu.load_rom(&test_rom_bytes(0x20, 0x00, 0, 1)),
+            Err(EmuError::InvalidRom(_))
+        ));
+    }
+
+    // Test case for rejecting data without the iNES tag
+    #[test]
+    fn test_reject_bad_tag() {
+        assert!(Rom::new(&[0; 32]).is_err());
+    }
+
+    // Test case for detecting four-screen VRAM from flags 6 bit 3
+    #[test]
+    fn test_parse_four_screen() {
+        let rom = Rom::new(&test_rom_bytes(0b1001, 0x00, 1, 1)).unwrap();
+        assert_eq!(rom.screen_mirroring, Mirroring::FourScreen);
+    }
+
+    // Test case for the VS Unisystem flag being reported without touching the mapper number
+    #[test]
+    fn test_parse_vs_unisystem_flag() {
+        let rom = Rom::new(&test_rom_bytes(0x10, 0x41, 1, 1)).unwrap();
+        assert_eq!(rom.mapper, 0x41);
+        assert!(rom.vs_unisystem);
+        assert!(!rom.playchoice_10);
+    }
+
+    // Test case for a PlayChoice-10 dump booting with its hint ROM ignored
+    #[test]
+    fn test_parse_playchoice_10_flag() {
+        let mut raw = test_rom_bytes(0x00, 0x02, 1, 1);
+        raw.extend_from_slice(&[0xff; 0x2000]);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 0);
+        assert!(rom.playchoice_10);
+        assert_eq!(rom.chr_rom, vec![0; CHR_ROM_PAGE_SIZE]);
+    }
+
+    // Test case for the CRC32 and MD5 of the PRG and CHR data against known digests
+    #[test]
+    fn test_rom_checksums() {
+        let mut rom = Rom {
+            prg_rom: b"1234".to_vec(),
+            chr_rom: b"56789".to_vec(),
+            ..Rom::default()
+        };
+        assert_eq!(rom.crc32(), 0xCBF4_3926);
+
+        rom.prg_rom = b"ab".to_vec();
+        rom.chr_rom = b"c".to_vec();
+        assert_eq!(
+            rom.md5(),
+            [
+                0x90, 0x01, 0x50, 0x98, 0x3c, 0xd2, 0x4f, 0xb0, 0xd6, 0x96, 0x3f, 0x7d, 0x28, 0xe1,
+                0x7f, 0x72
+            ]
+        );
+
+        // The header is not hashed, so the digests only follow the ROM data
+        let blank = Rom::new(&test_rom_bytes(0x00, 0x00, 1, 1)).unwrap();
+        let mirrored = Rom::new(&test_rom_bytes(0x01, 0x00, 1, 1)).unwrap();
+        assert_eq!(blank.crc32(), mirrored.crc32());
+        assert_eq!(blank.md5(), mirrored.md5());
+    }
+}
@@ -0,0 +1,13 @@
+// Receives the CPU cycles that pass, so a frontend can pace audio and video without
+// the core depending on std::time
+pub trait Clock {
+    fn tick(&mut self, cycles: u64);
+}
+
+// Clock that ignores time, used until a frontend installs its own
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullClock;
+
+impl Clock for NullClock {
+    fn tick(&mut self, _cycles: u64) {}
+}
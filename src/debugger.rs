@@ -0,0 +1,98 @@
+use crate::error::EmuError;
+use crate::hardware::CPU;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+// Define the Debugger struct wrapping a CPU with a bounded single-step undo history
+pub struct Debugger {
+    pub cpu: CPU,               // The console being debugged
+    history: VecDeque<Vec<u8>>, // Save states taken before each step, oldest first
+    depth: usize,               // Most steps that can be undone
+}
+
+impl Debugger {
+    // Constructor remembering up to `depth` steps
+    pub fn new(cpu: CPU, depth: usize) -> Self {
+        Debugger {
+            cpu,
+            history: VecDeque::with_capacity(depth),
+            depth,
+        }
+    }
+
+    // Execute one instruction, remembering the state before it
+    pub fn step(&mut self) -> Result<bool, EmuError> {
+        if self.depth > 0 {
+            if self.history.len() == self.depth {
+                self.history.pop_front();
+            }
+            self.history.push_back(self.cpu.save_state());
+        }
+        self.cpu.step()
+    }
+
+    // Undo the most recent step; returns false once the history is exhausted
+    pub fn step_back(&mut self) -> Result<bool, EmuError> {
+        match self.history.pop_back() {
+            Some(state) => {
+                self.cpu.load_state(&state)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // Number of steps that can currently be undone
+    pub fn undo_depth(&self) -> usize {
+        self.history.len()
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn debugger(depth: usize) -> Debugger {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&[
+            "LDA #$01", "STA $10", "INC $10", "LDA $10", "BRK",
+        ]));
+        cpu.reset();
+        Debugger::new(cpu, depth)
+    }
+
+    // Test case for stepping back to the state after the first instruction
+    #[test]
+    fn test_step_back_restores_intermediate_state() {
+        let mut dbg = debugger(8);
+        dbg.step().unwrap();
+        let expected = dbg.cpu.save_state();
+        let registers = dbg.cpu.registers();
+        dbg.step().unwrap();
+        dbg.step().unwrap();
+        assert_eq!(dbg.cpu.peek(0x10), 0x02);
+
+        assert!(dbg.step_back().unwrap());
+        assert!(dbg.step_back().unwrap());
+        assert_eq!(dbg.cpu.registers(), registers);
+        assert_eq!(dbg.cpu.peek(0x10), 0x00);
+        assert_eq!(dbg.cpu.save_state(), expected);
+        assert_eq!(dbg.undo_depth(), 1);
+    }
+
+    // Test case for the history dropping the oldest steps past its depth
+    #[test]
+    fn test_undo_history_is_bounded() {
+        let mut dbg = debugger(2);
+        for _ in 0..4 {
+            dbg.step().unwrap();
+        }
+        assert_eq!(dbg.undo_depth(), 2);
+        assert!(dbg.step_back().unwrap());
+        assert!(dbg.step_back().unwrap());
+        assert!(!dbg.step_back().unwrap());
+        assert_eq!(dbg.cpu.peek(0x10), 0x01); // Back to just before INC $10
+    }
+}
@@ -0,0 +1,54 @@
+use alloc::string::String;
+use core::fmt;
+
+// Errors surfaced by the emulator's run loops
+#[derive(Debug, PartialEq)]
+pub enum EmuError {
+    InstructionLimit,         // A run loop executed its maximum number of instructions
+    StackOverflow,            // Strict mode: a push with the stack pointer at 0x00
+    StackUnderflow,           // Strict mode: a pop with the stack pointer at 0xFF
+    Io(String),               // A ROM file could not be read
+    InvalidRom(String),       // A ROM image could not be parsed or mounted
+    Halted,                   // The program is stuck jumping to itself
+    UnsupportedMapper(u16),   // The cartridge uses a mapper with no implementation
+    InvalidSaveState(String), // A save state is truncated or from another format
+    InvalidMovie(String),     // A recorded movie could not be parsed or replayed
+    // The CPU fetched an opcode it does not implement
+    UnknownOpcode {
+        opcode: u8,
+        address: u16,                        // Where the opcode was fetched from
+        last_instruction: Option<(u16, u8)>, // (address, opcode) of the instruction before it
+    },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmuError::InstructionLimit => write!(f, "instruction limit reached"),
+            EmuError::StackOverflow => write!(f, "stack overflow"),
+            EmuError::StackUnderflow => write!(f, "stack underflow"),
+            EmuError::Io(message) => write!(f, "I/O error: {}", message),
+            EmuError::InvalidRom(message) => write!(f, "invalid ROM: {}", message),
+            EmuError::Halted => write!(f, "program halted in an infinite loop"),
+            EmuError::UnsupportedMapper(number) => write!(f, "mapper {} is not supported", number),
+            EmuError::InvalidSaveState(message) => write!(f, "invalid save state: {}", message),
+            EmuError::InvalidMovie(message) => write!(f, "invalid movie: {}", message),
+            EmuError::UnknownOpcode {
+                opcode,
+                address,
+                last_instruction,
+            } => {
+                write!(f, "unknown opcode ${:02X} at ${:04X}", opcode, address)?;
+                match last_instruction {
+                    Some((last_address, last_opcode)) => {
+                        write!(f, " after ${:02X} at ${:04X}", last_opcode, last_address)
+                    }
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EmuError {}
@@ -0,0 +1,28 @@
+// Things that happen while the emulator runs, streamed to a debugger frontend
+// attached with CPU::set_event_sink, possibly on another thread
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmuEvent {
+    // An instruction finished, with the cycles it took
+    InstructionExecuted {
+        address: u16,
+        opcode: u8,
+        cycles: u64,
+    },
+    // The CPU wrote to the bus
+    MemoryWrite {
+        address: u16,
+        value: u8,
+    },
+    // An NMI was taken, including one that hijacked a BRK
+    NmiFired,
+    // An IRQ was taken
+    IrqFired,
+    // The PPU finished a frame, counted from 1
+    FrameComplete {
+        frame: u64,
+    },
+    // An instruction at a breakpoint is about to run
+    Breakpoint {
+        address: u16,
+    },
+}
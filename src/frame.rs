@@ -0,0 +1,204 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+// 8x8 glyphs for ASCII 0x20-0x5F, one byte per row with bit 7 on the left
+// Lowercase letters are drawn in uppercase and any other character as '?'
+const FONT: [[u8; 8]; 64] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // Space
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x10, 0x00], // !
+    [0x28, 0x28, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+    [0x28, 0x28, 0x7c, 0x28, 0x7c, 0x28, 0x28, 0x00], // #
+    [0x10, 0x3c, 0x50, 0x38, 0x14, 0x78, 0x10, 0x00], // $
+    [0x60, 0x64, 0x08, 0x10, 0x20, 0x4c, 0x0c, 0x00], // %
+    [0x30, 0x48, 0x50, 0x20, 0x54, 0x48, 0x34, 0x00], // &
+    [0x10, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '
+    [0x08, 0x10, 0x20, 0x20, 0x20, 0x10, 0x08, 0x00], // (
+    [0x20, 0x10, 0x08, 0x08, 0x08, 0x10, 0x20, 0x00], // )
+    [0x00, 0x10, 0x54, 0x38, 0x54, 0x10, 0x00, 0x00], // *
+    [0x00, 0x10, 0x10, 0x7c, 0x10, 0x10, 0x00, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x18, 0x10, 0x20, 0x00], // ,
+    [0x00, 0x00, 0x00, 0x7c, 0x00, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x30, 0x00], // .
+    [0x00, 0x04, 0x08, 0x10, 0x20, 0x40, 0x00, 0x00], // /
+    [0x38, 0x44, 0x4c, 0x54, 0x64, 0x44, 0x38, 0x00], // 0
+    [0x10, 0x30, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00], // 1
+    [0x38, 0x44, 0x04, 0x08, 0x10, 0x20, 0x7c, 0x00], // 2
+    [0x7c, 0x08, 0x10, 0x08, 0x04, 0x44, 0x38, 0x00], // 3
+    [0x08, 0x18, 0x28, 0x48, 0x7c, 0x08, 0x08, 0x00], // 4
+    [0x7c, 0x40, 0x78, 0x04, 0x04, 0x44, 0x38, 0x00], // 5
+    [0x18, 0x20, 0x40, 0x78, 0x44, 0x44, 0x38, 0x00], // 6
+    [0x7c, 0x04, 0x08, 0x10, 0x20, 0x20, 0x20, 0x00], // 7
+    [0x38, 0x44, 0x44, 0x38, 0x44, 0x44, 0x38, 0x00], // 8
+    [0x38, 0x44, 0x44, 0x3c, 0x04, 0x08, 0x30, 0x00], // 9
+    [0x00, 0x30, 0x30, 0x00, 0x30, 0x30, 0x00, 0x00], // :
+    [0x00, 0x30, 0x30, 0x00, 0x30, 0x10, 0x20, 0x00], // ;
+    [0x08, 0x10, 0x20, 0x40, 0x20, 0x10, 0x08, 0x00], // <
+    [0x00, 0x00, 0x7c, 0x00, 0x7c, 0x00, 0x00, 0x00], // =
+    [0x20, 0x10, 0x08, 0x04, 0x08, 0x10, 0x20, 0x00], // >
+    [0x38, 0x44, 0x04, 0x08, 0x10, 0x00, 0x10, 0x00], // ?
+    [0x38, 0x44, 0x04, 0x34, 0x54, 0x54, 0x38, 0x00], // @
+    [0x38, 0x44, 0x44, 0x7c, 0x44, 0x44, 0x44, 0x00], // A
+    [0x78, 0x44, 0x44, 0x78, 0x44, 0x44, 0x78, 0x00], // B
+    [0x38, 0x44, 0x40, 0x40, 0x40, 0x44, 0x38, 0x00], // C
+    [0x70, 0x48, 0x44, 0x44, 0x44, 0x48, 0x70, 0x00], // D
+    [0x7c, 0x40, 0x40, 0x78, 0x40, 0x40, 0x7c, 0x00], // E
+    [0x7c, 0x40, 0x40, 0x78, 0x40, 0x40, 0x40, 0x00], // F
+    [0x38, 0x44, 0x40, 0x5c, 0x44, 0x44, 0x3c, 0x00], // G
+    [0x44, 0x44, 0x44, 0x7c, 0x44, 0x44, 0x44, 0x00], // H
+    [0x38, 0x10, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00], // I
+    [0x1c, 0x08, 0x08, 0x08, 0x08, 0x48, 0x30, 0x00], // J
+    [0x44, 0x48, 0x50, 0x60, 0x50, 0x48, 0x44, 0x00], // K
+    [0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x7c, 0x00], // L
+    [0x44, 0x6c, 0x54, 0x54, 0x44, 0x44, 0x44, 0x00], // M
+    [0x44, 0x44, 0x64, 0x54, 0x4c, 0x44, 0x44, 0x00], // N
+    [0x38, 0x44, 0x44, 0x44, 0x44, 0x44, 0x38, 0x00], // O
+    [0x78, 0x44, 0x44, 0x78, 0x40, 0x40, 0x40, 0x00], // P
+    [0x38, 0x44, 0x44, 0x44, 0x54, 0x48, 0x34, 0x00], // Q
+    [0x78, 0x44, 0x44, 0x78, 0x50, 0x48, 0x44, 0x00], // R
+    [0x3c, 0x40, 0x40, 0x38, 0x04, 0x04, 0x78, 0x00], // S
+    [0x7c, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00], // T
+    [0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x38, 0x00], // U
+    [0x44, 0x44, 0x44, 0x44, 0x44, 0x28, 0x10, 0x00], // V
+    [0x44, 0x44, 0x44, 0x54, 0x54, 0x54, 0x28, 0x00], // W
+    [0x44, 0x44, 0x28, 0x10, 0x28, 0x44, 0x44, 0x00], // X
+    [0x44, 0x44, 0x44, 0x28, 0x10, 0x10, 0x10, 0x00], // Y
+    [0x7c, 0x04, 0x08, 0x10, 0x20, 0x40, 0x7c, 0x00], // Z
+    [0x38, 0x20, 0x20, 0x20, 0x20, 0x20, 0x38, 0x00], // [
+    [0x00, 0x40, 0x20, 0x10, 0x08, 0x04, 0x00, 0x00], // Backslash
+    [0x38, 0x08, 0x08, 0x08, 0x08, 0x08, 0x38, 0x00], // ]
+    [0x10, 0x28, 0x44, 0x00, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7c, 0x00], // _
+];
+
+// Define the Frame struct holding one rendered picture as packed RGB
+#[derive(Clone)]
+pub struct Frame {
+    pub data: Vec<u8>, // WIDTH * HEIGHT pixels, three bytes each
+}
+
+impl Frame {
+    pub const WIDTH: usize = 256;
+    pub const HEIGHT: usize = 240;
+
+    // Constructor to create a new, black Frame
+    pub fn new() -> Self {
+        Frame {
+            data: vec![0; Frame::WIDTH * Frame::HEIGHT * 3],
+        }
+    }
+
+    // Set one pixel, ignoring coordinates outside the picture
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        if x >= Frame::WIDTH || y >= Frame::HEIGHT {
+            return;
+        }
+        let base = (y * Frame::WIDTH + x) * 3;
+        self.data[base] = rgb.0;
+        self.data[base + 1] = rgb.1;
+        self.data[base + 2] = rgb.2;
+    }
+
+    // Draw text in the built-in 8x8 font with its top-left corner at (x, y), for FPS
+    // counters and debug messages. '\n' starts a new line below the first character;
+    // glyphs are clipped at the frame edges and only their set pixels are drawn.
+    // Positions saturate, so origins far off the picture clip instead of overflowing
+    pub fn draw_text(&mut self, x: u16, y: u16, text: &str, color: [u8; 3]) {
+        let rgb = (color[0], color[1], color[2]);
+        let (mut glyph_x, mut glyph_y) = (x, y);
+        for ch in text.chars() {
+            if ch == '\n' {
+                glyph_x = x;
+                glyph_y = glyph_y.saturating_add(8);
+                continue;
+            }
+            let code = ch.to_ascii_uppercase() as u32;
+            let glyph = match code {
+                0x20..=0x5f => &FONT[(code - 0x20) as usize],
+                _ => &FONT[('?' as u32 - 0x20) as usize],
+            };
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..8u16 {
+                    if bits & (0x80 >> column) != 0 {
+                        let pixel_x = glyph_x.saturating_add(column) as usize;
+                        let pixel_y = glyph_y.saturating_add(row as u16) as usize;
+                        self.set_pixel(pixel_x, pixel_y, rgb);
+                    }
+                }
+            }
+            glyph_x = glyph_x.saturating_add(8);
+        }
+    }
+
+    // Read one pixel back
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * Frame::WIDTH + x) * 3;
+        (self.data[base], self.data[base + 1], self.data[base + 2])
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WHITE: [u8; 3] = [255, 255, 255];
+
+    // Rows of the 8x8 cell at (x, y) as bit patterns, bit 7 on the left
+    fn cell(frame: &Frame, x: usize, y: usize) -> [u8; 8] {
+        let mut rows = [0; 8];
+        for (row, bits) in rows.iter_mut().enumerate() {
+            for column in 0..8 {
+                if frame.pixel(x + column, y + row) == (255, 255, 255) {
+                    *bits |= 0x80 >> column;
+                }
+            }
+        }
+        rows
+    }
+
+    // Test case for drawing "A" at the origin setting exactly the glyph's pixels
+    #[test]
+    fn test_draw_text_glyph() {
+        let mut frame = Frame::new();
+        frame.draw_text(0, 0, "A", WHITE);
+        assert_eq!(
+            cell(&frame, 0, 0),
+            [0x38, 0x44, 0x44, 0x7c, 0x44, 0x44, 0x44, 0x00]
+        );
+        assert_eq!(cell(&frame, 8, 0), [0; 8]);
+        assert_eq!(frame.pixel(0, 0), (0, 0, 0)); // Unset glyph pixels are left alone
+    }
+
+    // Test case for newlines, lowercase folding and clipping at the right and bottom edges
+    #[test]
+    fn test_draw_text_newline_and_clipping() {
+        let mut frame = Frame::new();
+        frame.draw_text(0, 0, "a\nA", WHITE);
+        assert_eq!(cell(&frame, 0, 8), cell(&frame, 0, 0));
+        assert_eq!(cell(&frame, 8, 0), [0; 8]);
+
+        // Only the left columns of the last glyph fit; no panic past the corner
+        let (right, bottom) = (Frame::WIDTH as u16 - 4, Frame::HEIGHT as u16 - 4);
+        frame.draw_text(right, bottom, "AA", WHITE);
+        assert_eq!(frame.pixel(Frame::WIDTH - 3, Frame::HEIGHT - 4), (0, 0, 0));
+        assert_eq!(
+            frame.pixel(Frame::WIDTH - 2, Frame::HEIGHT - 4),
+            (255, 255, 255)
+        );
+    }
+
+    // Test case for origins near u16::MAX clipping instead of overflowing
+    #[test]
+    fn test_draw_text_large_origin() {
+        let mut frame = Frame::new();
+        frame.draw_text(u16::MAX - 3, u16::MAX - 3, "AA\nAA", WHITE);
+        frame.draw_text(0, u16::MAX, "A", WHITE);
+        assert!(frame.data.iter().all(|&byte| byte == 0));
+    }
+}
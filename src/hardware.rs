@@ -1,22 +1,131 @@
+use crate::bus::Bus;
+use crate::cartridge::Rom;
+use crate::clock::{Clock, NullClock};
+use crate::error::EmuError;
+use crate::event::EmuEvent;
+use crate::frame::Frame;
+use crate::log;
+use crate::opcodes;
+use crate::ppu::PpuSnapshot;
+use crate::random::RandomSource;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::collections::BTreeSet;
+#[cfg(feature = "std")]
+use std::fs::{self, File};
+#[cfg(feature = "std")]
+use std::io::{BufWriter, Write};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "std")]
+use std::sync::mpsc::Sender;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
 // Constants for stack start address and stack reset value
 // The reason the NES stack ends at 253 bytes (0x01FD) rather than 256 bytes (0x01FF) is due to a hardware limitation.
 // The top three addresses (0x01FD, 0x01FE, and 0x01FF) are reserved for the NES's interrupt vector table.
-const STACK_START: u16 = 0x0100;
-const STACK_RESET: u8 = 0xfd;
+pub const STACK_START: u16 = 0x0100;
+pub const STACK_RESET: u8 = 0xfd;
+
+// Status register flag bits
+const CARRY_FLAG: u8 = 0b0000_0001;
+const INTERRUPT_DISABLE_FLAG: u8 = 0b0000_0100;
+const DECIMAL_FLAG: u8 = 0b0000_1000;
+const BREAK_FLAG: u8 = 0b0001_0000;
+const UNUSED_FLAG: u8 = 0b0010_0000;
+
+// CPU cycles taken by the reset, NMI and IRQ sequences
+const INTERRUPT_CYCLES: u64 = 7;
+
+// Frames of timing statistics kept by frame_stats()
+const FRAME_STATS_LEN: usize = 60;
+
+// Status flags named by diff(), bit 7 first
+const FLAG_NAMES: [(u8, char); 8] = [
+    (0b1000_0000, 'N'),
+    (0b0100_0000, 'V'),
+    (0b0010_0000, 'U'),
+    (0b0001_0000, 'B'),
+    (0b0000_1000, 'D'),
+    (0b0000_0100, 'I'),
+    (0b0000_0010, 'Z'),
+    (0b0000_0001, 'C'),
+];
+
+// Save slots are numbered 0 to SAVE_SLOTS - 1
+#[cfg(feature = "std")]
+const SAVE_SLOTS: u8 = 10;
+
+// Upper bound on instructions executed by run_until, so a program that never
+// meets its halt condition cannot hang the caller
+const RUN_UNTIL_LIMIT: u64 = 1_000_000;
 
 // Define the CPU struct
 pub struct CPU {
-    pub accumulator: u8,      // Accumulator register
-    pub index_x: u8,          // X index register
-    pub index_y: u8,          // Y index register
-    pub status: u8,           // Status register (flags)
-    pub program_counter: u16, // Program counter
-    pub stack_pointer: u8,    // Stack pointer
-    memory: [u8; 0xFFFF],     // Memory array to store data and instructions
+    pub accumulator: u8,                     // Accumulator register
+    pub index_x: u8,                         // X index register
+    pub index_y: u8,                         // Y index register
+    pub status: u8,                          // Status register (flags)
+    pub program_counter: u16,                // Program counter
+    pub stack_pointer: u8,                   // Stack pointer
+    pub reset_stack_pointer: u8,             // Stack pointer loaded by reset(), STACK_RESET
+    pub strict_stack: bool,                  // Report stack overflow/underflow instead of wrapping
+    pub brk_halts: bool,                     // Stop at BRK instead of taking its interrupt
+    pub cycles: u64,                         // Total CPU cycles executed
+    pub decimal_enabled: bool,               // Honour the decimal flag (unused by the NES 2A03)
+    pub program_base: u16,                   // Address that load() places programs at
+    pub loop_detect_threshold: Option<u32>,  // Stop after this many consecutive `JMP self`
+    self_jumps: u32,                         // Consecutive jumps of an instruction to itself
+    paused: bool,                            // Run loops and step() do nothing while set
+    pub last_instruction: Option<(u16, u8)>, // (address, opcode) of the last instruction executed
+    pub max_instructions: Option<u64>,       // Instructions each run call may execute, or unlimited
+    entry_point: Option<u16>,                // Start address used instead of the reset vector
+    speed_multiplier: f32,                   // Pacing relative to real time, infinite when uncapped
+    clock: Box<dyn Clock>,                   // Told about the cycles each step takes
+    frame_stats: Option<Vec<FrameStat>>,     // Completed frames, oldest first, while recording
+    current_frame_stat: FrameStat,           // Work done so far in the frame being drawn
+    symbols: BTreeMap<u16, String>,          // Labels printed in place of jump and branch targets
+    rom_hash: u64,                           // Fingerprint of the mounted ROM image, 0 if none
+    #[cfg(feature = "std")]
+    rom_path: Option<PathBuf>, // File the ROM was loaded from; save slots sit next to it
+    #[cfg(feature = "std")]
+    trace_log: Option<BufWriter<File>>, // Receives a trace line for every instruction
+    #[cfg(feature = "std")]
+    event_sink: Option<Sender<EmuEvent>>, // Receives every event while a debugger is attached
+    #[cfg(feature = "std")]
+    breakpoints: BTreeSet<u16>, // Addresses reported with EmuEvent::Breakpoint
+    ram_random: Option<Box<dyn RandomSource>>, // Fills work RAM at each power-on, if set
+    pub bus: Bus,                            // Routes memory accesses to RAM, I/O and the cartridge
+}
+
+// Copy of the CPU registers, for debuggers and tests
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Registers {
+    pub a: u8,      // Accumulator
+    pub x: u8,      // X index register
+    pub y: u8,      // Y index register
+    pub sp: u8,     // Stack pointer
+    pub pc: u16,    // Program counter
+    pub status: u8, // Status register (flags)
+}
+
+// CPU work done during one PPU frame, for timing overlays
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStat {
+    pub cycles: u64,       // CPU cycles, including stalls and interrupts
+    pub instructions: u64, // Instructions executed
 }
 
 // Enum to represent addressing modes
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum AddressingMode {
     Immediate,
     ZeroPage,
@@ -37,96 +146,657 @@ impl CPU {
             accumulator: 0,
             index_x: 0,
             index_y: 0,
-            status: 0b00100100, // Default status flags (interrupt disabled and unused)
+            status: UNUSED_FLAG | INTERRUPT_DISABLE_FLAG, // Default status flags
             program_counter: 0,
             stack_pointer: STACK_RESET, // Initial stack pointer value
-            memory: [0; 0xFFFF],        // Initialize memory with all zeros
+            strict_stack: false,        // Wrap silently like the hardware
+            brk_halts: true,            // Test programs end with BRK
+            reset_stack_pointer: STACK_RESET,
+            cycles: 0,
+            decimal_enabled: false,
+            program_base: 0x8000,
+            loop_detect_threshold: None, // Infinite loops run forever, as on hardware
+            self_jumps: 0,
+            paused: false,
+            last_instruction: None,
+            max_instructions: None, // Run loops may run forever, as on hardware
+            entry_point: None,
+            speed_multiplier: 1.0,
+            clock: Box::new(NullClock),
+            frame_stats: None, // Off unless asked for, to keep step() lean
+            current_frame_stat: FrameStat::default(),
+            symbols: BTreeMap::new(),
+            rom_hash: 0,
+            ram_random: None,
+            #[cfg(feature = "std")]
+            rom_path: None,
+            #[cfg(feature = "std")]
+            trace_log: None,
+            #[cfg(feature = "std")]
+            event_sink: None,
+            #[cfg(feature = "std")]
+            breakpoints: BTreeSet::new(),
+            bus: Bus::default(), // Blank cartridge for directly loaded programs
+        }
+    }
+
+    // Take a snapshot of the registers
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.accumulator,
+            x: self.index_x,
+            y: self.index_y,
+            sp: self.stack_pointer,
+            pc: self.program_counter,
+            status: self.status,
+        }
+    }
+
+    // Overwrite the registers from a snapshot
+    pub fn set_registers(&mut self, regs: Registers) {
+        self.accumulator = regs.a;
+        self.index_x = regs.x;
+        self.index_y = regs.y;
+        self.stack_pointer = regs.sp;
+        self.program_counter = regs.pc;
+        self.status = regs.status;
+    }
+
+    // Describe how another CPU differs from this one: registers, flags, cycles, then
+    // runs of differing bytes in work RAM, PRG RAM and PRG ROM (I/O is not compared)
+    pub fn diff(&self, other: &CPU) -> Vec<String> {
+        let mut differences = Vec::new();
+        let registers = [
+            ("A", self.accumulator, other.accumulator),
+            ("X", self.index_x, other.index_x),
+            ("Y", self.index_y, other.index_y),
+            ("SP", self.stack_pointer, other.stack_pointer),
+            ("P", self.status, other.status),
+        ];
+        for (name, ours, theirs) in registers {
+            if ours != theirs {
+                differences.push(format!("{}: {:02X} != {:02X}", name, ours, theirs));
+            }
+        }
+        if self.program_counter != other.program_counter {
+            differences.push(format!(
+                "PC: {:04X} != {:04X}",
+                self.program_counter, other.program_counter
+            ));
+        }
+        for (bit, name) in FLAG_NAMES {
+            let ours = self.status & bit != 0;
+            let theirs = other.status & bit != 0;
+            if ours != theirs {
+                differences.push(format!("flag {}: {} != {}", name, ours as u8, theirs as u8));
+            }
+        }
+        if self.cycles != other.cycles {
+            differences.push(format!("cycles: {} != {}", self.cycles, other.cycles));
+        }
+
+        // Mirrors of work RAM would only repeat the same differences
+        let ram_end = if self.bus.ram_mirroring {
+            0x0800
+        } else {
+            0x2000
+        };
+        for range in [0x0000..ram_end, 0x6000..0x10000] {
+            let mut run_start = None;
+            for address in range.start..=range.end {
+                let differs =
+                    address < range.end && self.peek(address as u16) != other.peek(address as u16);
+                match (differs, run_start) {
+                    (true, None) => run_start = Some(address),
+                    (false, Some(start)) => {
+                        differences.push(memory_difference(self, other, start, address));
+                        run_start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        differences
+    }
+
+    // Capture the whole console: CPU, memory, PPU, APU, controller and cartridge mapper
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        self.write_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    // Restore the console from a save_state snapshot of the same cartridge
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmuError> {
+        let mut reader = StateReader::new(data)?;
+        self.read_state(&mut reader)
+    }
+
+    // Capture only the PPU: VRAM, OAM, palette RAM, registers and scroll state, so a
+    // graphical moment can be replayed without the rest of the console
+    pub fn ppu_snapshot(&self) -> PpuSnapshot {
+        let mut writer = StateWriter::new();
+        self.bus.ppu.write_state(&mut writer);
+        PpuSnapshot(writer.into_bytes())
+    }
+
+    // Put the PPU back as it was at a ppu_snapshot
+    pub fn restore_ppu(&mut self, snap: PpuSnapshot) -> Result<(), EmuError> {
+        let mut reader = StateReader::new(&snap.0)?;
+        self.bus.ppu.read_state(&mut reader)
+    }
+
+    // File of save slot n: the ROM's path with the extension .state0 to .state9
+    #[cfg(feature = "std")]
+    fn slot_path(&self, n: u8) -> Result<PathBuf, EmuError> {
+        if n >= SAVE_SLOTS {
+            return Err(EmuError::Io(format!("no save slot {}", n)));
+        }
+        match &self.rom_path {
+            Some(path) => Ok(path.with_extension(format!("state{}", n))),
+            None => Err(EmuError::Io(
+                "save slots need a ROM loaded from a file".to_string(),
+            )),
+        }
+    }
+
+    // Write a save state to slot n, headed by the ROM hash and the time it was saved
+    #[cfg(feature = "std")]
+    pub fn save_slot(&mut self, n: u8) -> Result<(), EmuError> {
+        let path = self.slot_path(n)?;
+        let saved_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let mut writer = StateWriter::new();
+        writer.u64(self.rom_hash);
+        writer.u64(saved_at);
+        writer.bytes(&self.save_state());
+        fs::write(path, writer.into_bytes()).map_err(|e| EmuError::Io(e.to_string()))
+    }
+
+    // Restore the save state in slot n, which must have been saved from this ROM
+    #[cfg(feature = "std")]
+    pub fn load_slot(&mut self, n: u8) -> Result<(), EmuError> {
+        let data = self.read_slot(n)?;
+        let mut reader = StateReader::new(&data)?;
+        if reader.u64()? != self.rom_hash {
+            return Err(EmuError::InvalidSaveState(format!(
+                "slot {} was saved from a different ROM",
+                n
+            )));
+        }
+        reader.u64()?; // Time saved
+        self.load_state(&reader.bytes()?)
+    }
+
+    // Seconds since the Unix epoch at which slot n was saved
+    #[cfg(feature = "std")]
+    pub fn slot_saved_at(&self, n: u8) -> Result<u64, EmuError> {
+        let data = self.read_slot(n)?;
+        let mut reader = StateReader::new(&data)?;
+        reader.u64()?; // ROM hash
+        reader.u64()
+    }
+
+    #[cfg(feature = "std")]
+    fn read_slot(&self, n: u8) -> Result<Vec<u8>, EmuError> {
+        fs::read(self.slot_path(n)?).map_err(|e| EmuError::Io(e.to_string()))
+    }
+
+    // Whether the emulator is paused; the last frame and the controllers stay available
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Stop executing until resume() is called
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    // Continue executing after pause()
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    // Switch between paused and running
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // Start or stop recording per-frame timing statistics; stopping discards them
+    pub fn record_frame_stats(&mut self, enabled: bool) {
+        self.frame_stats = enabled.then(|| Vec::with_capacity(FRAME_STATS_LEN));
+        self.current_frame_stat = FrameStat::default();
+    }
+
+    // Statistics of the last FRAME_STATS_LEN completed frames, oldest first
+    // Empty unless record_frame_stats(true) was called
+    pub fn frame_stats(&self) -> &[FrameStat] {
+        self.frame_stats.as_deref().unwrap_or(&[])
+    }
+
+    // Add a step's work to the current frame, closing it off when the PPU finished one
+    fn update_frame_stats(&mut self, cycles: u64, instruction: bool, frame_before: u64) {
+        let Some(stats) = &mut self.frame_stats else {
+            return;
+        };
+        self.current_frame_stat.cycles += cycles;
+        self.current_frame_stat.instructions += instruction as u64;
+        if self.bus.frame_count != frame_before {
+            if stats.len() == FRAME_STATS_LEN {
+                stats.remove(0);
+            }
+            stats.push(self.current_frame_stat);
+            self.current_frame_stat = FrameStat::default();
+        }
+    }
+
+    // Run at a multiple of real time, e.g. 2.0 or 4.0; f32::INFINITY runs uncapped
+    // Zero, negative and NaN multipliers are ignored, keeping the current pace
+    pub fn set_speed_multiplier(&mut self, multiplier: f32) {
+        if multiplier > 0.0 {
+            self.speed_multiplier = multiplier;
+        }
+    }
+
+    // Switch between uncapped speed and real time
+    pub fn fast_forward(&mut self, enabled: bool) {
+        self.speed_multiplier = if enabled { f32::INFINITY } else { 1.0 };
+    }
+
+    // Wall-clock time a frontend should spend per frame; zero when uncapped
+    pub fn target_frame_duration(&self) -> Duration {
+        let frames_per_second = self.bus.ppu.region.frame_rate() * self.speed_multiplier as f64;
+        // A tiny multiplier asks for more time than a Duration holds
+        Duration::try_from_secs_f64(1.0 / frames_per_second).unwrap_or(Duration::MAX)
+    }
+
+    // Whether a frontend should drop audio rather than queue it, to avoid
+    // overrunning its buffer while running faster than real time
+    pub fn drop_audio(&self) -> bool {
+        self.speed_multiplier > 1.0
+    }
+
+    // Fill work RAM from source now and again each time load_rom powers on a cartridge;
+    // None leaves the RAM of later cartridges zeroed
+    pub fn set_ram_random(&mut self, source: Option<Box<dyn RandomSource>>) {
+        self.ram_random = source;
+        if let Some(source) = &mut self.ram_random {
+            self.bus.fill_ram(source.as_mut());
         }
     }
 
-    // Helper function to read from memory
-    fn mem_read(&self, address: u16) -> u8 {
-        self.memory[address as usize]
+    // Install the clock that step() reports elapsed cycles to
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // Read memory for a debugger without disturbing any I/O register, see Bus::peek
+    pub fn peek(&self, address: u16) -> u8 {
+        self.bus.peek(address)
     }
 
-    // Helper function to write to memory
+    // Helper function to read from memory through the bus
+    fn mem_read(&mut self, address: u16) -> u8 {
+        self.bus.mem_read(address)
+    }
+
+    // Helper function to write to memory through the bus
     fn mem_write(&mut self, address: u16, data: u8) {
-        self.memory[address as usize] = data;
+        self.emit(EmuEvent::MemoryWrite {
+            address,
+            value: data,
+        });
+        self.bus.mem_write(address, data);
     }
 
-    // Helper function to read a 16-bit value from memory
-    fn mem_read_u16(&self, address: u16) -> u16 {
-        let byte_one = self.mem_read(address) as u16;
-        let byte_two = self.mem_read(address + 1) as u16;
-        (byte_two as u16) << 8 | (byte_one as u16)
+    // Stream events to a debugger, or stop with None
+    // A sink whose receiver has hung up is kept, its events are dropped
+    #[cfg(feature = "std")]
+    pub fn set_event_sink(&mut self, sink: Option<Sender<EmuEvent>>) {
+        self.event_sink = sink;
     }
 
-    // Helper function to write a 16-bit value to memory
-    fn mem_write_u16(&mut self, address: u16, data: u16) {
-        let byte_one = (data & 0xff) as u8;
-        let byte_two = (data >> 8) as u8;
-        self.mem_write(address, byte_one);
-        self.mem_write(address + 1, byte_two);
+    // Report an EmuEvent::Breakpoint each time the instruction at an address is about to
+    // run; execution carries on, the debugger decides whether to pause()
+    #[cfg(feature = "std")]
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
     }
 
-    // Helper function to calculate the operand address based on addressing mode
-    fn address_operand(&self, mode: &AddressingMode) -> u16 {
-        match mode {
+    // Stop reporting an address added with add_breakpoint
+    #[cfg(feature = "std")]
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    // Send an event to the attached sink, if there is one
+    #[cfg(feature = "std")]
+    fn emit(&self, event: EmuEvent) {
+        if let Some(sink) = &self.event_sink {
+            let _ = sink.send(event);
+        }
+    }
+
+    // Without std there is nowhere to send events
+    #[cfg(not(feature = "std"))]
+    fn emit(&self, _event: EmuEvent) {}
+
+    // Push a byte onto the stack in page 0x01
+    fn stack_push(&mut self, data: u8) -> Result<(), EmuError> {
+        if self.strict_stack && self.stack_pointer == 0x00 {
+            return Err(EmuError::StackOverflow);
+        }
+        self.mem_write(STACK_START + self.stack_pointer as u16, data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        Ok(())
+    }
+
+    // Pop a byte off the stack in page 0x01
+    fn stack_pop(&mut self) -> Result<u8, EmuError> {
+        if self.strict_stack && self.stack_pointer == 0xFF {
+            return Err(EmuError::StackUnderflow);
+        }
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        Ok(self.mem_read(STACK_START + self.stack_pointer as u16))
+    }
+
+    // Helper function to read a 16-bit value from memory
+    // The high byte wraps from 0xFFFF around to 0x0000
+    fn mem_read_u16(&mut self, address: u16) -> u16 {
+        let byte_one = self.mem_read(address) as u16;
+        let byte_two = self.mem_read(address.wrapping_add(1)) as u16;
+        (byte_two << 8) | byte_one
+    }
+
+    // Helper function to calculate the operand address based on addressing mode,
+    // and whether indexing crossed into another page (which costs read instructions a cycle)
+    // All address arithmetic wraps the way the 6502 does, so no mode can overflow
+    fn address_operand(&mut self, mode: &AddressingMode) -> (u16, bool) {
+        let address = match mode {
+            // The operand is the byte after the opcode
             AddressingMode::Immediate => self.program_counter,
+            // One-byte address in page zero
             AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+            // Two-byte address; an operand at 0xFFFF takes its high byte from 0x0000
             AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+            // Indexing stays inside page zero: 0xFF + X wraps to 0x00 + (X - 1)
             AddressingMode::ZeroPageX => {
                 let offset = self.mem_read(self.program_counter);
-                let address = offset.wrapping_add(self.index_x) as u16;
-                address
+                offset.wrapping_add(self.index_x) as u16
             }
             AddressingMode::ZeroPageY => {
                 let offset = self.mem_read(self.program_counter);
-                let address = offset.wrapping_add(self.index_y) as u16;
-                address
+                offset.wrapping_add(self.index_y) as u16
             }
+            // Indexing may cross pages and wraps from 0xFFFF to 0x0000
             AddressingMode::AbsoluteX => {
                 let base = self.mem_read_u16(self.program_counter);
                 let address = base.wrapping_add(self.index_x as u16);
-                address
+                return (address, page_differs(base, address));
             }
             AddressingMode::AbsoluteY => {
                 let base = self.mem_read_u16(self.program_counter);
                 let address = base.wrapping_add(self.index_y as u16);
-                address
+                return (address, page_differs(base, address));
             }
+            // The pointer is indexed within page zero, and its high byte at
+            // 0xFF is fetched from 0x00
             AddressingMode::IndirectX => {
                 let base = self.mem_read(self.program_counter);
-                let offset: u8 = (base as u8).wrapping_add(self.index_x);
+                let offset: u8 = base.wrapping_add(self.index_x);
                 let byte_one = self.mem_read(offset as u16);
                 let byte_two = self.mem_read(offset.wrapping_add(1) as u16);
                 (byte_two as u16) << 8 | (byte_one as u16)
             }
+            // The pointer fetch wraps within page zero, then Y is added across
+            // the full 16-bit space; only that addition can cross a page
             AddressingMode::IndirectY => {
                 let base = self.mem_read(self.program_counter);
                 let byte_one = self.mem_read(base as u16);
-                let byte_two = self.mem_read((base as u8).wrapping_add(1) as u16);
+                let byte_two = self.mem_read(base.wrapping_add(1) as u16);
                 let deref_base = (byte_two as u16) << 8 | (byte_one as u16);
                 let deref = deref_base.wrapping_add(self.index_y as u16);
-                deref
+                return (deref, page_differs(deref_base, deref));
             }
             AddressingMode::NoneAddressing => {
                 panic!("mode {:?} is not supported", mode);
             }
+        };
+        (address, false)
+    }
+
+    // Helper function to fetch the operand of a read instruction
+    // When indexing crosses a page the 6502 first reads from the address whose high byte
+    // has not been fixed up yet, and I/O registers see that dummy read too
+    fn read_operand(&mut self, mode: &AddressingMode) -> (u8, bool) {
+        let (address, page_crossed) = self.address_operand(mode);
+        let index = match mode {
+            AddressingMode::AbsoluteX => self.index_x,
+            AddressingMode::AbsoluteY | AddressingMode::IndirectY => self.index_y,
+            _ => 0,
+        };
+        let base = address.wrapping_sub(index as u16);
+        if page_differs(base, address) {
+            self.mem_read((base & 0xFF00) | (address & 0x00FF));
+        }
+        (self.mem_read(address), page_crossed)
+    }
+
+    // Write a byte straight into memory for test setup or cheats, see Bus::poke
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.bus.poke(address, value);
+    }
+
+    // Write consecutive bytes straight into memory starting at an address
+    pub fn poke_range(&mut self, address: u16, bytes: &[u8]) {
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.bus.poke(address.wrapping_add(offset as u16), *byte);
+        }
+    }
+
+    // Disassemble the instruction at an address, returning its text and length in bytes
+    // Unknown opcodes come back as a one-byte ".byte" directive
+    pub fn disassemble_one(&self, address: u16) -> (String, u8) {
+        let code = self.bus.peek(address);
+        let op = match opcodes::find(code) {
+            Some(op) => op,
+            None => return (format!(".byte ${:02X}", code), 1),
+        };
+        let low = self.bus.peek(address.wrapping_add(1));
+        let high = self.bus.peek(address.wrapping_add(2));
+        let word = u16::from_le_bytes([low, high]);
+
+        let operand = match op.mode {
+            AddressingMode::Immediate => format!("#${:02X}", low),
+            AddressingMode::ZeroPage => format!("${:02X}", low),
+            AddressingMode::ZeroPageX => format!("${:02X},X", low),
+            AddressingMode::ZeroPageY => format!("${:02X},Y", low),
+            AddressingMode::Absolute if op.mnemonic == "JMP" || op.mnemonic == "JSR" => {
+                self.target_name(word)
+            }
+            AddressingMode::Absolute => format!("${:04X}", word),
+            AddressingMode::AbsoluteX => format!("${:04X},X", word),
+            AddressingMode::AbsoluteY => format!("${:04X},Y", word),
+            AddressingMode::IndirectX => format!("(${:02X},X)", low),
+            AddressingMode::IndirectY => format!("(${:02X}),Y", low),
+            // Branches show the address they jump to
+            AddressingMode::NoneAddressing if op.len == 2 => {
+                let next = address.wrapping_add(2);
+                self.target_name(next.wrapping_add(low as i8 as u16))
+            }
+            AddressingMode::NoneAddressing => String::new(),
+        };
+
+        let text = if operand.is_empty() {
+            op.mnemonic.to_string()
+        } else {
+            format!("{} {}", op.mnemonic, operand)
+        };
+        (text, op.len)
+    }
+
+    // Listing of the instructions from start up to and including end, one line each
+    // with the address, the raw bytes and the disassembly; unknown opcodes are .byte lines
+    pub fn disassemble(&self, start: u16, end: u16) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut address = start as u32;
+        while address <= end as u32 {
+            let (text, len) = self.disassemble_one(address as u16);
+            lines.push(format!(
+                "{:04X}  {:<8}  {}",
+                address,
+                self.instruction_bytes(address as u16, len),
+                text
+            ));
+            address += len as u32;
+        }
+        lines
+    }
+
+    // Write the disassemble listing of an address range to a text file
+    #[cfg(feature = "std")]
+    pub fn export_disassembly(&self, start: u16, end: u16, path: &Path) -> Result<(), EmuError> {
+        let mut listing = self.disassemble(start, end).join("\n");
+        listing.push('\n');
+        fs::write(path, listing).map_err(|e| EmuError::Io(e.to_string()))
+    }
+
+    // Bytes of the instruction at an address as space-separated hex
+    fn instruction_bytes(&self, address: u16, len: u8) -> String {
+        let bytes: Vec<String> = (0..len as u16)
+            .map(|offset| format!("{:02X}", self.bus.peek(address.wrapping_add(offset))))
+            .collect();
+        bytes.join(" ")
+    }
+
+    // Label of a jump or branch target from load_with_symbols, or its address
+    fn target_name(&self, address: u16) -> String {
+        match self.symbols.get(&address) {
+            Some(label) => label.clone(),
+            None => format!("${:04X}", address),
+        }
+    }
+
+    // nestest-style log line for the instruction about to execute: address, bytes,
+    // disassembly, registers, PPU position and cycle count
+    pub fn trace_line(&self) -> String {
+        let pc = self.program_counter;
+        let (text, len) = self.disassemble_one(pc);
+        format!(
+            "{:04X}  {:<8}  {:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            pc,
+            self.instruction_bytes(pc, len),
+            text,
+            self.accumulator,
+            self.index_x,
+            self.index_y,
+            self.status,
+            self.stack_pointer,
+            self.bus.ppu.scanline,
+            self.bus.ppu.cycles,
+            self.cycles
+        )
+    }
+
+    // Step through a hand-written expected trace, comparing trace_line() before each
+    // instruction for at most max_steps lines
+    // The first mismatch comes back as (line index, expected line, actual line); a
+    // program that stops early shows an empty line, and an error its message
+    pub fn run_and_compare(
+        &mut self,
+        expected_trace: &[&str],
+        max_steps: usize,
+    ) -> Result<(), (usize, String, String)> {
+        for (index, expected) in expected_trace.iter().take(max_steps).enumerate() {
+            let actual = self.trace_line();
+            if actual != *expected {
+                return Err((index, expected.to_string(), actual));
+            }
+            let last = index + 1 == expected_trace.len().min(max_steps);
+            match self.step() {
+                Ok(true) => {}
+                Ok(false) if last => {}
+                Ok(false) => {
+                    let next = expected_trace[index + 1].to_string();
+                    return Err((index + 1, next, String::new()));
+                }
+                Err(error) => return Err((index, expected.to_string(), error.to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    // Write a trace line for every instruction executed to a file, until
+    // disable_trace_log(); output is buffered
+    #[cfg(feature = "std")]
+    pub fn enable_trace_log(&mut self, path: &Path) -> Result<(), EmuError> {
+        let file = File::create(path).map_err(|e| EmuError::Io(e.to_string()))?;
+        self.trace_log = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    // Stop tracing, flushing what is still buffered
+    // A CPU dropped while tracing flushes its log too, but cannot report errors
+    #[cfg(feature = "std")]
+    pub fn disable_trace_log(&mut self) -> Result<(), EmuError> {
+        match self.trace_log.take() {
+            Some(mut log) => log.flush().map_err(|e| EmuError::Io(e.to_string())),
+            None => Ok(()),
         }
     }
 
-    // Load instructions into memory starting at address 0x8000
+    // Load instructions into memory starting at program_base (0x8000 by default)
     pub fn load(&mut self, instructions: Vec<u8>) {
-        self.memory[0x8000..(0x8000 + instructions.len())].copy_from_slice(&instructions[..]);
-        self.mem_write_u16(0xFFFC, 0x8000); // Set the reset vector
+        // Written like poke_range, so loading reaches no I/O registers and is not traced
+        let base = self.program_base;
+        self.poke_range(base, &instructions);
+        self.bus.load_prg(0xFFFC, &base.to_le_bytes()); // Set the reset vector
+    }
+
+    // Load a program assembled elsewhere at base, along with labels for its
+    // addresses that the disassembler and trace log print instead of the numbers
+    pub fn load_with_symbols(&mut self, bytes: Vec<u8>, base: u16, symbols: BTreeMap<u16, String>) {
+        self.program_base = base;
+        self.load(bytes);
+        self.symbols = symbols;
+    }
+
+    // Read an iNES file, mount it on a new bus and reset to its reset vector
+    #[cfg(feature = "std")]
+    pub fn load_rom_file(&mut self, path: &Path) -> Result<(), EmuError> {
+        let raw = fs::read(path).map_err(|e| EmuError::Io(e.to_string()))?;
+        self.load_rom(&raw)?;
+        self.rom_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    // Mount an iNES image already in memory on a new bus and reset to its reset vector
+    // Works without a filesystem: for ROMs embedded with include_bytes! or fetched by a page
+    // The new bus keeps the old one's setup (see Bus::take_setup_from) and RAM is filled
+    // from the source given to set_ram_random
+    pub fn load_rom(&mut self, raw: &[u8]) -> Result<(), EmuError> {
+        let rom = Rom::new(raw).map_err(EmuError::InvalidRom)?;
+        let mut bus = Bus::with_custom_mappers(rom, self.bus.custom_mappers().clone())?;
+        bus.take_setup_from(&mut self.bus);
+        self.bus = bus;
+        if let Some(source) = &mut self.ram_random {
+            self.bus.fill_ram(source.as_mut());
+        }
+        self.rom_hash = savestate::rom_hash(raw);
+        #[cfg(feature = "std")]
+        {
+            self.rom_path = None;
+        }
+        self.reset();
+        Ok(())
     }
 
     // Load instructions into memory and interpret them
-    pub fn load_and_interpret(&mut self, instructions: Vec<u8>) {
+    pub fn load_and_interpret(&mut self, instructions: Vec<u8>) -> Result<(), EmuError> {
         self.load(instructions);
         self.reset(); // Initialize CPU state
-        self.interpret(); // Start interpretation
+        self.interpret() // Start interpretation
     }
 
     // Reset the CPU to its initial state
@@ -134,94 +804,2460 @@ impl CPU {
         self.accumulator = 0;
         self.index_x = 0;
         self.index_y = 0;
-        self.stack_pointer = STACK_RESET;
-        self.status = 0b00100100;
-        self.program_counter = self.mem_read_u16(0xFFFC); // Set program counter to reset vector
+        self.stack_pointer = self.reset_stack_pointer;
+        // Reset disables interrupts and turns decimal mode off: the 2A03 ignores the
+        // decimal flag, but a general 6502 (decimal_enabled) must come out of reset in
+        // binary. Every other flag starts clear
+        self.status = (UNUSED_FLAG | INTERRUPT_DISABLE_FLAG) & !DECIMAL_FLAG;
+        self.program_counter = self.start_address();
+        let cycles_before = self.cycles;
+        self.cycles += INTERRUPT_CYCLES;
+        self.catch_up(cycles_before);
+    }
+
+    // Start at a fixed address after reset() instead of the reset vector,
+    // which stays untouched in memory (nestest's automation mode starts at 0xC000)
+    pub fn set_entry_point(&mut self, pc: u16) {
+        self.entry_point = Some(pc);
+        self.program_counter = pc;
+    }
+
+    // Go back to starting from the reset vector
+    pub fn clear_entry_point(&mut self) {
+        self.entry_point = None;
+    }
+
+    // Address execution starts from: the entry point if set, otherwise the reset vector
+    fn start_address(&mut self) -> u16 {
+        match self.entry_point {
+            Some(pc) => pc,
+            None => self.mem_read_u16(0xFFFC),
+        }
     }
 
     // Implement the LDA instruction
     fn lda(&mut self, mode: &AddressingMode) {
-        let address = self.address_operand(&mode);
-        let value = self.mem_read(address);
+        let (value, page_crossed) = self.read_operand(mode);
+        if page_crossed {
+            self.cycles += 1;
+        }
         self.accumulator = value;
+        self.update_flags(self.accumulator);
     }
 
-    // Update CPU status flags
-    fn update_flags(&mut self, to_check: u8) {
-        if to_check == 0 {
-            self.status = self.status | 0b00000010; // Set zero flag
+    // Implement the STA instruction
+    fn sta(&mut self, mode: &AddressingMode) {
+        let (address, _) = self.address_operand(mode);
+        self.mem_write(address, self.accumulator);
+    }
+
+    // Implement the JMP instruction with absolute addressing
+    fn jmp_absolute(&mut self, opcode_address: u16) -> Result<(), EmuError> {
+        let target = self.mem_read_u16(self.program_counter);
+        self.program_counter = target;
+
+        // A jump to itself can only be left through an interrupt
+        if target == opcode_address {
+            self.self_jumps += 1;
         } else {
-            self.status = self.status & 0b11111101; // Clear zero flag
+            self.self_jumps = 0;
+        }
+        match self.loop_detect_threshold {
+            Some(threshold) if self.self_jumps >= threshold => Err(EmuError::Halted),
+            _ => Ok(()),
         }
+    }
 
-        if to_check & 0b10000000 == 0b10000000 {
-            self.status = self.status | 0b10000000; // Set negative flag
+    // Implement the PHA instruction
+    fn pha(&mut self) -> Result<(), EmuError> {
+        self.stack_push(self.accumulator)
+    }
+
+    // Relative branch: 2 cycles when not taken, 3 when taken, 4 when taken onto another page
+    fn branch(&mut self, condition: bool) {
+        let offset = self.mem_read(self.program_counter) as i8;
+        self.program_counter = self.program_counter.wrapping_add(1);
+        if condition {
+            let target = self.program_counter.wrapping_add(offset as u16);
+            self.cycles += 1;
+            if page_differs(self.program_counter, target) {
+                self.cycles += 1;
+            }
+            self.program_counter = target;
+        }
+    }
+
+    // Implement the PLA instruction
+    fn pla(&mut self) -> Result<(), EmuError> {
+        self.accumulator = self.stack_pop()?;
+        self.update_flags(self.accumulator);
+        Ok(())
+    }
+
+    // Set or clear the carry flag
+    fn set_carry(&mut self, carry: bool) {
+        if carry {
+            self.status |= CARRY_FLAG;
         } else {
-            self.status = self.status & 0b01111111; // Clear negative flag
+            self.status &= !CARRY_FLAG;
+        }
+    }
+
+    // Implement the ADC instruction: A = A + operand + carry
+    fn adc(&mut self, mode: &AddressingMode) {
+        let (value, page_crossed) = self.read_operand(mode);
+        if page_crossed {
+            self.cycles += 1;
+        }
+        self.add_to_accumulator(value, false);
+    }
+
+    // Implement the SBC instruction: A = A - operand - (1 - carry)
+    fn sbc(&mut self, mode: &AddressingMode) {
+        let (value, page_crossed) = self.read_operand(mode);
+        if page_crossed {
+            self.cycles += 1;
         }
+        self.add_to_accumulator(value, true);
     }
 
-    // Main interpreter loop
-    pub fn interpret(&mut self) {
-        self.program_counter = self.mem_read_u16(0xFFFC); // Set program counter to reset vector
+    // Shared ADC/SBC adder; subtraction adds the operand's complement
+    // The 2A03 has no decimal adder, so SED and CLD still move the D flag but the sum
+    // is binary unless decimal_enabled is set for a general 6502. The flags always
+    // follow the binary sum, as the NMOS 6502 reports N, V and Z in decimal mode
+    fn add_to_accumulator(&mut self, value: u8, subtract: bool) {
+        let carry_in = self.status & CARRY_FLAG;
+        let operand = if subtract { !value } else { value };
+        let sum = self.accumulator as u16 + operand as u16 + carry_in as u16;
+        let binary = sum as u8;
 
-        loop {
-            let opcode = self.memory[self.program_counter as usize];
-            self.program_counter += 1;
+        let overflow = (self.accumulator ^ binary) & (operand ^ binary) & 0b1000_0000 != 0;
+        if overflow {
+            self.status |= 0b0100_0000;
+        } else {
+            self.status &= !0b0100_0000;
+        }
+        self.update_flags(binary);
 
-            match opcode {
-                0xa9 => {
-                    self.lda(&AddressingMode::Immediate);
-                    self.program_counter += 1;
-                }
-                0xa5 => {
-                    self.lda(&AddressingMode::ZeroPage);
-                    self.program_counter += 1;
-                }
-                0xb5 => {
-                    self.lda(&AddressingMode::ZeroPageX);
-                    self.program_counter += 1;
-                }
-                0xad => {
-                    self.lda(&AddressingMode::Absolute);
-                    self.program_counter += 2;
-                }
-                0xbd => {
-                    self.lda(&AddressingMode::AbsoluteX);
-                    self.program_counter += 2;
-                }
-                0xb9 => {
-                    self.lda(&AddressingMode::AbsoluteY);
-                    self.program_counter += 2;
-                }
-                0xa1 => {
-                    self.lda(&AddressingMode::IndirectX);
-                    self.program_counter += 1;
-                }
-                0xb1 => {
-                    self.lda(&AddressingMode::IndirectY);
-                    self.program_counter += 1;
-                }
-                0x00 => return, // Exit the interpreter loop
+        if !(self.decimal_enabled && self.status & DECIMAL_FLAG != 0) {
+            self.set_carry(sum > 0xff);
+            self.accumulator = binary;
+            return;
+        }
+
+        // Adjust each nibble that left the 0-9 range
+        let a = self.accumulator as i16;
+        let v = value as i16;
+        let c = carry_in as i16;
+        if subtract {
+            let mut low = (a & 0x0f) - (v & 0x0f) - (1 - c);
+            if low < 0 {
+                low = ((low - 0x06) & 0x0f) - 0x10;
+            }
+            let mut result = (a & 0xf0) - (v & 0xf0) + low;
+            if result < 0 {
+                result -= 0x60;
+            }
+            self.set_carry(sum > 0xff);
+            self.accumulator = result as u8;
+        } else {
+            let mut low = (a & 0x0f) + (v & 0x0f) + c;
+            if low > 0x09 {
+                low = ((low + 0x06) & 0x0f) + 0x10;
+            }
+            let mut result = (a & 0xf0) + (v & 0xf0) + low;
+            if result > 0x9f {
+                result += 0x60;
+            }
+            self.set_carry(result > 0xff);
+            self.accumulator = result as u8;
+        }
+    }
+
+    // Implement CMP, CPX and CPY: subtract the operand from a register without storing
+    // the result, setting carry when register >= operand
+    // Unlike SBC the overflow flag is left alone
+    fn compare(&mut self, mode: &AddressingMode, register: u8) {
+        let (value, page_crossed) = self.read_operand(mode);
+        if page_crossed {
+            self.cycles += 1;
+        }
+        if register >= value {
+            self.status |= CARRY_FLAG;
+        } else {
+            self.status &= !CARRY_FLAG;
+        }
+        self.update_flags(register.wrapping_sub(value));
+    }
+
+    // Read-modify-write an operand in memory: read it, write it back unmodified as the
+    // 6502 does, then write op's result, which is returned for the flag updates
+    // Zero-page operands, common in hot loops, take a shorter path with the same effects
+    fn modify_memory<F: Fn(&mut CPU, u8) -> u8>(&mut self, mode: &AddressingMode, op: F) -> u8 {
+        match mode {
+            AddressingMode::ZeroPage => self.modify_zero_page(0, op),
+            AddressingMode::ZeroPageX => self.modify_zero_page(self.index_x, op),
+            _ => self.modify_memory_generic(mode, op),
+        }
+    }
+
+    // Read-modify-write at the operand address decoded by address_operand
+    fn modify_memory_generic<F: Fn(&mut CPU, u8) -> u8>(
+        &mut self,
+        mode: &AddressingMode,
+        op: F,
+    ) -> u8 {
+        let (address, _) = self.address_operand(mode);
+        let value = self.mem_read(address);
+        self.mem_write(address, value); // Dummy write of the unmodified value
+        let result = op(self, value);
+        self.mem_write(address, result);
+        result
+    }
+
+    // Read-modify-write a zero-page operand indexed by index, which wraps within page
+    // zero; the RAM is reached directly instead of through the bus's address decoding
+    fn modify_zero_page<F: Fn(&mut CPU, u8) -> u8>(&mut self, index: u8, op: F) -> u8 {
+        let address = self.mem_read(self.program_counter).wrapping_add(index);
+        let value = self.bus.read_zero_page(address);
+        self.zero_page_write(address, value); // Dummy write of the unmodified value
+        let result = op(self, value);
+        self.zero_page_write(address, result);
+        result
+    }
+
+    // Helper function to write to page zero, reporting the write like mem_write
+    fn zero_page_write(&mut self, address: u8, data: u8) {
+        self.emit(EmuEvent::MemoryWrite {
+            address: address as u16,
+            value: data,
+        });
+        self.bus.write_zero_page(address, data);
+    }
+
+    // Implement the INC instruction
+    fn inc(&mut self, mode: &AddressingMode) {
+        let result = self.modify_memory(mode, |_, value| value.wrapping_add(1));
+        self.update_flags(result);
+    }
+
+    // Implement the DEC instruction
+    fn dec(&mut self, mode: &AddressingMode) {
+        let result = self.modify_memory(mode, |_, value| value.wrapping_sub(1));
+        self.update_flags(result);
+    }
+
+    // Implement INX and DEX, which only ever change the X register
+    fn inx(&mut self) {
+        self.index_x = self.index_x.wrapping_add(1);
+        self.update_flags(self.index_x);
+    }
+
+    fn dex(&mut self) {
+        self.index_x = self.index_x.wrapping_sub(1);
+        self.update_flags(self.index_x);
+    }
+
+    // Implement INY and DEY, which only ever change the Y register
+    fn iny(&mut self) {
+        self.index_y = self.index_y.wrapping_add(1);
+        self.update_flags(self.index_y);
+    }
+
+    fn dey(&mut self) {
+        self.index_y = self.index_y.wrapping_sub(1);
+        self.update_flags(self.index_y);
+    }
 
-                _ => todo!("write more functions for opcodes"),
+    // Apply a shift or rotate to the accumulator (NoneAddressing) or to memory
+    // Carry is set from the bit shifted out before Z and N follow the result
+    fn shift(&mut self, mode: &AddressingMode, op: fn(&mut CPU, u8) -> u8) {
+        let result = match mode {
+            AddressingMode::NoneAddressing => {
+                self.accumulator = op(self, self.accumulator);
+                self.accumulator
             }
+            _ => self.modify_memory(mode, op),
+        };
+        self.update_flags(result);
+    }
+
+    // Implement the ASL instruction
+    fn asl(&mut self, mode: &AddressingMode) {
+        self.shift(mode, |cpu, value| {
+            cpu.set_carry(value & 0b1000_0000 != 0);
+            value << 1
+        });
+    }
+
+    // Implement the LSR instruction
+    fn lsr(&mut self, mode: &AddressingMode) {
+        self.shift(mode, |cpu, value| {
+            cpu.set_carry(value & 0b0000_0001 != 0);
+            value >> 1
+        });
+    }
+
+    // Implement the ROL instruction
+    fn rol(&mut self, mode: &AddressingMode) {
+        self.shift(mode, |cpu, value| {
+            let carry_in = cpu.status & CARRY_FLAG;
+            cpu.set_carry(value & 0b1000_0000 != 0);
+            (value << 1) | carry_in
+        });
+    }
+
+    // Implement the ROR instruction
+    fn ror(&mut self, mode: &AddressingMode) {
+        self.shift(mode, |cpu, value| {
+            let carry_in = (cpu.status & CARRY_FLAG) << 7;
+            cpu.set_carry(value & 0b0000_0001 != 0);
+            (value >> 1) | carry_in
+        });
+    }
+
+    // Implement the unofficial SKB/SKW NOPs, which read their operand and discard it
+    fn nop_read(&mut self, mode: &AddressingMode) {
+        let (_, page_crossed) = self.read_operand(mode); // The read still reaches I/O registers
+        if page_crossed {
+            self.cycles += 1;
         }
     }
-}
 
-// Unit test module
-#[cfg(test)]
-mod test {
-    use super::*;
+    // Implement the unofficial ANC: AND, then copy bit 7 of the result into carry
+    fn anc(&mut self) {
+        self.accumulator &= self.mem_read(self.program_counter);
+        self.update_flags(self.accumulator);
+        self.set_carry(self.accumulator & 0b1000_0000 != 0);
+    }
 
-    // Test case for the LDA (Load Accumulator) instruction with immediate addressing
-    #[test]
-    fn test_0xa9_lda_immediate_load_data() {
-        let mut cpu = CPU::new();
-        cpu.load_and_interpret(vec![0xa9, 0x05, 0x00]); // Load LDA instruction with value 0x05
-        assert_eq!(cpu.accumulator, 5); // Check if accumulator is loaded correctly
-        assert!(cpu.status & 0b0000_0010 == 0b00); // Check if zero flag is not set
-        assert!(cpu.status & 0b1000_0000 == 0); // Check if negative flag is not set
+    // Implement the unofficial ALR: AND, then shift the accumulator right
+    fn alr(&mut self) {
+        let value = self.accumulator & self.mem_read(self.program_counter);
+        self.set_carry(value & 1 != 0);
+        self.accumulator = value >> 1;
+        self.update_flags(self.accumulator);
+    }
+
+    // Implement the unofficial ARR: AND, then rotate right through carry
+    // Carry comes from bit 6 of the result and overflow from bit 6 XOR bit 5
+    fn arr(&mut self) {
+        let value = self.accumulator & self.mem_read(self.program_counter);
+        self.accumulator = (value >> 1) | ((self.status & CARRY_FLAG) << 7);
+        self.update_flags(self.accumulator);
+        let bit6 = self.accumulator & 0b0100_0000 != 0;
+        let bit5 = self.accumulator & 0b0010_0000 != 0;
+        self.set_carry(bit6);
+        if bit6 != bit5 {
+            self.status |= 0b0100_0000;
+        } else {
+            self.status &= !0b0100_0000;
+        }
+    }
+
+    // Implement the unofficial AXS: X = (A AND X) - operand, setting carry like CMP
+    // Neither the decimal flag nor overflow takes part
+    fn axs(&mut self) {
+        let value = self.mem_read(self.program_counter);
+        let masked = self.accumulator & self.index_x;
+        self.set_carry(masked >= value);
+        self.index_x = masked.wrapping_sub(value);
+        self.update_flags(self.index_x);
+    }
+
+    // Update CPU status flags
+    fn update_flags(&mut self, to_check: u8) {
+        if to_check == 0 {
+            self.status |= 0b00000010; // Set zero flag
+        } else {
+            self.status &= 0b11111101; // Clear zero flag
+        }
+
+        if to_check & 0b10000000 == 0b10000000 {
+            self.status |= 0b10000000; // Set negative flag
+        } else {
+            self.status &= 0b01111111; // Clear negative flag
+        }
+    }
+
+    // Push the return address and status, then jump through an interrupt vector:
+    // 0xFFFA for NMI, 0xFFFE for IRQ
+    fn interrupt(&mut self, vector: u16) -> Result<(), EmuError> {
+        let [low, high] = self.program_counter.to_le_bytes();
+        self.stack_push(high)?;
+        self.stack_push(low)?;
+        // The pushed status has B clear and bit 5 set
+        self.stack_push((self.status & !0b0001_0000) | 0b0010_0000)?;
+        self.status |= 0b0000_0100; // Disable further IRQs
+        self.program_counter = self.mem_read_u16(vector);
+        self.cycles += INTERRUPT_CYCLES;
+        Ok(())
+    }
+
+    // BRK taken as a software interrupt: the byte after the opcode is skipped, and the
+    // return address and the status with B set are pushed before the vector is fetched
+    // in the last two cycles. An NMI raised before then hijacks the BRK, which vectors
+    // through 0xFFFA while the pushed B flag still shows a BRK
+    fn brk(&mut self, cycles_before: u64) -> Result<(), EmuError> {
+        let [low, high] = self.program_counter.wrapping_add(1).to_le_bytes();
+        self.stack_push(high)?;
+        self.stack_push(low)?;
+        self.stack_push(self.status | BREAK_FLAG | UNUSED_FLAG)?;
+        self.status |= INTERRUPT_DISABLE_FLAG;
+
+        // Run the bus up to the vector fetch, so an NMI raised meanwhile is seen
+        self.cycles += INTERRUPT_CYCLES - 2;
+        self.catch_up(cycles_before);
+        let vector = if self.bus.poll_nmi() {
+            self.emit(EmuEvent::NmiFired);
+            0xFFFA
+        } else {
+            0xFFFE
+        };
+        self.program_counter = self.mem_read_u16(vector);
+        let vector_fetch = self.cycles;
+        self.cycles += 2;
+        self.catch_up(vector_fetch);
+        Ok(())
+    }
+
+    // Force an NMI, taken before the next instruction
+    pub fn request_nmi(&mut self) {
+        self.bus.request_nmi();
+    }
+
+    // Force an IRQ, taken before the next instruction once interrupts are enabled
+    pub fn request_irq(&mut self) {
+        self.bus.request_irq();
+    }
+
+    // Execute a single instruction, returning false once BRK is reached
+    // While paused nothing is executed and false is returned, so run loops return at once
+    pub fn step(&mut self) -> Result<bool, EmuError> {
+        if self.paused {
+            return Ok(false);
+        }
+        // NMI is taken between instructions. The PPU catches up after each instruction,
+        // so a PPUCTRL write in the instruction during which VBlank begins lands first;
+        // clearing NMI-enable there suppresses the NMI as on hardware
+        // An IRQ is only taken while the interrupt-disable flag is clear
+        let frame_before = self.bus.frame_count;
+        let vector = if self.bus.poll_nmi() {
+            Some(0xFFFA)
+        } else if self.status & INTERRUPT_DISABLE_FLAG == 0 && self.bus.poll_irq() {
+            Some(0xFFFE)
+        } else {
+            None
+        };
+        if let Some(vector) = vector {
+            let cycles_before = self.cycles;
+            log::trace!(
+                "interrupt through ${:04X} at ${:04X}",
+                vector,
+                self.program_counter
+            );
+            self.emit(if vector == 0xFFFA {
+                EmuEvent::NmiFired
+            } else {
+                EmuEvent::IrqFired
+            });
+            self.interrupt(vector)?;
+            self.catch_up(cycles_before);
+            self.finish_step(self.cycles - cycles_before, false, frame_before);
+            return Ok(true);
+        }
+        #[cfg(feature = "std")]
+        if self.breakpoints.contains(&self.program_counter) {
+            self.emit(EmuEvent::Breakpoint {
+                address: self.program_counter,
+            });
+        }
+        #[cfg(feature = "std")]
+        if self.trace_log.is_some() {
+            let line = self.trace_line();
+            if let Some(log) = &mut self.trace_log {
+                writeln!(log, "{}", line).map_err(|e| EmuError::Io(e.to_string()))?;
+            }
+        }
+        let cycles_before = self.cycles;
+        let opcode_address = self.program_counter;
+        let opcode = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        if opcode == 0x00 && !self.brk_halts {
+            self.brk(cycles_before)?;
+            self.last_instruction = Some((opcode_address, opcode));
+            self.finish_step(self.cycles - cycles_before, true, frame_before);
+            return Ok(true);
+        }
+        let op = opcodes::find(opcode);
+        let base_cycles = op.map_or(0, |op| op.cycles);
+        // Single-byte instructions still read the byte after the opcode on their second
+        // cycle and ignore it, without stepping past it
+        if opcode != 0x00 && op.is_some_and(|op| op.len == 1) {
+            self.mem_read(self.program_counter);
+        }
+        let mut running = true;
+        let mut advance_pc = true; // Cleared by instructions that set the PC themselves
+
+        match opcode {
+            0xa9 => self.lda(&AddressingMode::Immediate),
+            0xa5 => self.lda(&AddressingMode::ZeroPage),
+            0xb5 => self.lda(&AddressingMode::ZeroPageX),
+            0xad => self.lda(&AddressingMode::Absolute),
+            0xbd => self.lda(&AddressingMode::AbsoluteX),
+            0xb9 => self.lda(&AddressingMode::AbsoluteY),
+            0xa1 => self.lda(&AddressingMode::IndirectX),
+            0xb1 => self.lda(&AddressingMode::IndirectY),
+            0x85 => self.sta(&AddressingMode::ZeroPage),
+            0x95 => self.sta(&AddressingMode::ZeroPageX),
+            0x8d => self.sta(&AddressingMode::Absolute),
+            0x9d => self.sta(&AddressingMode::AbsoluteX),
+            0x99 => self.sta(&AddressingMode::AbsoluteY),
+            0x81 => self.sta(&AddressingMode::IndirectX),
+            0x91 => self.sta(&AddressingMode::IndirectY),
+            0x4c => {
+                self.jmp_absolute(opcode_address)?;
+                advance_pc = false;
+            }
+            0x10 => self.branch(self.status & 0b1000_0000 == 0), // BPL
+            0x30 => self.branch(self.status & 0b1000_0000 != 0), // BMI
+            0x50 => self.branch(self.status & 0b0100_0000 == 0), // BVC
+            0x70 => self.branch(self.status & 0b0100_0000 != 0), // BVS
+            0x90 => self.branch(self.status & CARRY_FLAG == 0),  // BCC
+            0xb0 => self.branch(self.status & CARRY_FLAG != 0),  // BCS
+            0xd0 => self.branch(self.status & 0b0000_0010 == 0), // BNE
+            0xf0 => self.branch(self.status & 0b0000_0010 != 0), // BEQ
+            0x48 => self.pha()?,
+            0x68 => self.pla()?,
+            0x69 => self.adc(&AddressingMode::Immediate),
+            0x65 => self.adc(&AddressingMode::ZeroPage),
+            0x75 => self.adc(&AddressingMode::ZeroPageX),
+            0x6d => self.adc(&AddressingMode::Absolute),
+            0x7d => self.adc(&AddressingMode::AbsoluteX),
+            0x79 => self.adc(&AddressingMode::AbsoluteY),
+            0x61 => self.adc(&AddressingMode::IndirectX),
+            0x71 => self.adc(&AddressingMode::IndirectY),
+            0xe9 => self.sbc(&AddressingMode::Immediate),
+            0xe5 => self.sbc(&AddressingMode::ZeroPage),
+            0xf5 => self.sbc(&AddressingMode::ZeroPageX),
+            0xed => self.sbc(&AddressingMode::Absolute),
+            0xfd => self.sbc(&AddressingMode::AbsoluteX),
+            0xf9 => self.sbc(&AddressingMode::AbsoluteY),
+            0xe1 => self.sbc(&AddressingMode::IndirectX),
+            0xf1 => self.sbc(&AddressingMode::IndirectY),
+            0xc9 => self.compare(&AddressingMode::Immediate, self.accumulator),
+            0xc5 => self.compare(&AddressingMode::ZeroPage, self.accumulator),
+            0xd5 => self.compare(&AddressingMode::ZeroPageX, self.accumulator),
+            0xcd => self.compare(&AddressingMode::Absolute, self.accumulator),
+            0xdd => self.compare(&AddressingMode::AbsoluteX, self.accumulator),
+            0xd9 => self.compare(&AddressingMode::AbsoluteY, self.accumulator),
+            0xc1 => self.compare(&AddressingMode::IndirectX, self.accumulator),
+            0xd1 => self.compare(&AddressingMode::IndirectY, self.accumulator),
+            0xe0 => self.compare(&AddressingMode::Immediate, self.index_x),
+            0xe4 => self.compare(&AddressingMode::ZeroPage, self.index_x),
+            0xec => self.compare(&AddressingMode::Absolute, self.index_x),
+            0xc0 => self.compare(&AddressingMode::Immediate, self.index_y),
+            0xc4 => self.compare(&AddressingMode::ZeroPage, self.index_y),
+            0xcc => self.compare(&AddressingMode::Absolute, self.index_y),
+            0xe6 => self.inc(&AddressingMode::ZeroPage),
+            0xf6 => self.inc(&AddressingMode::ZeroPageX),
+            0xee => self.inc(&AddressingMode::Absolute),
+            0xfe => self.inc(&AddressingMode::AbsoluteX),
+            0xc6 => self.dec(&AddressingMode::ZeroPage),
+            0xd6 => self.dec(&AddressingMode::ZeroPageX),
+            0xce => self.dec(&AddressingMode::Absolute),
+            0xde => self.dec(&AddressingMode::AbsoluteX),
+            0xf8 => self.status |= DECIMAL_FLAG,  // SED
+            0xd8 => self.status &= !DECIMAL_FLAG, // CLD
+            0xe8 => self.inx(),
+            0xc8 => self.iny(),
+            0xca => self.dex(),
+            0x88 => self.dey(),
+            0x0a => self.asl(&AddressingMode::NoneAddressing),
+            0x06 => self.asl(&AddressingMode::ZeroPage),
+            0x16 => self.asl(&AddressingMode::ZeroPageX),
+            0x0e => self.asl(&AddressingMode::Absolute),
+            0x1e => self.asl(&AddressingMode::AbsoluteX),
+            0x4a => self.lsr(&AddressingMode::NoneAddressing),
+            0x46 => self.lsr(&AddressingMode::ZeroPage),
+            0x56 => self.lsr(&AddressingMode::ZeroPageX),
+            0x4e => self.lsr(&AddressingMode::Absolute),
+            0x5e => self.lsr(&AddressingMode::AbsoluteX),
+            0x2a => self.rol(&AddressingMode::NoneAddressing),
+            0x26 => self.rol(&AddressingMode::ZeroPage),
+            0x36 => self.rol(&AddressingMode::ZeroPageX),
+            0x2e => self.rol(&AddressingMode::Absolute),
+            0x3e => self.rol(&AddressingMode::AbsoluteX),
+            0x6a => self.ror(&AddressingMode::NoneAddressing),
+            0x66 => self.ror(&AddressingMode::ZeroPage),
+            0x76 => self.ror(&AddressingMode::ZeroPageX),
+            0x6e => self.ror(&AddressingMode::Absolute),
+            0x7e => self.ror(&AddressingMode::AbsoluteX),
+            0x0b | 0x2b => self.anc(),
+            0x4b => self.alr(),
+            0x6b => self.arr(),
+            0xcb => self.axs(),
+            // SKB: two-byte NOPs
+            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => self.nop_read(&AddressingMode::Immediate),
+            0x04 | 0x44 | 0x64 => self.nop_read(&AddressingMode::ZeroPage),
+            0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 => self.nop_read(&AddressingMode::ZeroPageX),
+            // SKW: three-byte NOPs
+            0x0c => self.nop_read(&AddressingMode::Absolute),
+            0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => self.nop_read(&AddressingMode::AbsoluteX),
+            0x00 => running = false, // Stop at BRK
+
+            _ => {
+                self.program_counter = opcode_address;
+                log::warn!("unknown opcode ${:02X} at ${:04X}", opcode, opcode_address);
+                return Err(EmuError::UnknownOpcode {
+                    opcode,
+                    address: opcode_address,
+                    last_instruction: self.last_instruction,
+                });
+            }
+        }
+        // Step past the operand bytes the instruction has read
+        if advance_pc {
+            let operand_bytes = op.map_or(0, |op| op.mode.operand_bytes() as u16);
+            self.program_counter = self.program_counter.wrapping_add(operand_bytes);
+        }
+        self.last_instruction = Some((opcode_address, opcode));
+        self.cycles += base_cycles as u64;
+        self.catch_up(cycles_before);
+        self.finish_step(self.cycles - cycles_before, true, frame_before);
+        Ok(running)
+    }
+
+    // Account for a completed step in the frame statistics and the event stream
+    fn finish_step(&mut self, cycles: u64, instruction: bool, frame_before: u64) {
+        self.update_frame_stats(cycles, instruction, frame_before);
+        if let (true, Some((address, opcode))) = (instruction, self.last_instruction) {
+            self.emit(EmuEvent::InstructionExecuted {
+                address,
+                opcode,
+                cycles,
+            });
+        }
+        if self.bus.frame_count != frame_before {
+            self.emit(EmuEvent::FrameComplete {
+                frame: self.bus.frame_count,
+            });
+        }
+    }
+
+    // Run the bus for the cycles spent since `cycles_before`, charge any DMC stall,
+    // and report the total to the clock
+    fn catch_up(&mut self, cycles_before: u64) {
+        let stall = self.bus.tick((self.cycles - cycles_before) as u16);
+        self.cycles += stall as u64;
+        self.clock.tick(self.cycles - cycles_before);
+    }
+
+    // Main interpreter loop, continuing from the current program counter
+    // Call reset() first to start a program from its reset vector
+    pub fn interpret(&mut self) -> Result<(), EmuError> {
+        let mut executed = 0;
+        while self.step_within(&mut executed, self.max_instructions)? {}
+        Ok(())
+    }
+
+    // Step, unless `limit` instructions have already been executed in this run
+    fn step_within(&mut self, executed: &mut u64, limit: Option<u64>) -> Result<bool, EmuError> {
+        if limit == Some(*executed) {
+            return Err(EmuError::InstructionLimit);
+        }
+        *executed += 1;
+        self.step()
+    }
+
+    // Run until the PPU completes the next frame, or the program stops
+    pub fn run_frame(&mut self) -> Result<(), EmuError> {
+        let mut executed = 0;
+        self.run_frame_within(&mut executed)
+    }
+
+    fn run_frame_within(&mut self, executed: &mut u64) -> Result<(), EmuError> {
+        let frame = self.bus.frame_count;
+        while self.bus.frame_count == frame {
+            if !self.step_within(executed, self.max_instructions)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Run a number of complete frames and return the last picture rendered
+    // max_instructions covers all of the frames together
+    pub fn run_frames(&mut self, n: u32) -> Result<Frame, EmuError> {
+        let mut executed = 0;
+        for _ in 0..n {
+            self.run_frame_within(&mut executed)?;
+        }
+        Ok(self.bus.frame.clone())
+    }
+
+    // Step until the predicate holds, BRK is reached, or the instruction guard trips
+    // The guard is max_instructions, or RUN_UNTIL_LIMIT when that is unset
+    pub fn run_until<F: Fn(&CPU) -> bool>(&mut self, done: F) -> Result<(), EmuError> {
+        let limit = Some(self.max_instructions.unwrap_or(RUN_UNTIL_LIMIT));
+        let mut executed = 0;
+        while !done(self) {
+            if !self.step_within(&mut executed, limit)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Settings such as strict_stack and program_base are configuration, not state
+// A state carries the ROM hash and only loads back onto the cartridge it was saved from,
+// whose mapper can then take the rest of the state in place
+impl SaveState for CPU {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.u64(self.rom_hash);
+        writer.u8(self.accumulator);
+        writer.u8(self.index_x);
+        writer.u8(self.index_y);
+        writer.u8(self.status);
+        writer.u16(self.program_counter);
+        writer.u8(self.stack_pointer);
+        writer.u64(self.cycles);
+        writer.u32(self.self_jumps);
+        self.bus.write_state(writer);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        if reader.u64()? != self.rom_hash {
+            return Err(EmuError::InvalidSaveState(
+                "state was saved from a different ROM".to_string(),
+            ));
+        }
+        self.accumulator = reader.u8()?;
+        self.index_x = reader.u8()?;
+        self.index_y = reader.u8()?;
+        self.status = reader.u8()?;
+        self.program_counter = reader.u16()?;
+        self.stack_pointer = reader.u8()?;
+        self.cycles = reader.u64()?;
+        self.self_jumps = reader.u32()?;
+        self.bus.read_state(reader)
+    }
+}
+
+// One line of diff() for the differing bytes from start up to end
+fn memory_difference(ours: &CPU, theirs: &CPU, start: u32, end: u32) -> String {
+    let (start, last) = (start as u16, (end - 1) as u16);
+    if start == last {
+        format!(
+            "${:04X}: {:02X} != {:02X}",
+            start,
+            ours.peek(start),
+            theirs.peek(start)
+        )
+    } else {
+        format!(
+            "${:04X}-${:04X}: {} bytes differ",
+            start,
+            last,
+            end - start as u32
+        )
+    }
+}
+
+// Register block in the nestest log style
+impl fmt::Display for CPU {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.program_counter,
+            self.accumulator,
+            self.index_x,
+            self.index_y,
+            self.status,
+            self.stack_pointer
+        )
+    }
+}
+
+// Registers and cycle count, leaving out the bus and its memory
+impl fmt::Debug for CPU {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CPU")
+            .field("registers", &self.registers())
+            .field("cycles", &self.cycles)
+            .finish()
+    }
+}
+
+// Operand template in assembler syntax
+impl fmt::Display for AddressingMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let template = match self {
+            AddressingMode::Immediate => "#imm",
+            AddressingMode::ZeroPage => "$zp",
+            AddressingMode::Absolute => "$abs",
+            AddressingMode::ZeroPageX => "$zp,X",
+            AddressingMode::ZeroPageY => "$zp,Y",
+            AddressingMode::AbsoluteX => "$abs,X",
+            AddressingMode::AbsoluteY => "$abs,Y",
+            AddressingMode::IndirectX => "($zp,X)",
+            AddressingMode::IndirectY => "($zp),Y",
+            AddressingMode::NoneAddressing => "", // Implied: no operand
+        };
+        write!(f, "{}", template)
+    }
+}
+
+impl AddressingMode {
+    // Bytes of operand following the opcode
+    // Implied instructions have none; branches keep their relative offset under
+    // NoneAddressing too and step past it themselves
+    pub fn operand_bytes(&self) -> u8 {
+        match self {
+            AddressingMode::Immediate
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY => 1,
+            AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => 2,
+            AddressingMode::NoneAddressing => 0,
+        }
+    }
+}
+
+// Whether two addresses fall in different 256-byte pages
+fn page_differs(a: u16, b: u16) -> bool {
+    a & 0xFF00 != b & 0xFF00
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::joypad::JoypadButton;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Load and interpret a program on a fresh CPU, returning the CPU once it stops
+    fn run_program(bytes: Vec<u8>) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load_and_interpret(bytes).unwrap();
+        cpu
+    }
+
+    // Test case for the LDA (Load Accumulator) instruction with immediate addressing
+    #[test]
+    fn test_0xa9_lda_immediate_load_data() {
+        let cpu = run_program(vec![0xa9, 0x05, 0x00]); // Load LDA instruction with value 0x05
+        assert_eq!(cpu.accumulator, 5); // Check if accumulator is loaded correctly
+        assert!(cpu.status & 0b0000_0010 == 0b00); // Check if zero flag is not set
+        assert!(cpu.status & 0b1000_0000 == 0); // Check if negative flag is not set
+    }
+
+    // Test case for LDA reading the controller port through the bus
+    #[test]
+    fn test_lda_absolute_reads_controller() {
+        let mut cpu = CPU::new();
+        cpu.bus
+            .joypad1
+            .set_button_pressed_status(JoypadButton::A, true);
+        cpu.bus.mem_write(0x4016, 1); // Strobe the controller
+        cpu.load_and_interpret(vec![0xad, 0x16, 0x40, 0x00])
+            .unwrap(); // LDA $4016
+        assert_eq!(cpu.accumulator, 1); // A button is pressed
+    }
+
+    // Test case for a 16-bit read straddling 0xFFFF and 0x0000
+    #[test]
+    fn test_mem_read_u16_wraps_at_0xffff() {
+        let mut cpu = CPU::new();
+        cpu.bus.load_prg(0xFFFF, &[0x34]); // Low byte at the top of the cartridge
+        cpu.bus.mem_write(0x0000, 0x12); // High byte at the bottom of RAM
+        assert_eq!(cpu.mem_read_u16(0xFFFF), 0x1234);
+    }
+
+    // Test case for the STA instruction storing the accumulator
+    #[test]
+    fn test_sta_zero_page_stores_accumulator() {
+        let mut cpu = run_program(assemble(&["LDA #$05", "STA $10", "BRK"]));
+        assert_eq!(cpu.mem_read(0x10), 0x05);
+    }
+
+    // Test case for reading, editing and writing back the register snapshot
+    #[test]
+    fn test_registers_round_trip() {
+        let mut cpu = run_program(vec![0xa9, 0x42, 0x00]);
+        let mut regs = cpu.registers();
+        assert_eq!(regs.a, 0x42);
+        regs.x = 0x10;
+        cpu.set_registers(regs);
+        assert_eq!(cpu.index_x, 0x10);
+        assert_eq!(cpu.registers(), regs);
+    }
+
+    // Test case for stepping until the program counter reaches a sentinel
+    #[test]
+    fn test_run_until_pc_sentinel() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$01", "STA $0200", "LDA #$02", "BRK"]));
+        cpu.reset();
+        cpu.run_until(|cpu| cpu.program_counter == 0x8005).unwrap();
+        assert_eq!(cpu.accumulator, 0x01); // Stopped before the second load
+        assert_eq!(cpu.mem_read(0x0200), 0x01);
+    }
+
+    // Test case for the instruction guard on a program that never halts
+    #[test]
+    fn test_run_until_instruction_limit() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["JMP $8000"]));
+        cpu.reset();
+        assert_eq!(cpu.run_until(|_| false), Err(EmuError::InstructionLimit));
+    }
+
+    // Test case for ZeroPageX wrapping within page zero
+    #[test]
+    fn test_zero_page_x_wraps_at_0xff() {
+        let mut cpu = CPU::new();
+        cpu.program_counter = 0x0300;
+        cpu.mem_write(0x0300, 0xFF);
+        cpu.index_x = 0x02;
+        assert_eq!(cpu.address_operand(&AddressingMode::ZeroPageX).0, 0x0001);
+    }
+
+    // Test case for AbsoluteX wrapping from 0xFFFF to the bottom of memory
+    #[test]
+    fn test_absolute_x_wraps_at_0xffff() {
+        let mut cpu = CPU::new();
+        cpu.program_counter = 0x0300;
+        cpu.mem_write(0x0300, 0xFF);
+        cpu.mem_write(0x0301, 0xFF);
+        cpu.index_x = 0x01;
+        assert_eq!(
+            cpu.address_operand(&AddressingMode::AbsoluteX),
+            (0x0000, true)
+        );
+    }
+
+    // Test case for IndirectY fetching the pointer's high byte from 0x00
+    #[test]
+    fn test_indirect_y_pointer_wraps_at_0xff() {
+        let mut cpu = CPU::new();
+        cpu.program_counter = 0x0300;
+        cpu.mem_write(0x0300, 0xFF); // Pointer lives at 0x00FF
+        cpu.mem_write(0x00FF, 0x34);
+        cpu.mem_write(0x0000, 0x12);
+        cpu.mem_write(0x0100, 0x56); // Would be read without the wrap
+        cpu.index_y = 0x01;
+        assert_eq!(cpu.address_operand(&AddressingMode::IndirectY).0, 0x1235);
+    }
+
+    // Test case for a program loaded below 0x8000 leaving I/O registers, watchpoints and
+    // the memory trace untouched
+    #[test]
+    fn test_load_below_rom_has_no_side_effects() {
+        let mut cpu = CPU::new();
+        cpu.program_base = 0x1FFE;
+        cpu.bus.add_write_watchpoint(0x1FFE);
+        cpu.bus.start_mem_trace();
+        cpu.load(vec![0xa9, 0x80, 0x8d, 0x01]); // Runs on into PPUCTRL and PPUMASK
+        assert_eq!(cpu.peek(0x1FFE), 0xa9);
+        assert_eq!(cpu.bus.ppu.io_latch(), 0);
+        assert!(cpu.bus.watch_hits.is_empty());
+        assert!(cpu.bus.take_mem_trace().is_empty());
+    }
+
+    // Test case for an instruction at 0xFFFF taking its operand from 0x0000
+    #[test]
+    fn test_program_counter_wraps_at_0xffff() {
+        let mut cpu = CPU::new();
+        cpu.bus.load_prg(0xFFFF, &[0xA9]); // LDA #
+        cpu.mem_write(0x0000, 0x42);
+        cpu.program_counter = 0xFFFF;
+        cpu.step().unwrap();
+        assert_eq!(cpu.accumulator, 0x42);
+        assert_eq!(cpu.program_counter, 0x0001);
+    }
+
+    // Test case for a strict-mode push with the stack already full
+    #[test]
+    fn test_strict_stack_overflow() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["PHA", "BRK"]));
+        cpu.reset();
+        cpu.strict_stack = true;
+        cpu.stack_pointer = 0x00;
+        assert_eq!(cpu.step(), Err(EmuError::StackOverflow));
+    }
+
+    // Test case for a strict-mode pop with the stack empty
+    #[test]
+    fn test_strict_stack_underflow() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["PLA", "BRK"]));
+        cpu.reset();
+        cpu.strict_stack = true;
+        cpu.stack_pointer = 0xFF;
+        assert_eq!(cpu.step(), Err(EmuError::StackUnderflow));
+    }
+
+    // Test case for the default mode wrapping the stack pointer silently
+    #[test]
+    fn test_default_stack_wraps_silently() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$07", "PHA", "PLA", "BRK"]));
+        cpu.reset();
+        cpu.stack_pointer = 0x00;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.stack_pointer, 0xFF);
+        assert_eq!(cpu.mem_read(0x0100), 0x07);
+        cpu.step().unwrap();
+        assert_eq!(cpu.stack_pointer, 0x00);
+        assert_eq!(cpu.accumulator, 0x07);
+    }
+
+    // Test case for pushes wrapping the stack pointer from 0x0100 back to 0x01FF
+    #[test]
+    fn test_stack_push_wraps_within_page_one() {
+        use crate::bus::AccessKind::Write;
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&[
+            "LDA #$11", "PHA", "LDA #$22", "PHA", "LDA #$33", "PHA", "LDA #$44", "PHA", "BRK",
+        ]));
+        cpu.reset();
+        cpu.stack_pointer = 0x02;
+        cpu.bus.start_mem_trace();
+        for _ in 0..8 {
+            cpu.step().unwrap();
+        }
+        let writes: Vec<(u16, u8)> = cpu
+            .bus
+            .take_mem_trace()
+            .into_iter()
+            .filter(|&(_, _, kind)| kind == Write)
+            .map(|(address, data, _)| (address, data))
+            .collect();
+        assert_eq!(
+            writes,
+            vec![
+                (0x0102, 0x11),
+                (0x0101, 0x22),
+                (0x0100, 0x33),
+                (0x01FF, 0x44)
+            ]
+        );
+        assert_eq!(cpu.stack_pointer, 0xFE);
+    }
+
+    // Test case for a SKW AbsoluteX charging the page-cross cycle
+    #[test]
+    fn test_skw_absolute_x_page_cross_cycle() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["*NOP $80FF,X", "*NOP $8000,X", "BRK"]));
+        cpu.reset();
+        cpu.index_x = 0x01;
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 5); // 0x80FF + 1 crosses into page 0x81
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 9); // Same page: base 4 cycles
+        assert_eq!(cpu.program_counter, 0x8006);
+    }
+
+    // Test case for a SKW performing its read on an I/O register
+    #[test]
+    fn test_skw_reads_io_register() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["*NOP $2002", "BRK"]));
+        cpu.reset();
+        cpu.bus.mem_write(0x2006, 0x21); // Leaves the PPUADDR latch on the low byte
+        cpu.step().unwrap(); // Reading PPUSTATUS resets the latch
+        cpu.bus.mem_write(0x2006, 0x23);
+        cpu.bus.mem_write(0x2006, 0x05);
+        assert_eq!(cpu.bus.ppu.vram_addr(), 0x2305);
+    }
+
+    // Test case for running whole frames headlessly
+    #[test]
+    fn test_run_frames_returns_final_frame() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["JMP $8000"]));
+        cpu.reset();
+        let frame = cpu.run_frames(2).unwrap();
+        assert_eq!(frame.data.len(), Frame::WIDTH * Frame::HEIGHT * 3);
+        assert_eq!(cpu.bus.frame_count, 2);
+    }
+
+    // Test case for the nestest-style register display
+    #[test]
+    fn test_display_cpu_registers() {
+        let mut cpu = CPU::new();
+        cpu.set_registers(Registers {
+            a: 0x05,
+            x: 0x1f,
+            y: 0x00,
+            sp: 0xfd,
+            pc: 0xc000,
+            status: 0x24,
+        });
+        assert_eq!(cpu.to_string(), "PC:C000 A:05 X:1F Y:00 P:24 SP:FD");
+    }
+
+    // Test case for the operand templates of the addressing modes
+    #[test]
+    fn test_display_addressing_mode() {
+        assert_eq!(AddressingMode::Immediate.to_string(), "#imm");
+        assert_eq!(AddressingMode::AbsoluteX.to_string(), "$abs,X");
+        assert_eq!(AddressingMode::IndirectY.to_string(), "($zp),Y");
+        assert_eq!(AddressingMode::NoneAddressing.to_string(), "");
+    }
+
+    // Test case for the operand length of each addressing mode
+    #[test]
+    fn test_operand_bytes() {
+        assert_eq!(AddressingMode::Immediate.operand_bytes(), 1);
+        assert_eq!(AddressingMode::ZeroPage.operand_bytes(), 1);
+        assert_eq!(AddressingMode::ZeroPageX.operand_bytes(), 1);
+        assert_eq!(AddressingMode::ZeroPageY.operand_bytes(), 1);
+        assert_eq!(AddressingMode::IndirectX.operand_bytes(), 1);
+        assert_eq!(AddressingMode::IndirectY.operand_bytes(), 1);
+        assert_eq!(AddressingMode::Absolute.operand_bytes(), 2);
+        assert_eq!(AddressingMode::AbsoluteX.operand_bytes(), 2);
+        assert_eq!(AddressingMode::AbsoluteY.operand_bytes(), 2);
+        assert_eq!(AddressingMode::NoneAddressing.operand_bytes(), 0);
+
+        // Every opcode's length agrees with its mode, apart from the relative branches
+        for op in opcodes::CPU_OPS_CODES.iter() {
+            let branch = op.mode == AddressingMode::NoneAddressing && op.len == 2;
+            if !branch {
+                assert_eq!(op.len, 1 + op.mode.operand_bytes(), "{}", op.mnemonic);
+            }
+        }
+    }
+
+    // Test case for the RMW dummy write reaching a watched address
+    #[test]
+    fn test_rmw_dummy_write_hits_watchpoint_twice() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["INC $10", "BRK"]));
+        cpu.reset();
+        cpu.mem_write(0x10, 0x41);
+        cpu.bus.add_write_watchpoint(0x10);
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.watch_hits, vec![(0x10, 0x41), (0x10, 0x42)]);
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 5);
+    }
+
+    // Test case for INC touching only memory while INX and friends touch only their register
+    #[test]
+    fn test_inc_memory_and_index_registers_stay_separate() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&[
+            "LDA #$05", "INC $10", "INX", "INY", "INY", "DEX", "DEY", "BRK",
+        ]));
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.accumulator, 0x05);
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.index_x, 0x00);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.index_x, 0x01);
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 2 + 5 + 2);
+        assert_eq!(cpu.accumulator, 0x05);
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+
+        cpu.interpret().unwrap();
+        assert_eq!(cpu.index_x, 0x00);
+        assert_eq!(cpu.index_y, 0x01);
+        assert_eq!(cpu.status & 0b0000_0010, 0); // Z reflects Y after DEY
+        assert_eq!(cpu.accumulator, 0x05);
+    }
+
+    // Test case for DEX wrapping from zero and setting the negative flag
+    #[test]
+    fn test_dex_wraps() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["DEX", "BRK"]));
+        cpu.reset();
+        cpu.step().unwrap();
+        assert_eq!(cpu.index_x, 0xff);
+        assert_eq!(cpu.status & 0b1000_0000, 0b1000_0000);
+    }
+
+    // Test case for the shift and rotate instructions on memory
+    #[test]
+    fn test_rmw_shifts_and_rotates() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&[
+            "ASL $10", "ROL $10", "LSR $11", "ROR $11", "DEC $12", "BRK",
+        ]));
+        cpu.reset();
+        cpu.mem_write(0x10, 0b1000_0001);
+        cpu.mem_write(0x11, 0b0000_0011);
+        cpu.interpret().unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0b0000_0101); // Carry from ASL rotated back in
+        assert_eq!(cpu.mem_read(0x11), 0b1000_0000); // Carry from LSR rotated into bit 7
+        assert_eq!(cpu.mem_read(0x12), 0xff);
+        assert_eq!(cpu.status & CARRY_FLAG, CARRY_FLAG); // Bit 0 shifted out by ROR
+    }
+
+    // Test case for loading an iNES file from disk
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_load_rom_file() {
+        let mut raw = crate::cartridge::test::test_rom_bytes(0x00, 0x00, 1, 1);
+        raw[16 + 0x3ffc] = 0x00; // Reset vector, mirrored from 0xFFFC into the 16KB bank
+        raw[16 + 0x3ffd] = 0xc0;
+        let path = std::env::temp_dir().join("nes_test_load_rom_file.nes");
+        fs::write(&path, &raw).unwrap();
+
+        let mut cpu = CPU::new();
+        cpu.load_rom_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cpu.bus.mapper(), 0);
+        assert_eq!(cpu.program_counter, 0xc000);
+    }
+
+    // Test case for booting an embedded ROM image to its reset vector
+    #[test]
+    fn test_load_embedded_rom() {
+        const ROM: &[u8] = include_bytes!("../test_roms/minimal.nes");
+        let mut cpu = CPU::new();
+        cpu.load_rom(ROM).unwrap();
+        assert_eq!(cpu.program_counter, 0x8000);
+        cpu.interpret().unwrap(); // LDA #$42, STA $10, BRK
+        assert_eq!(cpu.mem_read(0x10), 0x42);
+    }
+
+    // Test case for a save slot round trip and rejecting a slot saved from another ROM
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_save_slots() {
+        let mut raw = crate::cartridge::test::test_rom_bytes(0x00, 0x00, 1, 1);
+        raw[16 + 0x3ffd] = 0xc0; // Reset vector 0xC000
+        let path = std::env::temp_dir().join("nes_test_save_slots.nes");
+        fs::write(&path, &raw).unwrap();
+
+        let mut cpu = CPU::new();
+        cpu.load_rom_file(&path).unwrap();
+        cpu.poke(0x0010, 0x42);
+        cpu.save_slot(3).unwrap();
+        assert!(cpu.slot_saved_at(3).unwrap() > 0);
+        cpu.poke(0x0010, 0x00);
+        cpu.load_slot(3).unwrap();
+        assert_eq!(cpu.peek(0x0010), 0x42);
+        assert!(matches!(cpu.load_slot(4), Err(EmuError::Io(_))));
+        assert!(matches!(cpu.save_slot(10), Err(EmuError::Io(_))));
+
+        // A different game at the same path must not pick up the slot
+        raw[16] = 0xea;
+        fs::write(&path, &raw).unwrap();
+        cpu.load_rom_file(&path).unwrap();
+        assert!(matches!(
+            cpu.load_slot(3),
+            Err(EmuError::InvalidSaveState(_))
+        ));
+        fs::remove_file(path.with_extension("state3")).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Test case for a missing file surfacing an I/O error
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_load_rom_file_missing() {
+        let mut cpu = CPU::new();
+        let path = std::env::temp_dir().join("nes_test_no_such_rom.nes");
+        assert!(matches!(cpu.load_rom_file(&path), Err(EmuError::Io(_))));
+    }
+
+    // Test case for the detector stopping a `JMP self` loop at the threshold
+    #[test]
+    fn test_loop_detector_fires_at_threshold() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["JMP $8000"]));
+        cpu.reset();
+        cpu.loop_detect_threshold = Some(5);
+        for _ in 0..4 {
+            assert_eq!(cpu.step(), Ok(true));
+        }
+        assert_eq!(cpu.step(), Err(EmuError::Halted));
+        assert_eq!(cpu.program_counter, 0x8000);
+    }
+
+    // Test case for jumps elsewhere not counting towards the threshold
+    #[test]
+    fn test_loop_detector_ignores_other_jumps() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["JMP $8003", "JMP $8000"]));
+        cpu.reset();
+        cpu.loop_detect_threshold = Some(2);
+        for _ in 0..10 {
+            assert_eq!(cpu.step(), Ok(true));
+        }
+    }
+
+    // Test case for a DMC sample fetch stalling the CPU and raising an IRQ at the end
+    #[test]
+    fn test_dmc_fetch_stalls_cpu_and_raises_irq() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&[
+            "LDA #$8F", // IRQ enabled, fastest rate
+            "STA $4010",
+            "LDA #$00",
+            "STA $4012", // Sample at 0xC000
+            "STA $4013", // One byte long
+            "LDA #$10",
+            "STA $4015", // Start the sample
+            "BRK",
+        ]));
+        cpu.reset();
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 18);
+        assert!(!cpu.bus.apu.irq());
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 18 + 4 + 4); // STA plus the fetch stall
+        assert!(cpu.bus.apu.irq());
+    }
+
+    // Test case for a paused emulator not advancing across run_frame
+    #[test]
+    fn test_paused_run_frame_does_nothing() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["INC $10", "JMP $8000"]));
+        cpu.reset();
+        cpu.pause();
+        assert!(cpu.is_paused());
+        let registers = cpu.registers();
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.registers(), registers);
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES); // Only the reset
+        assert_eq!(cpu.bus.frame_count, 0);
+
+        cpu.toggle_pause();
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.bus.frame_count, 1);
+        assert_ne!(cpu.bus.mem_read(0x10), 0);
+    }
+
+    // Test case for skipped frames still running the CPU and raising NMIs
+    #[test]
+    fn test_frame_skip_still_raises_nmi() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&[
+            "LDA #$80", // Enable NMI at VBlank
+            "STA $2000",
+            "JMP $8005",
+            "INC $20", // NMI handler at 0x8008
+            "JMP $800A",
+        ]));
+        cpu.bus.load_prg(0xFFFA, &[0x08, 0x80]);
+        cpu.reset();
+        cpu.bus.frame_skip = 1;
+        cpu.run_frames(4).unwrap();
+        assert_eq!(cpu.bus.frame_count, 4);
+        assert_eq!(cpu.bus.frames_rendered, 2);
+        cpu.step().unwrap(); // The last NMI is taken after its frame completes
+        cpu.step().unwrap();
+        assert_eq!(cpu.bus.mem_read(0x20), 4);
+    }
+
+    // Test case for the speed multiplier scaling the frame duration
+    #[test]
+    fn test_speed_multiplier_scales_frame_duration() {
+        let mut cpu = CPU::new();
+        let normal = cpu.target_frame_duration();
+        assert_eq!(normal.as_micros(), 16_639); // 60.0988 Hz
+        assert!(!cpu.drop_audio());
+
+        cpu.set_speed_multiplier(2.0);
+        assert_eq!(
+            cpu.target_frame_duration().as_micros(),
+            normal.as_micros() / 2
+        );
+        assert!(cpu.drop_audio());
+
+        cpu.fast_forward(true);
+        assert_eq!(cpu.target_frame_duration(), Duration::ZERO);
+        cpu.fast_forward(false);
+        assert_eq!(cpu.target_frame_duration(), normal);
+    }
+
+    // Test case for multipliers with no frame rate leaving the pace unchanged
+    #[test]
+    fn test_speed_multiplier_rejects_non_positive() {
+        let mut cpu = CPU::new();
+        let normal = cpu.target_frame_duration();
+        for multiplier in [0.0, -2.0, f32::NAN, f32::NEG_INFINITY] {
+            cpu.set_speed_multiplier(multiplier);
+            assert_eq!(cpu.target_frame_duration(), normal);
+        }
+        cpu.set_speed_multiplier(f32::MIN_POSITIVE);
+        assert_eq!(cpu.target_frame_duration(), Duration::MAX);
+    }
+
+    // Test case for a branch that is not taken costing 2 cycles
+    #[test]
+    fn test_branch_not_taken_cycles() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$01", "BEQ $10", "BRK"]));
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 2 + 2);
+        assert_eq!(cpu.program_counter, 0x8004);
+    }
+
+    // Test case for a taken branch within the page costing 3 cycles
+    #[test]
+    fn test_branch_taken_same_page_cycles() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$00", "BEQ $10", "BRK"]));
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 2 + 3);
+        assert_eq!(cpu.program_counter, 0x8014);
+    }
+
+    // Test case for a taken branch onto another page costing 4 cycles
+    #[test]
+    fn test_branch_taken_page_cross_cycles() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$00", "BEQ $F0", "BRK"])); // Back 16 bytes from 0x8004
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 2 + 4);
+        assert_eq!(cpu.program_counter, 0x7ff4);
+    }
+
+    // Test case for a branch whose offset is the last byte of memory
+    #[test]
+    fn test_branch_wraps_at_0xffff() {
+        let mut cpu = CPU::new();
+        cpu.bus.load_prg(0xFFFE, &[0xD0, 0x02]); // BNE +2
+        cpu.program_counter = 0xFFFE;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0002);
+    }
+
+    // Put the PPU 6 dots before VBlank with NMI enabled, ready to run one instruction
+    fn cpu_before_vblank(program: &[&str]) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(program));
+        cpu.reset();
+        cpu.bus.mem_write(0x2000, 0b1000_0000);
+        cpu.bus.ppu.scanline = 240;
+        cpu.bus.ppu.cycles = 335;
+        cpu
+    }
+
+    // Test case for clearing NMI-enable just as VBlank sets suppressing the NMI
+    #[test]
+    fn test_nmi_suppressed_by_clearing_enable_at_vblank() {
+        let mut cpu = cpu_before_vblank(&["LDA #$00", "STA $2000", "BRK"]);
+        cpu.bus.ppu.cycles -= 6; // Leave room for the LDA
+        cpu.step().unwrap();
+        cpu.step().unwrap(); // VBlank begins during this STA
+        assert_eq!(cpu.bus.ppu.scanline, 241);
+        assert!(!cpu.bus.poll_nmi());
+    }
+
+    // Test case for the same instruction timing raising an NMI when enable is left alone
+    #[test]
+    fn test_nmi_raised_at_vblank() {
+        let mut cpu = cpu_before_vblank(&["LDA #$00", "STA $10", "BRK"]);
+        cpu.bus.ppu.cycles -= 6;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.bus.poll_nmi());
+    }
+
+    // Test case for a program reading a data table set up with poke_range
+    #[test]
+    fn test_poke_range_data_table() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&[
+            "LDA $0202",
+            "STA $10",
+            "LDA $9001",
+            "STA $11",
+            "BRK",
+        ]));
+        cpu.reset();
+        cpu.poke_range(0x0200, &[0x10, 0x20, 0x30, 0x40]);
+        cpu.poke_range(0x9000, &[0xaa, 0xbb]);
+        cpu.interpret().unwrap();
+        assert_eq!(cpu.bus.mem_read(0x10), 0x30);
+        assert_eq!(cpu.bus.mem_read(0x11), 0xbb);
+    }
+
+    // Test case for disassembling single instructions of several forms
+    #[test]
+    fn test_disassemble_one() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA $1234,X", "STA ($20),Y", "BNE $FB", "BRK"]));
+        assert_eq!(cpu.disassemble_one(0x8000), ("LDA $1234,X".to_string(), 3));
+        assert_eq!(cpu.disassemble_one(0x8003), ("STA ($20),Y".to_string(), 2));
+        assert_eq!(cpu.disassemble_one(0x8005), ("BNE $8002".to_string(), 2));
+        assert_eq!(cpu.disassemble_one(0x8007), ("BRK".to_string(), 1));
+        cpu.poke(0x8008, 0x02);
+        assert_eq!(cpu.disassemble_one(0x8008), (".byte $02".to_string(), 1));
+    }
+
+    // Test case for writing a disassembly listing with raw bytes to a file
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_export_disassembly() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA $1234,X", "STA ($20),Y", "BRK"]));
+        cpu.poke(0x8006, 0x02);
+        let path = std::env::temp_dir().join("nes_test_export_disassembly.txt");
+        cpu.export_disassembly(0x8000, 0x8006, &path).unwrap();
+        let listing = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            listing.lines().collect::<Vec<_>>(),
+            vec![
+                "8000  BD 34 12  LDA $1234,X",
+                "8003  91 20     STA ($20),Y",
+                "8005  00        BRK",
+                "8006  02        .byte $02",
+            ]
+        );
+    }
+
+    // Test case for jump and branch targets disassembling as their labels
+    #[test]
+    fn test_disassemble_with_symbols() {
+        let mut cpu = CPU::new();
+        let program = assemble(&["JMP $C003", "LDA $C003", "BNE $FB", "BRK"]);
+        let symbols = BTreeMap::from([(0xc003, "init".to_string())]);
+        cpu.load_with_symbols(program, 0xc000, symbols);
+        assert_eq!(cpu.disassemble_one(0xc000), ("JMP init".to_string(), 3));
+        assert_eq!(cpu.disassemble_one(0xc003), ("LDA $C003".to_string(), 3));
+        assert_eq!(cpu.disassemble_one(0xc006), ("BNE init".to_string(), 2));
+        cpu.reset();
+        assert!(cpu.trace_line().contains("JMP init"));
+    }
+
+    // Test case for interpret() resuming where run_until() stopped
+    #[test]
+    fn test_interpret_resumes_from_program_counter() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&[
+            "LDA #$01", "STA $10", "LDA #$02", "STA $11", "BRK",
+        ]));
+        cpu.reset();
+        cpu.run_until(|cpu| cpu.program_counter == 0x8004).unwrap();
+        cpu.mem_write(0x10, 0x00); // Would be stored again if the program restarted
+        cpu.interpret().unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert_eq!(cpu.mem_read(0x11), 0x02);
+    }
+
+    // Test case for an unknown opcode reporting the instruction executed before it
+    #[test]
+    fn test_unknown_opcode_reports_last_instruction() {
+        let mut cpu = CPU::new();
+        let mut program = assemble(&["LDA #$01", "STA $10"]);
+        program.push(0x02); // Not implemented
+        cpu.load(program);
+        cpu.reset();
+        let error = cpu.interpret().unwrap_err();
+        assert_eq!(
+            error,
+            EmuError::UnknownOpcode {
+                opcode: 0x02,
+                address: 0x8004,
+                last_instruction: Some((0x8002, 0x85)),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "unknown opcode $02 at $8004 after $85 at $8002"
+        );
+        assert_eq!(cpu.program_counter, 0x8004);
+    }
+
+    // Test case for max_instructions stopping an infinite loop after exactly that many
+    #[test]
+    fn test_max_instructions_limit() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["JMP $8000"]));
+        cpu.reset();
+        cpu.max_instructions = Some(100);
+        assert_eq!(cpu.interpret(), Err(EmuError::InstructionLimit));
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 100 * 3);
+        assert!(matches!(
+            cpu.run_frames(10),
+            Err(EmuError::InstructionLimit)
+        ));
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 200 * 3);
+    }
+
+    // Test case for the dummy read of a page-crossing indexed load reaching PPUSTATUS
+    #[test]
+    fn test_indexed_read_page_cross_dummy_read() {
+        let mut cpu = CPU::new();
+        // After the first PPUADDR write the latch expects the low byte, until LDA reads
+        // 0x4002 with a dummy read of 0x3F02 first, which mirrors PPUSTATUS
+        cpu.load(assemble(&[
+            "LDA #$21",
+            "STA $2006",
+            "LDA $3FF2,X",
+            "LDA #$23",
+            "STA $2006",
+            "LDA #$45",
+            "STA $2006",
+            "BRK",
+        ]));
+        cpu.reset();
+        cpu.index_x = 0x10;
+        cpu.run_until(|_| false).unwrap();
+        // The dummy read reset the latch, so these writes form a full address
+        assert_eq!(cpu.bus.ppu.vram_addr(), 0x2345);
+    }
+
+    // Clock shared with the test, which adds up every tick
+    struct CountingClock(Rc<Cell<u64>>);
+
+    impl Clock for CountingClock {
+        fn tick(&mut self, cycles: u64) {
+            self.0.set(self.0.get() + cycles);
+        }
+    }
+
+    // Test case for an installed clock being ticked with every cycle the CPU spends
+    #[test]
+    fn test_clock_receives_elapsed_cycles() {
+        let total = Rc::new(Cell::new(0));
+        let mut cpu = CPU::new();
+        cpu.set_clock(Box::new(CountingClock(total.clone())));
+        cpu.load(assemble(&["LDA #$01", "STA $10", "INC $10", "BRK"]));
+        cpu.reset();
+        cpu.run_until(|_| false).unwrap();
+        assert_eq!(total.get(), INTERRUPT_CYCLES + 2 + 3 + 5 + 7);
+        assert_eq!(total.get(), cpu.cycles);
+    }
+
+    // Test case for mounting an in-memory image and running it, which needs no std
+    #[test]
+    fn test_load_rom_from_memory() {
+        let mut raw = crate::cartridge::test::test_rom_bytes(0x00, 0x00, 1, 1);
+        let program = assemble(&["LDA #$42", "STA $10", "BRK"]);
+        raw[16..16 + program.len()].copy_from_slice(&program);
+        raw[16 + 0x3ffc] = 0x00; // Reset vector 0xC000, the start of the mirrored bank
+        raw[16 + 0x3ffd] = 0xc0;
+
+        let mut cpu = CPU::new();
+        cpu.load_rom(&raw).unwrap();
+        cpu.run_until(|_| false).unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x42);
+    }
+
+    // Test case for reset and NMI each charging 7 cycles
+    #[test]
+    fn test_reset_and_nmi_take_seven_cycles() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["JMP $8000"]));
+        cpu.bus.load_prg(0xFFFA, &[0x00, 0x80]);
+        cpu.reset();
+        assert_eq!(cpu.cycles, 7);
+
+        cpu.bus.ppu.write_to_ctrl(0b1000_0000); // Enable NMI at VBlank
+        while !cpu.bus.ppu.tick(341) {}
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.stack_pointer, STACK_RESET - 3);
+        assert_eq!(cpu.cycles, 7 + 7);
+    }
+
+    // Test case for an entry point overriding the reset vector without rewriting it
+    #[test]
+    fn test_entry_point_overrides_reset_vector() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$01", "BRK"]));
+        cpu.poke_range(0x8010, &assemble(&["LDA #$02", "BRK"]));
+        cpu.set_entry_point(0x8010);
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x8010);
+        cpu.interpret().unwrap();
+        assert_eq!(cpu.accumulator, 0x02);
+        assert_eq!(cpu.last_instruction, Some((0x8012, 0x00)));
+        assert_eq!(cpu.peek(0xFFFC), 0x00);
+        assert_eq!(cpu.peek(0xFFFD), 0x80);
+
+        cpu.clear_entry_point();
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x8000);
+    }
+
+    // LDA in each of its eight addressing modes, with memory set up through poke
+    mod lda {
+        use super::*;
+
+        const ZERO: u8 = 0b0000_0010;
+        const NEGATIVE: u8 = 0b1000_0000;
+
+        // Run one LDA followed by BRK with the given index registers
+        fn run_lda(line: &str, x: u8, y: u8, memory: &[(u16, u8)]) -> CPU {
+            let mut cpu = CPU::new();
+            cpu.load(assemble(&[line, "BRK"]));
+            for &(address, value) in memory {
+                cpu.poke(address, value);
+            }
+            cpu.reset();
+            cpu.index_x = x;
+            cpu.index_y = y;
+            cpu.run_until(|_| false).unwrap();
+            cpu
+        }
+
+        // Test case for immediate mode setting the zero and negative flags
+        #[test]
+        fn test_immediate() {
+            let cpu = run_lda("LDA #$00", 0, 0, &[]);
+            assert_eq!(cpu.accumulator, 0x00);
+            assert_eq!(cpu.status & (ZERO | NEGATIVE), ZERO);
+            let cpu = run_lda("LDA #$80", 0, 0, &[]);
+            assert_eq!(cpu.accumulator, 0x80);
+            assert_eq!(cpu.status & (ZERO | NEGATIVE), NEGATIVE);
+        }
+
+        // Test case for zero page mode
+        #[test]
+        fn test_zero_page() {
+            let cpu = run_lda("LDA $10", 0, 0, &[(0x0010, 0x55)]);
+            assert_eq!(cpu.accumulator, 0x55);
+            assert_eq!(cpu.status & (ZERO | NEGATIVE), 0);
+        }
+
+        // Test case for zero page X mode, including wrapping within page zero
+        #[test]
+        fn test_zero_page_x() {
+            let cpu = run_lda("LDA $10,X", 0x05, 0, &[(0x0015, 0x21)]);
+            assert_eq!(cpu.accumulator, 0x21);
+            let cpu = run_lda("LDA $FF,X", 0x01, 0, &[(0x0000, 0x80), (0x0100, 0x11)]);
+            assert_eq!(cpu.accumulator, 0x80); // 0xFF + 1 stays in page zero
+            assert_eq!(cpu.status & (ZERO | NEGATIVE), NEGATIVE);
+        }
+
+        // Test case for absolute mode
+        #[test]
+        fn test_absolute() {
+            let cpu = run_lda("LDA $0234", 0, 0, &[(0x0234, 0x42)]);
+            assert_eq!(cpu.accumulator, 0x42);
+        }
+
+        // Test case for absolute X mode, including the wrap from 0xFFFF to 0x0000
+        #[test]
+        fn test_absolute_x() {
+            let cpu = run_lda("LDA $01F8,X", 0x10, 0, &[(0x0208, 0x99)]);
+            assert_eq!(cpu.accumulator, 0x99);
+            assert_eq!(cpu.status & (ZERO | NEGATIVE), NEGATIVE);
+            let cpu = run_lda("LDA $FFFF,X", 0x01, 0, &[(0x0000, 0x33)]);
+            assert_eq!(cpu.accumulator, 0x33);
+        }
+
+        // Test case for absolute Y mode, including the wrap from 0xFFFF to 0x0000
+        #[test]
+        fn test_absolute_y() {
+            let cpu = run_lda("LDA $0300,Y", 0, 0x20, &[(0x0320, 0x44)]);
+            assert_eq!(cpu.accumulator, 0x44);
+            let cpu = run_lda("LDA $FFF0,Y", 0, 0x12, &[(0x0002, 0x00)]);
+            assert_eq!(cpu.accumulator, 0x00);
+            assert_eq!(cpu.status & (ZERO | NEGATIVE), ZERO);
+        }
+
+        // Test case for indirect X mode, including a pointer that wraps within page zero
+        #[test]
+        fn test_indirect_x() {
+            let memory = [(0x0024, 0x00), (0x0025, 0x04), (0x0400, 0x66)];
+            let cpu = run_lda("LDA ($20,X)", 0x04, 0, &memory);
+            assert_eq!(cpu.accumulator, 0x66);
+            // 0xFE + 1 gives a pointer at 0xFF whose high byte comes from 0x00
+            let memory = [(0x00ff, 0x10), (0x0000, 0x05), (0x0510, 0x77)];
+            let cpu = run_lda("LDA ($FE,X)", 0x01, 0, &memory);
+            assert_eq!(cpu.accumulator, 0x77);
+        }
+
+        // Test case for indirect Y mode, including pointer wrapping and a page cross
+        #[test]
+        fn test_indirect_y() {
+            let memory = [(0x0040, 0x00), (0x0041, 0x06), (0x0605, 0x12)];
+            let cpu = run_lda("LDA ($40),Y", 0, 0x05, &memory);
+            assert_eq!(cpu.accumulator, 0x12);
+            // Pointer 0x06F0 read from 0xFF and 0x00, plus Y crossing into page 0x07
+            let memory = [(0x00ff, 0xf0), (0x0000, 0x06), (0x0710, 0xfe)];
+            let cpu = run_lda("LDA ($FF),Y", 0, 0x20, &memory);
+            assert_eq!(cpu.accumulator, 0xfe);
+            assert_eq!(cpu.status & (ZERO | NEGATIVE), NEGATIVE);
+        }
+    }
+
+    // Test case for CMP updating carry, zero and negative but never overflow
+    #[test]
+    fn test_cmp_leaves_overflow_flag() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["CMP #$10", "BRK"]));
+        cpu.reset();
+        cpu.accumulator = 0x10;
+        cpu.status |= 0b0100_0000; // Overflow set
+        cpu.run_until(|_| false).unwrap();
+        assert_eq!(cpu.status & 0b1100_0011, 0b0100_0011); // V, Z and C set, N clear
+
+        cpu.reset();
+        cpu.accumulator = 0x00;
+        cpu.run_until(|_| false).unwrap();
+        assert_eq!(cpu.status & 0b1100_0011, 0b1000_0000); // 0x00 - 0x10 = 0xF0: N only
+    }
+
+    // Test case for CPX and CPY comparing against the index registers
+    #[test]
+    fn test_cpx_cpy() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["CPX $10", "BRK"]));
+        cpu.poke(0x10, 0x05);
+        cpu.reset();
+        cpu.index_x = 0x06;
+        cpu.run_until(|_| false).unwrap();
+        assert_eq!(cpu.status & 0b1000_0011, CARRY_FLAG);
+
+        cpu.load(assemble(&["CPY #$07", "BRK"]));
+        cpu.reset();
+        cpu.index_y = 0x06;
+        cpu.run_until(|_| false).unwrap();
+        assert_eq!(cpu.status & 0b1000_0011, 0b1000_0000);
+    }
+
+    // Test case for the trace log holding one nestest-style line per instruction
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_trace_log_to_file() {
+        let path = std::env::temp_dir().join("nes_test_trace_log.txt");
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$01", "STA $10", "BRK"]));
+        cpu.reset();
+        cpu.enable_trace_log(&path).unwrap();
+        cpu.run_until(|_| false).unwrap();
+        cpu.disable_trace_log().unwrap();
+
+        let log = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("8000  A9 01     LDA #$01"));
+        assert!(lines[0].ends_with("A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"));
+        assert!(lines[1].starts_with("8002  85 10     STA $10"));
+        assert!(lines[2].starts_with("8004  00        BRK"));
+    }
+
+    // Run an unofficial immediate opcode with the given A, X and status
+    fn run_immediate(line: &str, a: u8, x: u8, status: u8) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&[line, "BRK"]));
+        cpu.reset();
+        cpu.accumulator = a;
+        cpu.index_x = x;
+        cpu.status = status;
+        cpu.run_until(|_| false).unwrap();
+        cpu
+    }
+
+    // Test case for ANC copying the negative result into carry
+    #[test]
+    fn test_anc() {
+        let cpu = run_immediate("*ANC #$F0", 0x8f, 0, 0);
+        assert_eq!(cpu.accumulator, 0x80);
+        assert_eq!(cpu.status & 0b1000_0011, 0b1000_0001); // N and C
+        let cpu = run_immediate("*ANC #$0F", 0x8f, 0, CARRY_FLAG);
+        assert_eq!(cpu.accumulator, 0x0f);
+        assert_eq!(cpu.status & 0b1000_0011, 0); // Carry cleared with bit 7
+    }
+
+    // Test case for ALR shifting the masked value and carrying out bit 0
+    #[test]
+    fn test_alr() {
+        let cpu = run_immediate("*ALR #$03", 0xff, 0, 0);
+        assert_eq!(cpu.accumulator, 0x01);
+        assert_eq!(cpu.status & 0b1000_0011, CARRY_FLAG);
+        let cpu = run_immediate("*ALR #$01", 0x01, 0, 0);
+        assert_eq!(cpu.accumulator, 0x00);
+        assert_eq!(cpu.status & 0b1000_0011, 0b0000_0011); // Z and C
+    }
+
+    // Test case for ARR taking carry from bit 6 and overflow from bit 6 XOR bit 5
+    #[test]
+    fn test_arr() {
+        // 0xC0 >> 1 with carry in: 0xE0, bits 6 and 5 both set
+        let cpu = run_immediate("*ARR #$FF", 0xc0, 0, CARRY_FLAG);
+        assert_eq!(cpu.accumulator, 0xe0);
+        assert_eq!(cpu.status & 0b1100_0011, 0b1000_0001); // N and C, V clear
+
+        // 0x80 >> 1: 0x40, bit 6 set and bit 5 clear
+        let cpu = run_immediate("*ARR #$FF", 0x80, 0, 0);
+        assert_eq!(cpu.accumulator, 0x40);
+        assert_eq!(cpu.status & 0b1100_0011, 0b0100_0001); // V and C
+
+        // 0x40 >> 1: 0x20, bit 5 set alone
+        let cpu = run_immediate("*ARR #$FF", 0x40, 0, 0);
+        assert_eq!(cpu.accumulator, 0x20);
+        assert_eq!(cpu.status & 0b1100_0011, 0b0100_0000); // V only
+    }
+
+    // Test case for AXS subtracting from A AND X into X like a compare
+    #[test]
+    fn test_axs() {
+        let cpu = run_immediate("*AXS #$02", 0xf3, 0x3f, 0b0100_0000);
+        assert_eq!(cpu.index_x, 0x31);
+        assert_eq!(cpu.accumulator, 0xf3);
+        assert_eq!(cpu.status & 0b1100_0011, 0b0100_0001); // Carry set, V untouched
+        let cpu = run_immediate("*AXS #$10", 0x0f, 0xff, 0);
+        assert_eq!(cpu.index_x, 0xff);
+        assert_eq!(cpu.status & 0b1000_0011, 0b1000_0000); // Borrow: carry clear
+    }
+
+    // Test case for frame statistics recording about 29780 cycles per NTSC frame
+    #[test]
+    fn test_frame_stats_cycles_per_frame() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["JMP $8000"]));
+        cpu.reset();
+        assert!(cpu.frame_stats().is_empty());
+        cpu.record_frame_stats(true);
+        cpu.run_frames(5).unwrap();
+
+        let stats = cpu.frame_stats();
+        assert_eq!(stats.len(), 5);
+        // The first frame started part-way, at reset; a frame ends with the
+        // instruction during which VBlank begins, so each is within a JMP of 29780.67
+        for stat in &stats[1..] {
+            assert!((29778..=29784).contains(&stat.cycles), "{:?}", stat);
+            assert_eq!(stat.instructions * 3, stat.cycles);
+        }
+    }
+
+    // Test case for reset clearing decimal mode and disabling interrupts
+    #[test]
+    fn test_reset_clears_decimal_and_sets_interrupt_disable() {
+        let mut cpu = crate::builder::EmulatorBuilder::new()
+            .decimal_mode(true)
+            .build();
+        cpu.status = DECIMAL_FLAG;
+        cpu.reset();
+        assert_eq!(cpu.status & DECIMAL_FLAG, 0);
+        assert_eq!(cpu.status & INTERRUPT_DISABLE_FLAG, INTERRUPT_DISABLE_FLAG);
+    }
+
+    // Test case for the memory trace recording each access of a store and a load
+    #[test]
+    fn test_mem_trace_store_then_load() {
+        use crate::bus::AccessKind::{Read, Write};
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$42", "STA $10", "LDA $10", "BRK"]));
+        cpu.reset();
+        cpu.bus.start_mem_trace();
+        cpu.run_until(|_| false).unwrap();
+        assert_eq!(
+            cpu.bus.take_mem_trace(),
+            vec![
+                (0x8000, 0xa9, Read),
+                (0x8001, 0x42, Read),
+                (0x8002, 0x85, Read),
+                (0x8003, 0x10, Read),
+                (0x0010, 0x42, Write),
+                (0x8004, 0xa5, Read),
+                (0x8005, 0x10, Read),
+                (0x0010, 0x42, Read),
+                (0x8006, 0x00, Read),
+            ]
+        );
+        assert!(cpu.bus.take_mem_trace().is_empty()); // Taking the trace ends it
+    }
+
+    // Test case for diff() reporting a register, its flags and runs of memory
+    #[test]
+    fn test_diff_reports_registers_and_memory() {
+        let mut cpu = CPU::new();
+        let mut other = CPU::new();
+        assert!(cpu.diff(&other).is_empty());
+
+        cpu.accumulator = 0x80;
+        cpu.status |= 0b1000_0000;
+        cpu.poke(0x0010, 0x42);
+        other.poke_range(0x0300, &[1, 2, 3]);
+        assert_eq!(
+            cpu.diff(&other),
+            vec![
+                "A: 80 != 00".to_string(),
+                "P: A4 != 24".to_string(),
+                "flag N: 1 != 0".to_string(),
+                "$0010: 42 != 00".to_string(),
+                "$0300-$0302: 3 bytes differ".to_string(),
+            ]
+        );
+    }
+
+    // Expected trace of the first three instructions of compare_program()
+    const COMPARE_TRACE: [&str; 3] = [
+        "8000  A9 01     LDA #$01                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+        "8002  85 10     STA $10                         A:01 X:00 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9",
+        "8004  E6 10     INC $10                         A:01 X:00 Y:00 P:24 SP:FD PPU:  0, 36 CYC:12",
+    ];
+
+    fn compare_program() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$01", "STA $10", "INC $10", "BRK"]));
+        cpu.reset();
+        cpu
+    }
+
+    // Test case for a run matching its expected trace
+    #[test]
+    fn test_run_and_compare_matches() {
+        let mut cpu = compare_program();
+        assert_eq!(cpu.run_and_compare(&COMPARE_TRACE, 10), Ok(()));
+        assert_eq!(cpu.mem_read(0x10), 0x02);
+    }
+
+    // Test case for the first mismatching line being reported with both versions
+    #[test]
+    fn test_run_and_compare_reports_first_mismatch() {
+        let mut cpu = compare_program();
+        let mut trace = COMPARE_TRACE;
+        let wrong = COMPARE_TRACE[2].replace("A:01", "A:02");
+        trace[2] = &wrong;
+        assert_eq!(
+            cpu.run_and_compare(&trace, 10),
+            Err((2, wrong.clone(), COMPARE_TRACE[2].to_string()))
+        );
+        assert_eq!(cpu.program_counter, 0x8004); // Stopped before the mismatching line
+    }
+
+    // Test case for ASL through modify_memory matching a hand-written read-modify-write
+    #[test]
+    fn test_modify_memory_asl_matches_hand_written() {
+        let mut via_helper = CPU::new();
+        via_helper.bus.mem_write(0x0010, 0b1100_0001);
+        via_helper.load(vec![0x06, 0x10, 0x00]); // ASL $10
+        via_helper.reset();
+        via_helper.bus.start_mem_trace();
+        via_helper.step().unwrap();
+        let helper_trace = via_helper.bus.take_mem_trace();
+
+        let mut by_hand = CPU::new();
+        by_hand.bus.mem_write(0x0010, 0b1100_0001);
+        by_hand.reset();
+        by_hand.bus.start_mem_trace();
+        let value = by_hand.mem_read(0x0010);
+        by_hand.mem_write(0x0010, value);
+        by_hand.set_carry(value & 0b1000_0000 != 0);
+        by_hand.mem_write(0x0010, value << 1);
+        by_hand.update_flags(value << 1);
+        let hand_trace = by_hand.bus.take_mem_trace();
+
+        assert_eq!(via_helper.mem_read(0x0010), 0b1000_0010);
+        assert_eq!(via_helper.mem_read(0x0010), by_hand.mem_read(0x0010));
+        assert_eq!(via_helper.status, by_hand.status);
+        // Skip the opcode and operand fetches, which the hand-written version does not do
+        assert_eq!(helper_trace[2..], hand_trace[..]);
+    }
+
+    // Test case for the zero-page read-modify-write fast path matching the generic path:
+    // the result, the flags, the dummy write and the memory trace and events around it
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_zero_page_rmw_matches_generic_path() {
+        let run = |mode: &AddressingMode, generic: bool| {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let mut cpu = CPU::new();
+            cpu.program_counter = 0x0300;
+            cpu.mem_write(0x0300, 0xFF); // ZeroPageX wraps to 0x01
+            cpu.index_x = 0x02;
+            cpu.mem_write(0x00FF, 0b1000_0001);
+            cpu.mem_write(0x0001, 0b1000_0001);
+            cpu.bus.add_write_watchpoint(0x00FF);
+            cpu.bus.add_write_watchpoint(0x0001);
+            cpu.status |= CARRY_FLAG;
+            cpu.bus.start_mem_trace();
+            cpu.set_event_sink(Some(sender));
+            let rol = |cpu: &mut CPU, value: u8| {
+                let carry_in = cpu.status & CARRY_FLAG;
+                cpu.set_carry(value & 0b1000_0000 != 0);
+                (value << 1) | carry_in
+            };
+            let result = if generic {
+                cpu.modify_memory_generic(mode, rol)
+            } else {
+                cpu.modify_memory(mode, rol)
+            };
+            cpu.set_event_sink(None);
+            let events: Vec<EmuEvent> = receiver.try_iter().collect();
+            (
+                result,
+                cpu.status,
+                [cpu.peek(0x00FF), cpu.peek(0x0001)],
+                cpu.bus.watch_hits.clone(),
+                cpu.bus.take_mem_trace(),
+                events,
+            )
+        };
+        for mode in [AddressingMode::ZeroPage, AddressingMode::ZeroPageX] {
+            let fast = run(&mode, false);
+            assert_eq!(fast, run(&mode, true));
+            assert_eq!(fast.0, 0b0000_0011);
+            assert_eq!(fast.3.len(), 2); // The dummy write precedes the result
+            assert_eq!(fast.3[0].1, 0b1000_0001);
+        }
+    }
+
+    // Test case for a requested NMI vectoring through 0xFFFA and pushing PC and status
+    #[test]
+    fn test_request_nmi() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$01", "LDA #$02", "BRK"]));
+        cpu.bus.load_prg(0xFFFA, &[0x00, 0x90]);
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.request_nmi();
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.stack_pointer, STACK_RESET - 3);
+        // Return address high, then low, then the status with B clear and bit 5 set
+        assert_eq!(cpu.mem_read(0x01fd), 0x80);
+        assert_eq!(cpu.mem_read(0x01fc), 0x02);
+        assert_eq!(cpu.mem_read(0x01fb), 0b0010_0100);
+        assert_eq!(cpu.status & INTERRUPT_DISABLE_FLAG, INTERRUPT_DISABLE_FLAG);
+    }
+
+    // Test case for BRK vectoring through 0xFFFE with B set in the pushed status
+    #[test]
+    fn test_brk_interrupt() {
+        let mut cpu = CPU::new();
+        cpu.brk_halts = false;
+        cpu.load(vec![0x00, 0xff, 0x00]);
+        cpu.bus.load_prg(0xFFFE, &[0x00, 0xA0]);
+        cpu.reset();
+        assert!(cpu.step().unwrap());
+        assert_eq!(cpu.program_counter, 0xA000);
+        assert_eq!(cpu.cycles, 2 * INTERRUPT_CYCLES);
+        // The return address skips the padding byte
+        assert_eq!(cpu.mem_read(0x01fd), 0x80);
+        assert_eq!(cpu.mem_read(0x01fc), 0x02);
+        assert_eq!(cpu.mem_read(0x01fb), 0b0011_0100);
+    }
+
+    // Test case for an NMI arriving during BRK hijacking it through 0xFFFA
+    #[test]
+    fn test_nmi_hijacks_brk() {
+        let mut cpu = CPU::new();
+        cpu.brk_halts = false;
+        cpu.load(vec![0x00, 0xff, 0x00]);
+        cpu.bus.load_prg(0xFFFA, &[0x00, 0x90]);
+        cpu.bus.load_prg(0xFFFE, &[0x00, 0xA0]);
+        cpu.bus.load_prg(0x9000, &[0xa9, 0x01]);
+        cpu.reset();
+        // VBlank, and with it the NMI, begins a few dots into the BRK
+        cpu.bus.ppu.write_to_ctrl(0b1000_0000);
+        cpu.bus.ppu.scanline = 240;
+        cpu.bus.ppu.cycles = 330;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x9000);
+        // Only one interrupt was taken, and the pushed status still shows a BRK
+        assert_eq!(cpu.stack_pointer, STACK_RESET - 3);
+        assert_eq!(cpu.mem_read(0x01fb) & BREAK_FLAG, BREAK_FLAG);
+        cpu.step().unwrap(); // LDA #$01 in the handler, with no NMI left pending
+        assert_eq!(cpu.program_counter, 0x9002);
+        assert_eq!(cpu.stack_pointer, STACK_RESET - 3);
+    }
+
+    // Test case for an implied instruction reading the byte after its opcode
+    #[test]
+    fn test_implied_dummy_read() {
+        use crate::bus::AccessKind::Read;
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["INX", "SED", "BRK"]));
+        cpu.reset();
+        cpu.bus.start_mem_trace();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.bus.take_mem_trace(),
+            vec![
+                (0x8000, 0xe8, Read),
+                (0x8001, 0xf8, Read), // Dummy read of the next opcode
+                (0x8001, 0xf8, Read),
+                (0x8002, 0x00, Read),
+            ]
+        );
+        assert_eq!(cpu.program_counter, 0x8002);
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 4);
+    }
+
+    // Test case for a debugger on the other end of a channel receiving the event stream
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_event_stream() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$42", "STA $10", "JMP $8004"]));
+        cpu.reset();
+        cpu.bus.ppu.write_to_ctrl(0b1000_0000); // NMI at VBlank
+        cpu.bus.load_prg(0xFFFA, &[0x04, 0x80]);
+        cpu.set_event_sink(Some(sender));
+        cpu.add_breakpoint(0x8002);
+        cpu.run_frame().unwrap();
+        cpu.step().unwrap();
+        cpu.set_event_sink(None);
+
+        let events: Vec<EmuEvent> = receiver.try_iter().collect();
+        assert_eq!(
+            events[..4],
+            [
+                EmuEvent::InstructionExecuted {
+                    address: 0x8000,
+                    opcode: 0xa9,
+                    cycles: 2
+                },
+                EmuEvent::Breakpoint { address: 0x8002 },
+                EmuEvent::MemoryWrite {
+                    address: 0x0010,
+                    value: 0x42
+                },
+                EmuEvent::InstructionExecuted {
+                    address: 0x8002,
+                    opcode: 0x85,
+                    cycles: 3
+                },
+            ]
+        );
+        let frame = events
+            .iter()
+            .position(|event| *event == EmuEvent::FrameComplete { frame: 1 })
+            .unwrap();
+        // The NMI pushes the return address and status like any other write
+        assert_eq!(events[frame + 1], EmuEvent::NmiFired);
+        assert_eq!(events.len(), frame + 5);
+        assert!(events[frame + 2..].iter().all(|event| matches!(
+            event,
+            EmuEvent::MemoryWrite {
+                address: 0x01fb..=0x01fd,
+                ..
+            }
+        )));
+        // The sink is gone, so nothing else arrives
+        cpu.step().unwrap();
+        assert!(receiver.try_recv().is_err());
+    }
+
+    // Test case for a requested IRQ waiting for the interrupt-disable flag to clear
+    #[test]
+    fn test_request_irq() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$01", "LDA #$02", "BRK"]));
+        cpu.bus.load_prg(0xFFFE, &[0x00, 0xA0]);
+        cpu.reset();
+        cpu.request_irq();
+        cpu.step().unwrap(); // Interrupts are disabled out of reset
+        assert_eq!(cpu.program_counter, 0x8002);
+
+        cpu.status &= !INTERRUPT_DISABLE_FLAG;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0xA000);
+        assert_eq!(cpu.mem_read(0x01fd), 0x80);
+        assert_eq!(cpu.mem_read(0x01fc), 0x02);
+        assert_eq!(cpu.mem_read(0x01fb), 0b0010_0000);
+        assert!(!cpu.bus.irq_pending()); // The request was used up
+    }
+
+    // Test case for SED setting the D flag without changing ADC's binary sum on the 2A03
+    #[test]
+    fn test_adc_ignores_decimal_flag_on_nes() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["SED", "LDA #$28", "ADC #$19", "BRK"]));
+        cpu.reset();
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.accumulator, 0x41); // 0x47 in decimal
+        assert_eq!(cpu.status & DECIMAL_FLAG, DECIMAL_FLAG);
+        assert_eq!(cpu.status & CARRY_FLAG, 0);
+    }
+
+    // Test case for CLD clearing the D flag
+    #[test]
+    fn test_cld_clears_decimal_flag() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["SED", "CLD", "BRK"]));
+        cpu.reset();
+        cpu.step().unwrap();
+        assert_eq!(cpu.status & DECIMAL_FLAG, DECIMAL_FLAG);
+        cpu.step().unwrap();
+        assert_eq!(cpu.status & DECIMAL_FLAG, 0);
+    }
+
+    // Test case for ADC and SBC carry, overflow and borrow in binary mode
+    #[test]
+    fn test_adc_sbc_binary_flags() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$7F", "ADC #$01", "SBC #$01", "BRK"]));
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.accumulator, 0x80);
+        assert_eq!(cpu.status & 0b1100_0001, 0b1100_0000); // N and V, no carry
+
+        // With carry clear SBC also takes the borrow: 0x80 - 0x01 - 1
+        cpu.step().unwrap();
+        assert_eq!(cpu.accumulator, 0x7e);
+        assert_eq!(cpu.status & 0b1100_0001, 0b0100_0001); // V, and carry for no borrow
+    }
+
+    // Test case for a general 6502 with decimal mode honoured adding and subtracting BCD
+    #[test]
+    fn test_decimal_adc_sbc_when_enabled() {
+        let mut cpu = crate::builder::EmulatorBuilder::new()
+            .decimal_mode(true)
+            .build();
+        cpu.load(assemble(&[
+            "SED", "LDA #$28", "ADC #$19", "SBC #$48", "BRK",
+        ]));
+        cpu.reset();
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.accumulator, 0x47);
+        assert_eq!(cpu.status & CARRY_FLAG, 0);
+        cpu.step().unwrap(); // 0x47 - 0x48 - 1 borrows
+        assert_eq!(cpu.accumulator, 0x98);
+        assert_eq!(cpu.status & CARRY_FLAG, 0);
+    }
+
+    // Test case for LSR A shifting 0x01 out to a zero result with carry set
+    #[test]
+    fn test_lsr_accumulator_to_zero() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$01", "LSR", "BRK"]));
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.accumulator, 0);
+        assert_eq!(cpu.status & CARRY_FLAG, CARRY_FLAG);
+        assert_eq!(cpu.status & 0b1000_0010, 0b0000_0010); // Zero set, negative clear
+        assert_eq!(cpu.program_counter, 0x8003);
+    }
+
+    // Test case for ROR A rotating the carry into bit 7 of a zero accumulator
+    #[test]
+    fn test_ror_accumulator_carry_into_bit_7() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$00", "ROR", "BRK"]));
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.status |= CARRY_FLAG;
+        cpu.step().unwrap();
+        assert_eq!(cpu.accumulator, 0x80);
+        assert_eq!(cpu.status & CARRY_FLAG, 0);
+        assert_eq!(cpu.status & 0b1000_0010, 0b1000_0000); // Negative set, zero clear
+    }
+
+    // Test case for restoring a PPU snapshot after VRAM, palette and scroll change
+    #[test]
+    fn test_ppu_snapshot_round_trip() {
+        let mut cpu = CPU::new();
+        cpu.bus.mem_write(0x2006, 0x20);
+        cpu.bus.mem_write(0x2006, 0x10);
+        cpu.bus.mem_write(0x2007, 0x5a);
+        cpu.bus.mem_write(0x2005, 0x18);
+        cpu.bus.ppu.palette_table[3] = 0x21;
+        let snap = cpu.ppu_snapshot();
+
+        cpu.bus.ppu.vram[0x10] = 0;
+        cpu.bus.ppu.palette_table[3] = 0;
+        cpu.bus.mem_write(0x2005, 0x40);
+        cpu.bus.ppu.oam_data[0] = 0x99;
+        cpu.mem_write(0x0000, 0x77); // CPU memory is not part of the snapshot
+
+        cpu.restore_ppu(snap).unwrap();
+        assert_eq!(cpu.bus.ppu.vram[0x10], 0x5a);
+        assert_eq!(cpu.bus.ppu.palette_table[3], 0x21);
+        assert_eq!(cpu.bus.ppu.oam_data[0], 0);
+        assert_eq!(cpu.bus.ppu.scroll(), (0x18, 0));
+        assert_eq!(cpu.bus.ppu.vram_addr(), 0x2011);
+        assert_eq!(cpu.mem_read(0x0000), 0x77);
+    }
+
+    // Test case for an IndirectY read paying a cycle only when adding Y crosses a page
+    #[test]
+    fn test_indirect_y_page_cross_cycle() {
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&[
+            "LDA ($FF),Y",
+            "LDA ($FF),Y",
+            "STA ($FF),Y",
+            "BRK",
+        ]));
+        cpu.reset();
+        // The pointer straddles the end of page zero, which costs nothing
+        cpu.mem_write(0x00ff, 0xf0);
+        cpu.mem_write(0x0000, 0x02);
+        cpu.mem_write(0x02f8, 0x11);
+        cpu.mem_write(0x0310, 0x22);
+
+        cpu.index_y = 0x08;
+        cpu.step().unwrap();
+        assert_eq!(cpu.accumulator, 0x11);
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 5);
+
+        cpu.index_y = 0x20; // 0x02F0 + 0x20 crosses into page 3
+        cpu.step().unwrap();
+        assert_eq!(cpu.accumulator, 0x22);
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 5 + 6);
+
+        // Stores always take the extra cycle, crossing or not
+        cpu.index_y = 0x08;
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles, INTERRUPT_CYCLES + 5 + 6 + 6);
+    }
+
+    // Test case for OAM DMA copying a page and stalling 513 or 514 cycles by parity
+    #[test]
+    fn test_oam_dma_stall_by_cycle_parity() {
+        // Reset (7) + LDA zero page (3) + STA (4) ends on an even cycle
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA $00", "STA $4014", "BRK"]));
+        cpu.reset();
+        for offset in 0..=255u8 {
+            cpu.mem_write(0x0200 + offset as u16, offset ^ 0xff);
+        }
+        cpu.mem_write(0x0000, 0x02);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles, 14 + 513);
+        assert_eq!(cpu.bus.ppu.oam_data[0], 0xff);
+        assert_eq!(cpu.bus.ppu.oam_data[255], 0x00);
+
+        // Reset (7) + LDA immediate (2) + STA (4) ends on an odd cycle
+        let mut cpu = CPU::new();
+        cpu.load(assemble(&["LDA #$02", "STA $4014", "BRK"]));
+        cpu.reset();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles, 13 + 514);
     }
 }
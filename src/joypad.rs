@@ -0,0 +1,170 @@
+use crate::error::EmuError;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+
+// Buttons on a standard controller, in the order they are shifted out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoypadButton {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl JoypadButton {
+    // Button at a position in the shift order, for frontends that pass plain numbers
+    pub fn from_index(index: u8) -> Option<JoypadButton> {
+        const BUTTONS: [JoypadButton; 8] = [
+            JoypadButton::A,
+            JoypadButton::B,
+            JoypadButton::Select,
+            JoypadButton::Start,
+            JoypadButton::Up,
+            JoypadButton::Down,
+            JoypadButton::Left,
+            JoypadButton::Right,
+        ];
+        BUTTONS.get(index as usize).copied()
+    }
+
+    // Bit of the shift register holding this button's state
+    fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+}
+
+// Define the Joypad struct
+pub struct Joypad {
+    strobe: bool,      // Strobe bit written through 0x4016
+    button_index: u8,  // Next button to be shifted out
+    button_status: u8, // Pressed buttons, one bit per button
+    latched: u8,       // Buttons captured when strobe fell, shifted out by reads
+}
+
+impl Joypad {
+    // Constructor to create a new Joypad instance
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            button_status: 0,
+            latched: 0,
+        }
+    }
+
+    // Handle a write to the controller port
+    // The buttons are latched when strobe goes from high to low
+    pub fn write(&mut self, data: u8) {
+        let strobe = data & 1 == 1;
+        if self.strobe && !strobe {
+            self.latched = self.button_status;
+            self.button_index = 0; // Restart the shift sequence from the A button
+        }
+        self.strobe = strobe;
+    }
+
+    // Handle a read from the controller port, shifting out one button per read
+    // While strobe is high nothing shifts and every read reports the live A button
+    // Once all 8 buttons are out, an official controller's shift register has filled
+    // with 1s from its serial input, so every further read returns 1
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.button_status & JoypadButton::A.bit();
+        }
+        if self.button_index > 7 {
+            return 1;
+        }
+        let response = (self.latched >> self.button_index) & 1;
+        self.button_index += 1;
+        response
+    }
+
+    // Press or release a button
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        if pressed {
+            self.button_status |= button.bit();
+        } else {
+            self.button_status &= !button.bit();
+        }
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SaveState for Joypad {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.strobe);
+        writer.u8(self.button_index);
+        writer.u8(self.button_status);
+        writer.u8(self.latched);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        self.strobe = reader.bool()?;
+        self.button_index = savestate::in_range("button index", reader.u8()?, 0..=8)?;
+        self.button_status = reader.u8()?;
+        self.latched = reader.u8()?;
+        Ok(())
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Test case for shifting out the button states in order after a strobe
+    #[test]
+    fn test_strobe_then_shift_buttons() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::A, true);
+        joypad.set_button_pressed_status(JoypadButton::Start, true);
+        joypad.write(1);
+        joypad.write(0);
+        let reads: Vec<u8> = (0..8).map(|_| joypad.read()).collect();
+        assert_eq!(reads, vec![1, 0, 0, 1, 0, 0, 0, 0]);
+    }
+
+    // Test case for reads reporting the live A button while strobe is held high
+    #[test]
+    fn test_strobe_high_reads_live_a_button() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        joypad.set_button_pressed_status(JoypadButton::B, true);
+        assert_eq!(joypad.read(), 0);
+        joypad.set_button_pressed_status(JoypadButton::A, true);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1);
+        joypad.set_button_pressed_status(JoypadButton::A, false);
+        assert_eq!(joypad.read(), 0);
+
+        // Clearing strobe latches the buttons, so later presses do not show up
+        joypad.write(0);
+        joypad.set_button_pressed_status(JoypadButton::Start, true);
+        let reads: Vec<u8> = (0..8).map(|_| joypad.read()).collect();
+        assert_eq!(reads, vec![0, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    // Test case for reads past the 8th button returning 1 until the next strobe
+    #[test]
+    fn test_reads_after_eighth_return_one() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::Select, true);
+        joypad.write(1);
+        joypad.write(0);
+        let reads: Vec<u8> = (0..12).map(|_| joypad.read()).collect();
+        assert_eq!(reads, vec![0, 0, 1, 0, 0, 0, 0, 0, 1, 1, 1, 1]);
+
+        // A new strobe restarts the sequence
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 0);
+    }
+}
@@ -0,0 +1,30 @@
+// The emulator core needs only core and alloc; file loading comes with the "std" feature
+// Tests link std for the harness, but still see the library without the feature
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod apu;
+#[cfg(test)]
+pub mod assembler;
+pub mod audio;
+pub mod builder;
+pub mod bus;
+pub mod cartridge;
+pub mod clock;
+pub mod debugger;
+pub mod error;
+pub mod event;
+pub mod frame;
+pub mod hardware;
+pub mod joypad;
+pub mod log;
+pub mod mapper;
+pub mod movie;
+pub mod opcodes;
+pub mod palette;
+pub mod ppu;
+pub mod random;
+pub mod render;
+pub mod savestate;
+pub mod wasm;
@@ -0,0 +1,213 @@
+// Diagnostic messages from the emulator core, raised with log::warn!/debug!/trace!
+// With the "log" feature they go through the log crate, so env_logger or any other
+// logger receives them. Without it the fallback below stands in with the same shape (levels,
+// a Log trait installed once per process), holding its logger under std only. Either way
+// messages are passed as fmt::Arguments and nothing is formatted or allocated unless the
+// level is enabled
+
+#[cfg(not(feature = "log"))]
+pub use fallback::*;
+
+#[cfg(not(feature = "log"))]
+mod fallback {
+    use core::fmt;
+    use core::sync::atomic::{AtomicU8, Ordering};
+    #[cfg(feature = "std")]
+    use std::sync::OnceLock;
+
+    // Severity of a message, from most to least important
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum Level {
+        Error = 1,
+        Warn,
+        Info,
+        Debug,
+        Trace,
+    }
+
+    // Receiver of the messages that pass the level filter
+    pub trait Log: Sync + Send {
+        fn log(&self, level: Level, args: fmt::Arguments);
+    }
+
+    // Returned by set_logger when a logger is already installed
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SetLoggerError;
+
+    // Most verbose level delivered, as a Level discriminant; 0 turns logging off
+    static MAX_LEVEL: AtomicU8 = AtomicU8::new(0);
+    #[cfg(feature = "std")]
+    static LOGGER: OnceLock<&'static dyn Log> = OnceLock::new();
+
+    // Install the logger receiving every message up to max_level, once per process
+    #[cfg(feature = "std")]
+    pub fn set_logger(logger: &'static dyn Log, max_level: Level) -> Result<(), SetLoggerError> {
+        LOGGER.set(logger).map_err(|_| SetLoggerError)?;
+        set_max_level(Some(max_level));
+        Ok(())
+    }
+
+    // Change the verbosity of the installed logger; None stops all messages
+    pub fn set_max_level(max_level: Option<Level>) {
+        MAX_LEVEL.store(max_level.map_or(0, |level| level as u8), Ordering::Relaxed);
+    }
+
+    // Whether messages at a level would reach the logger
+    pub fn enabled(level: Level) -> bool {
+        level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+    }
+
+    // Hand a message to the logger, called through the macros below
+    pub fn log(level: Level, args: fmt::Arguments) {
+        if !enabled(level) {
+            return;
+        }
+        #[cfg(feature = "std")]
+        if let Some(logger) = LOGGER.get() {
+            logger.log(level, args);
+        }
+        #[cfg(not(feature = "std"))]
+        let _ = args;
+    }
+}
+
+#[cfg(feature = "log")]
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        ::log::warn!($($arg)+)
+    };
+}
+
+#[cfg(feature = "log")]
+macro_rules! log_debug {
+    ($($arg:tt)+) => {
+        ::log::debug!($($arg)+)
+    };
+}
+
+#[cfg(feature = "log")]
+macro_rules! log_trace {
+    ($($arg:tt)+) => {
+        ::log::trace!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Warn, format_args!($($arg)+))
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log_debug {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Debug, format_args!($($arg)+))
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log_trace {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Trace, format_args!($($arg)+))
+    };
+}
+
+// Re-exported under the log crate's names; callers write log::warn!(...)
+pub(crate) use {log_debug as debug, log_trace as trace, log_warn as warn};
+
+// Unit test module
+#[cfg(all(test, feature = "std", not(feature = "log")))]
+mod test {
+    use super::*;
+    use crate::hardware::CPU;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use core::fmt;
+    use std::sync::Mutex;
+
+    // Logger keeping every message it is given
+    struct CapturingLogger {
+        messages: Mutex<Vec<(Level, String)>>,
+    }
+
+    impl Log for CapturingLogger {
+        fn log(&self, level: Level, args: fmt::Arguments) {
+            self.messages
+                .lock()
+                .unwrap()
+                .push((level, args.to_string()));
+        }
+    }
+
+    static CAPTURE: CapturingLogger = CapturingLogger {
+        messages: Mutex::new(Vec::new()),
+    };
+
+    // Test case for an unknown opcode being reported as a warning before the error
+    #[test]
+    fn test_unknown_opcode_logs_warning() {
+        set_logger(&CAPTURE, Level::Warn).unwrap();
+        assert_eq!(set_logger(&CAPTURE, Level::Trace), Err(SetLoggerError));
+        let mut cpu = CPU::new();
+        assert!(cpu.load_and_interpret(vec![0x02]).is_err());
+
+        let messages = CAPTURE.messages.lock().unwrap();
+        assert!(messages
+            .iter()
+            .any(|(level, message)| *level == Level::Warn && message.contains("$02")));
+        // Trace messages are filtered out at this level
+        assert!(messages.iter().all(|(level, _)| *level <= Level::Warn));
+        assert!(!enabled(Level::Trace));
+    }
+}
+
+// Unit test module for the log crate path, run with --features log
+#[cfg(all(test, feature = "std", feature = "log"))]
+mod log_crate_test {
+    use crate::hardware::CPU;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use std::sync::Mutex;
+
+    // log::Log implementation keeping every record it is given
+    struct CapturingLogger {
+        messages: Mutex<Vec<(::log::Level, String)>>,
+    }
+
+    impl ::log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &::log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &::log::Record) {
+            self.messages
+                .lock()
+                .unwrap()
+                .push((record.level(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: CapturingLogger = CapturingLogger {
+        messages: Mutex::new(Vec::new()),
+    };
+
+    // Test case for an unknown opcode reaching a log crate logger as a warning
+    #[test]
+    fn test_unknown_opcode_logs_warning() {
+        ::log::set_logger(&CAPTURE).unwrap();
+        ::log::set_max_level(::log::LevelFilter::Warn);
+        let mut cpu = CPU::new();
+        assert!(cpu.load_and_interpret(vec![0x02]).is_err());
+
+        let messages = CAPTURE.messages.lock().unwrap();
+        assert!(messages
+            .iter()
+            .any(|(level, message)| *level == ::log::Level::Warn && message.contains("$02")));
+        assert!(messages
+            .iter()
+            .all(|(level, _)| *level <= ::log::Level::Warn));
+    }
+}
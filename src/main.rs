@@ -1,5 +1,3 @@
-pub mod hardware;
-
 fn main() {
     println!("Hello, world!");
 }
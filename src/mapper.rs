@@ -0,0 +1,568 @@
+use crate::cartridge::{Mirroring, Rom};
+use crate::error::EmuError;
+use crate::log;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::cell::RefCell;
+
+// Cartridge hardware that decides what the CPU sees at 0x8000-0xFFFF
+// Save states tag a mapper's state with its number, see create_mapper
+pub trait Mapper: SaveState {
+    // iNES mapper number this implementation handles
+    fn number(&self) -> u16;
+
+    // Read a byte of PRG ROM at a CPU address in 0x8000-0xFFFF
+    fn read_prg(&self, address: u16) -> u8;
+
+    // Handle a CPU write to 0x8000-0xFFFF, which reaches the mapper's registers
+    fn write_prg(&mut self, address: u16, data: u8);
+
+    // Overwrite a byte of PRG ROM, for loading programs without a cartridge image
+    fn load_prg(&mut self, address: u16, data: u8);
+
+    // Index of the selected switchable 16KB PRG bank, for debuggers
+    fn prg_bank(&self) -> usize {
+        0
+    }
+
+    // Index of the selected CHR bank, for debuggers
+    fn chr_bank(&self) -> usize {
+        0
+    }
+
+    // Offsets into CHR ROM seen by the PPU at 0x0000 and 0x1000
+    fn chr_banks(&self) -> [usize; 2] {
+        [0, 0x1000]
+    }
+
+    // Nametable mirroring, when the mapper controls it rather than the board wiring
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+}
+
+// Builds a mapper from the cartridge it is wired to
+pub type MapperFactory = Box<dyn Fn(Rom) -> Box<dyn Mapper>>;
+
+// Mappers supplied from outside the crate, by iNES mapper number
+// Factories are shared so a bus can hand the set on to the next cartridge it loads
+pub type CustomMappers = BTreeMap<u16, Rc<dyn Fn(Rom) -> Box<dyn Mapper>>>;
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    // Mappers registered with register_mapper, copied into each console made on the thread
+    static THREAD_MAPPERS: RefCell<CustomMappers> = RefCell::new(CustomMappers::new());
+}
+
+// Plug in a mapper implementation for an iNES mapper number on every console created on
+// this thread afterwards, for code with no builder at hand
+// Consoles keep their own copy, so they are not affected by later registrations;
+// EmulatorBuilder::mapper and Bus::register_mapper register on one console alone
+#[cfg(feature = "std")]
+pub fn register_mapper(number: u16, factory: MapperFactory) {
+    THREAD_MAPPERS.with(|mappers| mappers.borrow_mut().insert(number, Rc::from(factory)));
+}
+
+// Mappers registered on this thread with register_mapper
+pub fn thread_mappers() -> CustomMappers {
+    #[cfg(feature = "std")]
+    return THREAD_MAPPERS.with(|mappers| mappers.borrow().clone());
+    #[cfg(not(feature = "std"))]
+    CustomMappers::new()
+}
+
+// Build the mapper for an iNES mapper number
+pub fn create_mapper(number: u16, rom: Rom) -> Result<Box<dyn Mapper>, EmuError> {
+    match number {
+        0 => Ok(Box::new(Nrom::new(rom))),
+        1 => Ok(Box::new(Mmc1::new(rom))),
+        2 => Ok(Box::new(Uxrom::new(rom))),
+        _ => Err(EmuError::UnsupportedMapper(number)),
+    }
+}
+
+// Build the mapper for an iNES mapper number, preferring a custom mapper registered
+// for it over the built-in one
+pub fn create_mapper_with(
+    custom: &CustomMappers,
+    number: u16,
+    rom: Rom,
+) -> Result<Box<dyn Mapper>, EmuError> {
+    match custom.get(&number) {
+        Some(factory) => Ok(factory(rom)),
+        None => create_mapper(number, rom),
+    }
+}
+
+// Mapper 0: 16KB or 32KB of PRG ROM with no bank switching
+pub struct Nrom {
+    prg_rom: Vec<u8>, // Program ROM mapped at 0x8000-0xFFFF
+}
+
+impl Nrom {
+    // Constructor to create a new NROM board around a cartridge
+    pub fn new(rom: Rom) -> Self {
+        Nrom {
+            prg_rom: rom.prg_rom,
+        }
+    }
+
+    // Map a CPU address in 0x8000-0xFFFF to an offset into PRG ROM
+    // 16KB cartridges mirror their single bank into both halves
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let mut index = (address - 0x8000) as usize;
+        if self.prg_rom.len() == 0x4000 {
+            index %= 0x4000;
+        }
+        index
+    }
+}
+
+impl Mapper for Nrom {
+    fn number(&self) -> u16 {
+        0
+    }
+
+    fn read_prg(&self, address: u16) -> u8 {
+        self.prg_rom[self.prg_rom_index(address)]
+    }
+
+    fn write_prg(&mut self, _address: u16, _data: u8) {} // No registers, PRG ROM is read-only
+
+    fn load_prg(&mut self, address: u16, data: u8) {
+        let index = self.prg_rom_index(address);
+        self.prg_rom[index] = data;
+    }
+}
+
+// Mapper 2: a switchable 16KB bank at 0x8000 and the last bank fixed at 0xC000
+pub struct Uxrom {
+    prg_rom: Vec<u8>, // Program ROM, a multiple of 16KB
+    bank: usize,      // Bank selected by the last write to 0x8000-0xFFFF
+}
+
+impl Uxrom {
+    // Constructor to create a new UxROM board around a cartridge
+    pub fn new(rom: Rom) -> Self {
+        Uxrom {
+            prg_rom: rom.prg_rom,
+            bank: 0,
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        (self.prg_rom.len() / 0x4000).max(1)
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let bank = match address {
+            0x8000..=0xBFFF => self.bank,
+            _ => self.bank_count() - 1,
+        };
+        bank * 0x4000 + (address & 0x3fff) as usize
+    }
+}
+
+impl Mapper for Uxrom {
+    fn number(&self) -> u16 {
+        2
+    }
+
+    fn read_prg(&self, address: u16) -> u8 {
+        self.prg_rom[self.prg_rom_index(address)]
+    }
+
+    fn write_prg(&mut self, _address: u16, data: u8) {
+        self.bank = data as usize % self.bank_count();
+        log::debug!("UxROM PRG bank {} at $8000", self.bank);
+    }
+
+    fn load_prg(&mut self, address: u16, data: u8) {
+        let index = self.prg_rom_index(address);
+        self.prg_rom[index] = data;
+    }
+
+    fn prg_bank(&self) -> usize {
+        self.bank
+    }
+}
+
+// Mapper 1: registers loaded one bit at a time through a 5-bit shift register
+pub struct Mmc1 {
+    prg_rom: Vec<u8>, // Program ROM, a multiple of 16KB
+    chr_size: usize,  // Size of CHR ROM, for wrapping bank offsets
+    shift: u8,        // Bits written so far, least significant first
+    shift_count: u8,  // Number of bits in the shift register
+    control: u8,      // Mirroring (bits 0-1), PRG mode (bits 2-3), CHR mode (bit 4)
+    chr_bank_0: u8,   // CHR bank for 0x0000, or the 8KB bank in 8KB mode
+    chr_bank_1: u8,   // CHR bank for 0x1000 in 4KB mode
+    prg_bank: u8,     // Switchable 16KB PRG bank
+}
+
+impl Mmc1 {
+    // Constructor to create a new MMC1 board around a cartridge
+    pub fn new(rom: Rom) -> Self {
+        Mmc1 {
+            prg_rom: rom.prg_rom,
+            chr_size: rom.chr_rom.len(),
+            shift: 0,
+            shift_count: 0,
+            control: 0x0c, // Power up with the last PRG bank fixed at 0xC000
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        (self.prg_rom.len() / 0x4000).max(1)
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let bank = self.prg_bank as usize & 0x0f;
+        let last = self.bank_count() - 1;
+        let upper = address >= 0xc000;
+        let bank = match ((self.control >> 2) & 0b11, upper) {
+            (0, _) | (1, _) => (bank & !1) + upper as usize, // One 32KB bank
+            (2, false) => 0,                                 // First bank fixed at 0x8000
+            (2, true) => bank,
+            (_, false) => bank,
+            (_, true) => last, // Last bank fixed at 0xC000
+        };
+        (bank % self.bank_count()) * 0x4000 + (address & 0x3fff) as usize
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        log::debug!("MMC1 register ${:04X} = ${:02X}", address & 0xe000, value);
+        match address {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn number(&self) -> u16 {
+        1
+    }
+
+    fn read_prg(&self, address: u16) -> u8 {
+        self.prg_rom[self.prg_rom_index(address)]
+    }
+
+    fn write_prg(&mut self, address: u16, data: u8) {
+        // Bit 7 resets the shift register and returns to PRG mode 3
+        if data & 0x80 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0c;
+            return;
+        }
+
+        self.shift |= (data & 1) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count == 5 {
+            self.write_register(address, self.shift);
+            self.shift = 0;
+            self.shift_count = 0;
+        }
+    }
+
+    fn load_prg(&mut self, address: u16, data: u8) {
+        let index = self.prg_rom_index(address);
+        self.prg_rom[index] = data;
+    }
+
+    fn prg_bank(&self) -> usize {
+        self.prg_rom_index(0x8000) / 0x4000
+    }
+
+    // In 8KB mode this is the 8KB bank; in 4KB mode the bank at 0x0000
+    fn chr_bank(&self) -> usize {
+        if self.control & 0x10 == 0 {
+            self.chr_bank_0 as usize >> 1
+        } else {
+            self.chr_bank_0 as usize
+        }
+    }
+
+    fn chr_banks(&self) -> [usize; 2] {
+        let size = self.chr_size.max(0x1000);
+        if self.control & 0x10 == 0 {
+            let base = (self.chr_bank_0 as usize & !1) * 0x1000 % size;
+            [base, base + 0x1000]
+        } else {
+            [
+                self.chr_bank_0 as usize * 0x1000 % size,
+                self.chr_bank_1 as usize * 0x1000 % size,
+            ]
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        })
+    }
+}
+
+// PRG ROM of a mapper's state, which the mappers need to be whole 16KB banks
+fn read_prg_rom(reader: &mut StateReader) -> Result<Vec<u8>, EmuError> {
+    let prg_rom = reader.bytes()?;
+    if prg_rom.is_empty() || prg_rom.len() % 0x4000 != 0 {
+        return Err(EmuError::InvalidSaveState(format!(
+            "PRG ROM of {} bytes",
+            prg_rom.len()
+        )));
+    }
+    Ok(prg_rom)
+}
+
+impl SaveState for Nrom {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.prg_rom);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        self.prg_rom = read_prg_rom(reader)?;
+        Ok(())
+    }
+}
+
+impl SaveState for Uxrom {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.prg_rom);
+        writer.u32(self.bank as u32);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        let prg_rom = read_prg_rom(reader)?;
+        let last_bank = prg_rom.len() / 0x4000 - 1;
+        self.bank = savestate::in_range("UxROM bank", reader.u32()? as usize, 0..=last_bank)?;
+        self.prg_rom = prg_rom;
+        Ok(())
+    }
+}
+
+impl SaveState for Mmc1 {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.prg_rom);
+        writer.u32(self.chr_size as u32);
+        writer.u8(self.shift);
+        writer.u8(self.shift_count);
+        writer.u8(self.control);
+        writer.u8(self.chr_bank_0);
+        writer.u8(self.chr_bank_1);
+        writer.u8(self.prg_bank);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        self.prg_rom = read_prg_rom(reader)?;
+        self.chr_size = reader.u32()? as usize;
+        self.shift = reader.u8()?;
+        // The fifth bit completes a write, so at most four are ever pending
+        self.shift_count = savestate::in_range("MMC1 shift count", reader.u8()?, 0..=4)?;
+        self.control = reader.u8()?;
+        self.chr_bank_0 = reader.u8()?;
+        self.chr_bank_1 = reader.u8()?;
+        self.prg_bank = reader.u8()?;
+        Ok(())
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::bus::Bus;
+    #[cfg(feature = "std")]
+    use crate::hardware::CPU;
+
+    // Test case for the registry building NROM for mapper 0 and rejecting unknown numbers
+    #[test]
+    fn test_create_mapper() {
+        let mapper = create_mapper(0, Rom::default()).unwrap();
+        assert_eq!(mapper.number(), 0);
+        assert_eq!(
+            create_mapper(999, Rom::default()).err(),
+            Some(EmuError::UnsupportedMapper(999))
+        );
+    }
+
+    // Build a cartridge whose 16KB PRG banks each start with their own index
+    fn banked_rom(banks: usize) -> Rom {
+        let mut prg_rom = vec![0; banks * 0x4000];
+        for bank in 0..banks {
+            prg_rom[bank * 0x4000] = bank as u8;
+        }
+        Rom {
+            prg_rom,
+            ..Rom::default()
+        }
+    }
+
+    // Test case for NROM always reporting bank 0
+    #[test]
+    fn test_nrom_banks() {
+        let mapper = Nrom::new(Rom::default());
+        assert_eq!(mapper.prg_bank(), 0);
+        assert_eq!(mapper.chr_bank(), 0);
+    }
+
+    // Test case for a UxROM bank switch changing the reported bank and the data at 0x8000
+    #[test]
+    fn test_uxrom_bank_switch() {
+        let mut mapper = Uxrom::new(banked_rom(4));
+        assert_eq!(mapper.prg_bank(), 0);
+        mapper.write_prg(0x8000, 3);
+        assert_eq!(mapper.prg_bank(), 3);
+        assert_eq!(mapper.read_prg(0x8000), 3);
+        assert_eq!(mapper.read_prg(0xC000), 3); // Last bank stays fixed
+    }
+
+    // Test case for a UxROM state selecting a bank the ROM does not have
+    #[test]
+    fn test_uxrom_state_rejects_missing_bank() {
+        let mut writer = StateWriter::new();
+        writer.bytes(&[0; 2 * 0x4000]);
+        writer.u32(2);
+        let data = writer.into_bytes();
+        let mut mapper = Uxrom::new(banked_rom(4));
+        let result = mapper.read_state(&mut StateReader::new(&data).unwrap());
+        assert!(matches!(result, Err(EmuError::InvalidSaveState(_))));
+        assert_eq!(mapper.read_prg(0xC000), 3); // Left as it was
+
+        let mut writer = StateWriter::new();
+        writer.bytes(&[0; 0x100]);
+        let data = writer.into_bytes();
+        let result = Nrom::new(Rom::default()).read_state(&mut StateReader::new(&data).unwrap());
+        assert!(matches!(result, Err(EmuError::InvalidSaveState(_))));
+    }
+
+    // Test case for MMC1 taking register values serially, five bits at a time
+    #[test]
+    fn test_mmc1_serial_writes() {
+        let mut mapper = Mmc1::new(banked_rom(8));
+        for bit in [1, 0, 1, 0, 0] {
+            mapper.write_prg(0xE000, bit); // PRG bank 5
+        }
+        assert_eq!(mapper.prg_bank(), 5);
+        assert_eq!(mapper.read_prg(0x8000), 5);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+        for bit in [0, 1, 0, 0, 0] {
+            mapper.write_prg(0xA000, bit); // CHR bank 2, 8KB bank 1
+        }
+        assert_eq!(mapper.chr_bank(), 1);
+    }
+
+    // Mapper stub answering every PRG read with the same byte
+    struct FixedMapper {
+        value: u8, // Byte seen everywhere in 0x8000-0xFFFF
+    }
+
+    impl Mapper for FixedMapper {
+        fn number(&self) -> u16 {
+            240
+        }
+
+        fn read_prg(&self, _address: u16) -> u8 {
+            self.value
+        }
+
+        fn write_prg(&mut self, _address: u16, _data: u8) {}
+
+        fn load_prg(&mut self, _address: u16, _data: u8) {}
+    }
+
+    impl SaveState for FixedMapper {
+        fn write_state(&self, _writer: &mut StateWriter) {}
+
+        fn read_state(&mut self, _reader: &mut StateReader) -> Result<(), EmuError> {
+            Ok(())
+        }
+    }
+
+    // Test case for a cartridge file using a mapper registered on the builder, which
+    // other CPUs do not see
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_register_mapper() {
+        use crate::builder::EmulatorBuilder;
+        let mut raw = crate::cartridge::test::test_rom_bytes(0x00, 0xf0, 1, 1);
+        raw[16] = 0x42;
+        let path = std::env::temp_dir().join("nes_test_register_mapper.nes");
+        std::fs::write(&path, &raw).unwrap();
+
+        let mut cpu = EmulatorBuilder::new()
+            .mapper(
+                240,
+                Box::new(|rom: Rom| {
+                    Box::new(FixedMapper {
+                        value: rom.prg_rom[0],
+                    })
+                }),
+            )
+            .build();
+        cpu.load_rom_file(&path).unwrap();
+        let unregistered = CPU::new().load_rom_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cpu.bus.mapper(), 240);
+        assert_eq!(cpu.peek(0xc123), 0x42);
+        assert_eq!(cpu.program_counter, 0x4242); // Reset vector read through the mapper
+        assert_eq!(unregistered, Err(EmuError::UnsupportedMapper(240)));
+
+        // The registration carries over to the next cartridge, and a state only loads
+        // back onto the cartridge it was saved from
+        let state = cpu.save_state();
+        cpu.load_rom(&crate::cartridge::test::test_rom_bytes(0x00, 0x00, 1, 1))
+            .unwrap();
+        assert!(matches!(
+            cpu.load_state(&state),
+            Err(EmuError::InvalidSaveState(_))
+        ));
+        cpu.load_rom(&raw).unwrap();
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.bus.mapper(), 240);
+        assert_eq!(cpu.peek(0xc123), 0x42);
+    }
+
+    // Test case for the thread-wide register_mapper reaching consoles created afterwards
+    // on the same thread only
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_register_mapper_for_thread() {
+        let mut raw = crate::cartridge::test::test_rom_bytes(0x00, 0xf0, 1, 1);
+        raw[16] = 0x42;
+        let mut before = CPU::new();
+        register_mapper(
+            240,
+            Box::new(|rom: Rom| {
+                Box::new(FixedMapper {
+                    value: rom.prg_rom[0],
+                })
+            }),
+        );
+        let mut cpu = CPU::new();
+        cpu.load_rom(&raw).unwrap();
+        assert_eq!(cpu.peek(0xc123), 0x42);
+        assert!(Bus::new(Rom::new(&raw).unwrap()).is_ok());
+
+        assert_eq!(before.load_rom(&raw), Err(EmuError::UnsupportedMapper(240)));
+        let other_thread = std::thread::spawn(move || CPU::new().load_rom(&raw));
+        assert_eq!(
+            other_thread.join().unwrap(),
+            Err(EmuError::UnsupportedMapper(240))
+        );
+    }
+}
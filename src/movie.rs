@@ -0,0 +1,215 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::error::EmuError;
+use crate::hardware::CPU;
+use crate::joypad::JoypadButton;
+
+// Button columns of an .fm2 input line, left to right; each is the letter when pressed
+// and '.' or ' ' when released
+const FM2_BUTTONS: [JoypadButton; 8] = [
+    JoypadButton::Right,
+    JoypadButton::Left,
+    JoypadButton::Down,
+    JoypadButton::Up,
+    JoypadButton::Start,
+    JoypadButton::Select,
+    JoypadButton::B,
+    JoypadButton::A,
+];
+
+// Bits of the commands column of an .fm2 input line
+pub const COMMAND_SOFT_RESET: u8 = 0b01;
+pub const COMMAND_POWER: u8 = 0b10;
+
+// How a movie expects the console to be set up before its first frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum MovieStart {
+    Reset,             // From power-on with the cartridge freshly loaded
+    SaveState(String), // From an FCEUX save state, kept as written in the header
+}
+
+// Input for one frame of a movie
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MovieFrame {
+    pub commands: u8,         // COMMAND_* bits executed before the frame runs
+    pub controllers: [u8; 2], // Pressed buttons for ports 1 and 2, one bit per JoypadButton
+}
+
+impl MovieFrame {
+    // Whether a button is held on a controller port (0 or 1) during this frame
+    pub fn pressed(&self, port: usize, button: JoypadButton) -> bool {
+        self.controllers[port] & (1 << button as u8) != 0
+    }
+}
+
+// Recorded controller input, replayed one frame at a time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_checksum: Option<String>, // Checksum of the ROM the movie was recorded on
+    pub start: MovieStart,            // Console state the movie starts from
+    pub frames: Vec<MovieFrame>,      // Input for each frame, in order
+}
+
+impl Movie {
+    // Parse the text of an FCEUX .fm2 movie
+    // Header lines are "key value" pairs and every input line starts with '|'; header
+    // keys other than the ROM checksum and the save state are ignored
+    pub fn from_fm2(text: &str) -> Result<Movie, EmuError> {
+        let mut rom_checksum = None;
+        let mut start = MovieStart::Reset;
+        let mut frames = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.starts_with('|') {
+                frames.push(parse_fm2_frame(line).map_err(|message| {
+                    EmuError::InvalidMovie(format!("line {}: {}", number + 1, message))
+                })?);
+                continue;
+            }
+            let (key, value) = match line.split_once(' ') {
+                Some((key, value)) => (key, value.trim()),
+                None => (line.trim(), ""),
+            };
+            match key {
+                "romChecksum" => rom_checksum = Some(value.to_string()),
+                "savestate" if !value.is_empty() => {
+                    start = MovieStart::SaveState(value.to_string())
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Movie {
+            rom_checksum,
+            start,
+            frames,
+        })
+    }
+
+    // Replay the movie on a CPU with the cartridge already loaded, one frame of input
+    // per emulated frame. Only port 1 is wired to the bus, so port 2 input is dropped
+    pub fn play(&self, cpu: &mut CPU) -> Result<(), EmuError> {
+        if let MovieStart::SaveState(_) = self.start {
+            return Err(EmuError::InvalidMovie(
+                "movies starting from an FCEUX save state are not supported".to_string(),
+            ));
+        }
+        for frame in &self.frames {
+            if frame.commands & (COMMAND_SOFT_RESET | COMMAND_POWER) != 0 {
+                cpu.reset();
+            }
+            for index in 0..8 {
+                if let Some(button) = JoypadButton::from_index(index) {
+                    let pressed = frame.pressed(0, button);
+                    cpu.bus.joypad1.set_button_pressed_status(button, pressed);
+                }
+            }
+            cpu.run_frame()?;
+        }
+        Ok(())
+    }
+}
+
+// Parse one "|commands|port1|port2|port3|" input line; an empty port column means
+// no controller is plugged in
+fn parse_fm2_frame(line: &str) -> Result<MovieFrame, String> {
+    let mut fields = line[1..].split('|');
+    let commands = fields.next().unwrap_or("").trim();
+    let commands = if commands.is_empty() {
+        0
+    } else {
+        commands
+            .parse::<u8>()
+            .map_err(|_| format!("bad commands field {:?}", commands))?
+    };
+
+    let mut controllers = [0; 2];
+    for controller in controllers.iter_mut() {
+        let column = fields.next().unwrap_or("");
+        if column.is_empty() {
+            continue;
+        }
+        if column.chars().count() != FM2_BUTTONS.len() {
+            return Err(format!("bad controller column {:?}", column));
+        }
+        for (symbol, button) in column.chars().zip(FM2_BUTTONS) {
+            if symbol != '.' && symbol != ' ' {
+                *controller |= 1 << button as u8;
+            }
+        }
+    }
+
+    Ok(MovieFrame {
+        commands,
+        controllers,
+    })
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SNIPPET: &str = "version 3\n\
+        emuVersion 22020\n\
+        romFilename test\n\
+        romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==\n\
+        guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B\n\
+        fourscore 0\n\
+        port0 1\n\
+        port1 1\n\
+        port2 0\n\
+        |0|........|........||\n\
+        |0|.......A|........||\n\
+        |0|R..U....|......B.||\n\
+        |1|...UTS..|R......A||\n";
+
+    // Test case for parsing the header and each frame's buttons from an .fm2 snippet
+    #[test]
+    fn test_parse_fm2() {
+        let movie = Movie::from_fm2(SNIPPET).unwrap();
+        assert_eq!(
+            movie.rom_checksum.as_deref(),
+            Some("base64:jjYwGG411HcjG/j9UOVM3Q==")
+        );
+        assert_eq!(movie.start, MovieStart::Reset);
+        assert_eq!(movie.frames.len(), 4);
+
+        assert_eq!(movie.frames[0], MovieFrame::default());
+        assert_eq!(movie.frames[1].controllers, [0b0000_0001, 0]);
+        assert!(movie.frames[1].pressed(0, JoypadButton::A));
+
+        let frame = movie.frames[2];
+        assert!(frame.pressed(0, JoypadButton::Right));
+        assert!(frame.pressed(0, JoypadButton::Up));
+        assert!(!frame.pressed(0, JoypadButton::Left));
+        assert!(frame.pressed(1, JoypadButton::B));
+        assert!(!frame.pressed(1, JoypadButton::A));
+
+        let frame = movie.frames[3];
+        assert_eq!(frame.commands, COMMAND_SOFT_RESET);
+        assert_eq!(frame.controllers, [0b0001_1100, 0b1000_0001]);
+    }
+
+    // Test case for a movie recorded from a save state, which cannot be replayed
+    #[test]
+    fn test_fm2_savestate_start() {
+        let movie = Movie::from_fm2("version 3\nsavestate base64:AAAA\n|0|........|||\n").unwrap();
+        assert_eq!(
+            movie.start,
+            MovieStart::SaveState("base64:AAAA".to_string())
+        );
+        assert_eq!(movie.frames[0].controllers, [0, 0]);
+        assert!(movie.play(&mut CPU::new()).is_err());
+    }
+
+    // Test case for rejecting a controller column of the wrong width
+    #[test]
+    fn test_fm2_bad_input_line() {
+        let result = Movie::from_fm2("version 3\n|0|....A|........||\n");
+        assert!(matches!(result, Err(EmuError::InvalidMovie(_))));
+    }
+}
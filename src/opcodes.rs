@@ -0,0 +1,183 @@
+use crate::hardware::AddressingMode;
+
+// Define the OpCode struct describing one instruction encoding
+pub struct OpCode {
+    pub code: u8,               // Opcode byte
+    pub mnemonic: &'static str, // Assembly mnemonic
+    pub len: u8,                // Instruction length in bytes, including the opcode
+    pub cycles: u8,             // Base cycle count
+    pub mode: AddressingMode,   // Addressing mode of the operand
+}
+
+impl OpCode {
+    const fn new(
+        code: u8,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+    ) -> Self {
+        OpCode {
+            code,
+            mnemonic,
+            len,
+            cycles,
+            mode,
+        }
+    }
+}
+
+// Table of every opcode the CPU implements
+pub static CPU_OPS_CODES: &[OpCode] = &[
+    OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
+    OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
+    OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
+    OpCode::new(0x4c, "JMP", 3, 3, AddressingMode::Absolute),
+    // Branches take a signed offset; taken branches cost more, see CPU::branch
+    OpCode::new(0x10, "BPL", 2, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x30, "BMI", 2, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x50, "BVC", 2, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x70, "BVS", 2, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x90, "BCC", 2, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xb0, "BCS", 2, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xd0, "BNE", 2, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xf0, "BEQ", 2, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xa9, "LDA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xa5, "LDA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xb5, "LDA", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xad, "LDA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xbd, "LDA", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xb9, "LDA", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0xa1, "LDA", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xb1, "LDA", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x8d, "STA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x9d, "STA", 3, 5, AddressingMode::AbsoluteX),
+    OpCode::new(0x99, "STA", 3, 5, AddressingMode::AbsoluteY),
+    OpCode::new(0x81, "STA", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x91, "STA", 2, 6, AddressingMode::IndirectY),
+    OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x6d, "ADC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x7d, "ADC", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x79, "ADC", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0x61, "ADC", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x71, "ADC", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0xe9, "SBC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xe5, "SBC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xf5, "SBC", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xed, "SBC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xfd, "SBC", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xf9, "SBC", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0xe1, "SBC", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xf1, "SBC", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0xc9, "CMP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xc5, "CMP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xd5, "CMP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xcd, "CMP", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xdd, "CMP", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xd9, "CMP", 3, 4, AddressingMode::AbsoluteY),
+    OpCode::new(0xc1, "CMP", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xd1, "CMP", 2, 5, AddressingMode::IndirectY),
+    OpCode::new(0xe0, "CPX", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xe4, "CPX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xec, "CPX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xc0, "CPY", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xc4, "CPY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xcc, "CPY", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xe6, "INC", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xf6, "INC", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0xee, "INC", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xfe, "INC", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0xc6, "DEC", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xd6, "DEC", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0xce, "DEC", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xde, "DEC", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0xf8, "SED", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xd8, "CLD", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xe8, "INX", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xc8, "INY", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xca, "DEX", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x0e, "ASL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x1e, "ASL", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0x4a, "LSR", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x4e, "LSR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x5e, "LSR", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0x2a, "ROL", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x2e, "ROL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x3e, "ROL", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0x6a, "ROR", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x6e, "ROR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x7e, "ROR", 3, 7, AddressingMode::AbsoluteX),
+    // Unofficial opcodes
+    OpCode::new(0x80, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x82, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x89, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xc2, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xe2, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x04, "*NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x44, "*NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x64, "*NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x14, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x34, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x54, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x74, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xd4, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xf4, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x0c, "*NOP", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x1c, "*NOP", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x3c, "*NOP", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x5c, "*NOP", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x7c, "*NOP", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xdc, "*NOP", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0xfc, "*NOP", 3, 4, AddressingMode::AbsoluteX),
+    OpCode::new(0x0b, "*ANC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x2b, "*ANC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x4b, "*ALR", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x6b, "*ARR", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xcb, "*AXS", 2, 2, AddressingMode::Immediate),
+];
+
+// Look up the table entry for an opcode byte
+pub fn find(code: u8) -> Option<&'static OpCode> {
+    CPU_OPS_CODES.iter().find(|op| op.code == code)
+}
+
+// Look up the encoding of a mnemonic in a given addressing mode
+pub fn find_by_mnemonic(mnemonic: &str, mode: &AddressingMode) -> Option<&'static OpCode> {
+    CPU_OPS_CODES
+        .iter()
+        .find(|op| op.mnemonic == mnemonic && op.mode == *mode)
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Test case for the table holding each opcode byte only once
+    #[test]
+    fn test_opcodes_are_unique() {
+        for (i, op) in CPU_OPS_CODES.iter().enumerate() {
+            assert!(
+                CPU_OPS_CODES[i + 1..]
+                    .iter()
+                    .all(|other| other.code != op.code),
+                "duplicate opcode {:#04x}",
+                op.code
+            );
+        }
+    }
+}
@@ -0,0 +1,846 @@
+use crate::cartridge::Mirroring;
+use crate::error::EmuError;
+use crate::savestate::{self, SaveState, StateReader, StateWriter};
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// 341 dots per scanline; VBlank starts after the 240 visible lines plus one idle line
+const DOTS_PER_SCANLINE: u16 = 341;
+const VISIBLE_SCANLINES: u16 = 240;
+const VBLANK_SCANLINE: u16 = 241;
+
+// Television standard of the console, which sets the frame length
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Ntsc, // 262 scanlines per frame
+    Pal,  // 312 scanlines per frame
+}
+
+impl Region {
+    // Frames per second of the television standard
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal => 50.007,
+        }
+    }
+
+    // Master clock cycles per CPU cycle
+    pub fn cpu_clock_divider(&self) -> u64 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+        }
+    }
+
+    // Master clock cycles per PPU dot
+    pub fn ppu_clock_divider(&self) -> u64 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal => 5,
+        }
+    }
+
+    fn scanlines_per_frame(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal => 312,
+        }
+    }
+}
+
+// Two-write latch behind PPUADDR (0x2006): high byte first, then low byte
+struct AddrRegister {
+    value: (u8, u8), // (high byte, low byte)
+    hi_ptr: bool,    // Whether the next write targets the high byte
+}
+
+impl AddrRegister {
+    fn new() -> Self {
+        AddrRegister {
+            value: (0, 0),
+            hi_ptr: true,
+        }
+    }
+
+    fn set(&mut self, data: u16) {
+        self.value.0 = (data >> 8) as u8;
+        self.value.1 = (data & 0xff) as u8;
+    }
+
+    fn get(&self) -> u16 {
+        ((self.value.0 as u16) << 8) | (self.value.1 as u16)
+    }
+
+    fn update(&mut self, data: u8) {
+        if self.hi_ptr {
+            self.value.0 = data;
+        } else {
+            self.value.1 = data;
+        }
+
+        // The PPU address space is only 14 bits wide
+        self.set(self.get() & 0x3fff);
+        self.hi_ptr = !self.hi_ptr;
+    }
+
+    fn increment(&mut self, inc: u8) {
+        self.set(self.get().wrapping_add(inc as u16) & 0x3fff);
+    }
+
+    fn reset_latch(&mut self) {
+        self.hi_ptr = true;
+    }
+}
+
+// Most sprites the PPU can draw on one scanline
+const SPRITES_PER_LINE: usize = 8;
+
+// Sprites found on one scanline by sprite evaluation, copied out of OAM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SecondaryOam {
+    pub data: [u8; 4 * SPRITES_PER_LINE], // Sprites in range, four bytes each, in OAM order
+    pub count: usize,                     // Number of sprites copied into data
+    pub sprite_zero: bool,                // Whether the first entry is sprite 0
+    pub overflow: bool,                   // More sprites were in range than fit
+}
+
+impl SecondaryOam {
+    fn new() -> Self {
+        SecondaryOam {
+            data: [0xff; 4 * SPRITES_PER_LINE],
+            count: 0,
+            sprite_zero: false,
+            overflow: false,
+        }
+    }
+}
+
+// Two-write latch behind PPUSCROLL (0x2005): horizontal offset first, then vertical
+struct ScrollRegister {
+    scroll_x: u8, // Pixels scrolled right
+    scroll_y: u8, // Pixels scrolled down
+    x_next: bool, // Whether the next write sets the horizontal offset
+}
+
+impl ScrollRegister {
+    fn new() -> Self {
+        ScrollRegister {
+            scroll_x: 0,
+            scroll_y: 0,
+            x_next: true,
+        }
+    }
+
+    fn update(&mut self, data: u8) {
+        if self.x_next {
+            self.scroll_x = data;
+        } else {
+            self.scroll_y = data;
+        }
+        self.x_next = !self.x_next;
+    }
+
+    fn reset_latch(&mut self) {
+        self.x_next = true;
+    }
+}
+
+// Fold a palette address (0x3F00-0x3F1F) into palette RAM
+// Entry 0 of each sprite palette (0x3F10/0x3F14/0x3F18/0x3F1C) is shared with
+// the matching background palette, so 0x3F10 is the universal background colour
+fn mirror_palette_index(address: u16) -> usize {
+    let index = (address & 0x1f) as usize;
+    if index & 0b1_0011 == 0b1_0000 {
+        index - 0x10
+    } else {
+        index
+    }
+}
+
+// PPU state captured by CPU::ppu_snapshot, kept in the save-state encoding
+#[derive(Debug, Clone, PartialEq)]
+pub struct PpuSnapshot(pub(crate) Vec<u8>);
+
+// Define the PPU struct
+pub struct PPU {
+    pub chr_rom: Vec<u8>,        // Pattern tables from the cartridge
+    pub chr_banks: [usize; 2],   // Offsets into chr_rom shown at 0x0000 and 0x1000
+    pub palette_table: [u8; 32], // Palette RAM
+    pub vram: Vec<u8>,           // Nametable RAM, 2KB or 4KB with four-screen VRAM
+    pub oam_data: [u8; 256],     // Sprite attribute memory, four bytes per sprite
+    secondary_oam: SecondaryOam, // Sprites evaluated for the current scanline
+    pub mirroring: Mirroring,    // Nametable mirroring wired on the cartridge
+    ctrl: u8,                    // PPUCTRL (0x2000)
+    mask: u8,                    // PPUMASK (0x2001)
+    status: u8,                  // PPUSTATUS (0x2002)
+    oam_addr: u8,                // OAMADDR (0x2003)
+    scroll: ScrollRegister,      // PPUSCROLL (0x2005)
+    addr: AddrRegister,          // PPUADDR (0x2006)
+    internal_data_buf: u8,       // Read buffer behind PPUDATA (0x2007)
+    nmi_pending: bool,           // NMI raised at the start of VBlank, not yet taken by the CPU
+    io_latch: u8,                // Last value driven on the PPU's data bus by a register access
+    pub scanline: u16,           // Current scanline, 0-261
+    odd_frame: bool,             // Alternates each frame; odd NTSC frames may skip a dot
+    pub cycles: u16,             // Current dot within the scanline, 0-340
+    pub region: Region,          // Selects the number of scanlines per frame
+}
+
+impl PPU {
+    // Constructor to create a new PPU instance
+    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        PPU {
+            chr_rom,
+            chr_banks: [0, 0x1000],
+            palette_table: [0; 32],
+            vram: vec![
+                0;
+                if mirroring == Mirroring::FourScreen {
+                    4096
+                } else {
+                    2048
+                }
+            ],
+            oam_data: [0; 256],
+            secondary_oam: SecondaryOam::new(),
+            mirroring,
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            scroll: ScrollRegister::new(),
+            addr: AddrRegister::new(),
+            internal_data_buf: 0,
+            nmi_pending: false,
+            io_latch: 0,
+            scanline: 0,
+            odd_frame: false,
+            cycles: 0,
+            region: Region::Ntsc,
+        }
+    }
+
+    // Advance by a number of dots, returning true when VBlank begins and the
+    // picture for this frame is complete
+    pub fn tick(&mut self, dots: u16) -> bool {
+        self.tick_drawing(dots, |_, _| {})
+    }
+
+    // Advance like tick(), calling draw_line as each visible scanline starts so it
+    // sees the registers as they were for that line
+    pub fn tick_drawing(&mut self, dots: u16, mut draw_line: impl FnMut(&PPU, usize)) -> bool {
+        let mut frame_complete = false;
+        self.cycles += dots;
+        while self.cycles >= self.scanline_length() {
+            self.cycles -= self.scanline_length();
+            self.scanline += 1;
+
+            if self.scanline == VBLANK_SCANLINE {
+                let nmi_before = self.nmi_line();
+                self.status |= 0b1000_0000; // Set VBlank
+                self.detect_nmi_edge(nmi_before);
+                frame_complete = true;
+            }
+
+            // VBlank ends on the pre-render line, the last of the frame
+            if self.scanline == self.region.scanlines_per_frame() - 1 {
+                self.status &= 0b0101_1111; // Clear VBlank and sprite overflow
+            }
+
+            if self.scanline == self.region.scanlines_per_frame() {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+
+            if self.scanline < VISIBLE_SCANLINES {
+                self.secondary_oam = if self.show_background() || self.show_sprites() {
+                    self.evaluate_sprites(self.scanline as usize)
+                } else {
+                    SecondaryOam::new() // Evaluation only runs while rendering
+                };
+                if self.secondary_oam.overflow {
+                    self.status |= 0b0010_0000; // Sprite overflow
+                }
+                draw_line(self, self.scanline as usize);
+            }
+        }
+        frame_complete
+    }
+
+    // Dots in the current scanline: on NTSC the pre-render line of odd frames is one
+    // dot short while background rendering is enabled
+    fn scanline_length(&self) -> u16 {
+        let pre_render = self.scanline == self.region.scanlines_per_frame() - 1;
+        let background = self.mask & 0b0000_1000 != 0;
+        if pre_render && background && self.odd_frame && self.region == Region::Ntsc {
+            DOTS_PER_SCANLINE - 1
+        } else {
+            DOTS_PER_SCANLINE
+        }
+    }
+
+    // NMI output: high while VBlank is set and PPUCTRL bit 7 enables NMI
+    fn nmi_line(&self) -> bool {
+        self.status & 0b1000_0000 != 0 && self.ctrl & 0b1000_0000 != 0
+    }
+
+    // The CPU takes an NMI on each low-to-high transition of the NMI output, so
+    // enabling NMI during VBlank raises one and clearing it before VBlank sets
+    // suppresses it
+    fn detect_nmi_edge(&mut self, line_before: bool) {
+        if !line_before && self.nmi_line() {
+            self.nmi_pending = true;
+        }
+    }
+
+    // Copy the first eight sprites of OAM that cover line y, noting whether there
+    // were more; the hardware's buggy overflow search is not reproduced
+    pub fn evaluate_sprites(&self, y: usize) -> SecondaryOam {
+        let height = self.sprite_size() as usize;
+        let mut found = SecondaryOam::new();
+        for (index, sprite) in self.oam_data.chunks_exact(4).enumerate() {
+            let top = sprite[0] as usize;
+            if y < top || y >= top + height {
+                continue;
+            }
+            if found.count == SPRITES_PER_LINE {
+                found.overflow = true;
+                break;
+            }
+            found.data[found.count * 4..found.count * 4 + 4].copy_from_slice(sprite);
+            found.sprite_zero |= index == 0;
+            found.count += 1;
+        }
+        found
+    }
+
+    // Sprites evaluated when the current scanline started
+    pub fn secondary_oam(&self) -> &SecondaryOam {
+        &self.secondary_oam
+    }
+
+    // Raise an NMI as if VBlank had begun with NMIs enabled
+    pub fn request_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    // Take a pending NMI, clearing it
+    pub fn poll_nmi(&mut self) -> bool {
+        core::mem::take(&mut self.nmi_pending)
+    }
+
+    // Base address of the nametable selected by PPUCTRL bits 0-1
+    pub fn nametable_addr(&self) -> u16 {
+        0x2000 + 0x400 * (self.ctrl & 0b11) as u16
+    }
+
+    // Pattern table used for background tiles (PPUCTRL bit 4)
+    pub fn bknd_pattern_addr(&self) -> u16 {
+        if self.ctrl & 0b0001_0000 != 0 {
+            0x1000
+        } else {
+            0
+        }
+    }
+
+    // Pattern table used for 8x8 sprites (PPUCTRL bit 3)
+    pub fn sprt_pattern_addr(&self) -> u16 {
+        if self.ctrl & 0b0000_1000 != 0 {
+            0x1000
+        } else {
+            0
+        }
+    }
+
+    // Sprite height in pixels: 8, or 16 when PPUCTRL bit 5 is set
+    pub fn sprite_size(&self) -> u8 {
+        if self.ctrl & 0b0010_0000 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    // PPUMASK bit 3: draw the background
+    pub fn show_background(&self) -> bool {
+        self.mask & 0b0000_1000 != 0
+    }
+
+    // PPUMASK bit 4: draw sprites
+    pub fn show_sprites(&self) -> bool {
+        self.mask & 0b0001_0000 != 0
+    }
+
+    // PPUMASK bit 1: draw the background in the leftmost 8 pixels
+    pub fn show_background_left(&self) -> bool {
+        self.mask & 0b0000_0010 != 0
+    }
+
+    // PPUMASK bit 2: draw sprites in the leftmost 8 pixels
+    pub fn show_sprites_left(&self) -> bool {
+        self.mask & 0b0000_0100 != 0
+    }
+
+    // Read a pattern table byte (0x0000-0x1FFF) through the mapper's CHR banks
+    pub fn chr_byte(&self, address: u16) -> u8 {
+        let bank = self.chr_banks[(address as usize >> 12) & 1];
+        let index = bank + (address & 0x0fff) as usize;
+        self.chr_rom.get(index).copied().unwrap_or(0)
+    }
+
+    // Colour stored at a palette RAM index (0x00-0x1F), following the backdrop mirrors
+    pub fn palette_colour(&self, index: u8) -> u8 {
+        self.palette_table[mirror_palette_index(0x3f00 + index as u16)]
+    }
+
+    // Colour shown where nothing opaque is drawn
+    // With rendering off and PPUADDR pointing into palette RAM, the PPU outputs the entry
+    // PPUADDR selects instead of 0x3F00; test ROMs use this to show any palette colour
+    pub fn backdrop_colour(&self) -> u8 {
+        let address = self.vram_addr();
+        if !self.show_background() && !self.show_sprites() && address >= 0x3f00 {
+            self.palette_colour((address & 0x1f) as u8)
+        } else {
+            self.palette_colour(0)
+        }
+    }
+
+    // Horizontal and vertical scroll offsets written to PPUSCROLL
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.scroll.scroll_x, self.scroll.scroll_y)
+    }
+
+    // Read a nametable byte without the side effects of PPUDATA
+    pub fn nametable_byte(&self, address: u16) -> u8 {
+        self.vram[self.mirror_vram_addr(address) as usize]
+    }
+
+    // Handle a write to PPUCTRL
+    pub fn write_to_ctrl(&mut self, value: u8) {
+        let nmi_before = self.nmi_line();
+        self.ctrl = value;
+        self.detect_nmi_edge(nmi_before);
+    }
+
+    // Handle a write to PPUMASK
+    // Turning rendering off outside VBlank while PPUADDR points at 0x3C00-0x3FFF corrupts
+    // the palette entry PPUADDR selects: it takes the backdrop colour being output at the
+    // time, which later pixels drawn with that entry show instead of their own colour
+    pub fn write_to_mask(&mut self, value: u8) {
+        let rendering_before = self.show_background() || self.show_sprites();
+        self.mask = value;
+        let rendering = self.show_background() || self.show_sprites();
+        let address = self.vram_addr();
+        if rendering_before && !rendering && self.outside_vblank() && address >= 0x3c00 {
+            self.palette_table[mirror_palette_index(address)] = self.palette_colour(0);
+        }
+    }
+
+    // Whether the PPU is on a visible or the pre-render scanline, where it renders
+    fn outside_vblank(&self) -> bool {
+        self.scanline < VISIBLE_SCANLINES || self.scanline == self.region.scanlines_per_frame() - 1
+    }
+
+    // Handle a write to PPUSCROLL
+    pub fn write_to_scroll(&mut self, value: u8) {
+        self.scroll.update(value);
+    }
+
+    // Handle a write to OAMADDR
+    pub fn write_to_oam_addr(&mut self, value: u8) {
+        self.oam_addr = value;
+    }
+
+    // Handle a write to OAMDATA, which stores at OAMADDR and moves on to the next byte
+    pub fn write_to_oam_data(&mut self, value: u8) {
+        self.oam_data[self.oam_addr as usize] = value;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    // Handle a read of OAMDATA, which leaves OAMADDR where it is
+    pub fn read_oam_data(&self) -> u8 {
+        self.oam_data[self.oam_addr as usize]
+    }
+
+    // PPUSTATUS as a read would return it, without clearing anything
+    pub fn peek_status(&self) -> u8 {
+        (self.status & 0b1110_0000) | (self.io_latch & 0b0001_1111)
+    }
+
+    // Handle a read of PPUSTATUS, which clears VBlank and the address and scroll latches
+    // Only the top three bits are driven; the rest come from the I/O latch
+    pub fn read_status(&mut self) -> u8 {
+        let data = self.peek_status();
+        self.status &= 0b0111_1111;
+        self.addr.reset_latch();
+        self.scroll.reset_latch();
+        data
+    }
+
+    // Value left on the PPU's data bus by the last register read or write, which is what
+    // reads of the write-only registers return
+    pub fn io_latch(&self) -> u8 {
+        self.io_latch
+    }
+
+    // Record a value passing through the PPU's register port
+    pub fn set_io_latch(&mut self, value: u8) {
+        self.io_latch = value;
+    }
+
+    // Handle a write to PPUADDR
+    pub fn write_to_ppu_addr(&mut self, value: u8) {
+        self.addr.update(value);
+    }
+
+    // Current VRAM address held in PPUADDR
+    pub fn vram_addr(&self) -> u16 {
+        self.addr.get()
+    }
+
+    // PPUCTRL bit 2 selects an increment of 32 (down a row) instead of 1 (across)
+    fn increment_vram_addr(&mut self) {
+        let inc = if self.ctrl & 0b0000_0100 != 0 { 32 } else { 1 };
+        self.addr.increment(inc);
+    }
+
+    // Handle a write to PPUDATA
+    pub fn write_to_data(&mut self, value: u8) {
+        let address = self.addr.get();
+        match address {
+            0x0000..=0x1fff => {} // Pattern tables are ROM on the cartridge
+            // 0x3000-0x3EFF mirrors the nametables at 0x2000-0x2EFF
+            0x2000..=0x3eff => {
+                let index = self.mirror_vram_addr(address) as usize;
+                self.vram[index] = value;
+            }
+            // Palette RAM repeats every 32 bytes up to 0x3FFF
+            _ => self.palette_table[mirror_palette_index(address)] = value,
+        }
+        self.increment_vram_addr();
+    }
+
+    // Handle a read of PPUDATA
+    pub fn read_data(&mut self) -> u8 {
+        let address = self.addr.get();
+        self.increment_vram_addr();
+
+        match address {
+            // Reads below palette RAM are delayed by one read through the internal buffer
+            0x0000..=0x1fff => {
+                let result = self.internal_data_buf;
+                self.internal_data_buf = self.chr_byte(address);
+                result
+            }
+            0x2000..=0x3eff => {
+                let result = self.internal_data_buf;
+                self.internal_data_buf = self.vram[self.mirror_vram_addr(address) as usize];
+                result
+            }
+            // Palette reads are returned immediately; the buffer takes the nametable
+            // byte underneath instead
+            _ => {
+                self.internal_data_buf = self.vram[self.mirror_vram_addr(address) as usize];
+                self.palette_table[mirror_palette_index(address)]
+            }
+        }
+    }
+
+    // Fold a nametable address (0x2000-0x3EFF) into VRAM
+    // Horizontal: [ A a ]  Vertical: [ A B ]  FourScreen: [ A B ]  SingleScreen: [ A a ]
+    //             [ B b ]            [ a b ]              [ C D ]                [ a a ]
+    fn mirror_vram_addr(&self, address: u16) -> u16 {
+        let vram_index = (address & 0x2fff) - 0x2000; // Offset into the 4KB nametable space
+        let name_table = vram_index / 0x400; // Which of the four logical nametables
+        match (&self.mirroring, name_table) {
+            (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => vram_index - 0x800,
+            (Mirroring::Horizontal, 1) | (Mirroring::Horizontal, 2) => vram_index - 0x400,
+            (Mirroring::Horizontal, 3) => vram_index - 0x800,
+            (Mirroring::FourScreen, _) => vram_index, // Every nametable has its own RAM
+            (Mirroring::SingleScreenLower, _) => vram_index % 0x400,
+            (Mirroring::SingleScreenUpper, _) => 0x400 + vram_index % 0x400,
+            _ => vram_index,
+        }
+    }
+}
+
+impl SaveState for PPU {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.chr_rom);
+        writer.u32(self.chr_banks[0] as u32);
+        writer.u32(self.chr_banks[1] as u32);
+        writer.bytes(&self.palette_table);
+        writer.bytes(&self.vram);
+        writer.bytes(&self.oam_data);
+        writer.u8(match self.mirroring {
+            Mirroring::Vertical => 0,
+            Mirroring::Horizontal => 1,
+            Mirroring::FourScreen => 2,
+            Mirroring::SingleScreenLower => 3,
+            Mirroring::SingleScreenUpper => 4,
+        });
+        writer.u8(self.ctrl);
+        writer.u8(self.mask);
+        writer.u8(self.status);
+        writer.u8(self.oam_addr);
+        writer.u8(self.scroll.scroll_x);
+        writer.u8(self.scroll.scroll_y);
+        writer.bool(self.scroll.x_next);
+        writer.u16(self.addr.get());
+        writer.bool(self.addr.hi_ptr);
+        writer.u8(self.internal_data_buf);
+        writer.bool(self.nmi_pending);
+        writer.u16(self.scanline);
+        writer.bool(self.odd_frame);
+        writer.u16(self.cycles);
+        writer.u8(match self.region {
+            Region::Ntsc => 0,
+            Region::Pal => 1,
+        });
+        writer.u8(self.io_latch);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError> {
+        self.chr_rom = reader.bytes()?;
+        self.chr_banks = [reader.u32()? as usize, reader.u32()? as usize];
+        reader.bytes_into(&mut self.palette_table)?;
+        let vram = reader.bytes()?;
+        reader.bytes_into(&mut self.oam_data)?;
+        let mirroring = match reader.u8()? {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            other => {
+                return Err(EmuError::InvalidSaveState(format!(
+                    "unknown mirroring {}",
+                    other
+                )))
+            }
+        };
+        // Four-screen boards need all 4KB; the others mirror 2KB, whatever the mapper selects
+        if vram.len() != 2048 && vram.len() != 4096
+            || mirroring == Mirroring::FourScreen && vram.len() != 4096
+        {
+            return Err(EmuError::InvalidSaveState(format!(
+                "{} bytes of nametable RAM",
+                vram.len()
+            )));
+        }
+        self.vram = vram;
+        self.mirroring = mirroring;
+        self.ctrl = reader.u8()?;
+        self.mask = reader.u8()?;
+        self.status = reader.u8()?;
+        self.oam_addr = reader.u8()?;
+        self.scroll.scroll_x = reader.u8()?;
+        self.scroll.scroll_y = reader.u8()?;
+        self.scroll.x_next = reader.bool()?;
+        self.addr
+            .set(savestate::in_range("PPUADDR", reader.u16()?, 0..=0x3fff)?);
+        self.addr.hi_ptr = reader.bool()?;
+        self.internal_data_buf = reader.u8()?;
+        self.nmi_pending = reader.bool()?;
+        let scanline = reader.u16()?;
+        self.odd_frame = reader.bool()?;
+        let cycles = reader.u16()?;
+        let region = match reader.u8()? {
+            0 => Region::Ntsc,
+            _ => Region::Pal,
+        };
+        let last_scanline = region.scanlines_per_frame() - 1;
+        self.scanline = savestate::in_range("scanline", scanline, 0..=last_scanline)?;
+        self.cycles = savestate::in_range("dot", cycles, 0..=DOTS_PER_SCANLINE - 1)?;
+        self.region = region;
+        self.io_latch = reader.u8()?;
+        Ok(())
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::Frame;
+    use crate::palette::SYSTEM_PALETTE;
+
+    // Test case for rendering turned off mid-frame with PPUADDR in palette RAM corrupting
+    // the entry PPUADDR selects, so later background pixels show the backdrop colour
+    #[test]
+    fn test_palette_corruption_during_rendering() {
+        let mut chr = vec![0; 0x2000];
+        chr[0..8].fill(0xff); // Tile 0 drawn entirely in colour 1
+        let mut ppu = PPU::new(chr, Mirroring::Horizontal);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x16;
+        let show_background = 0b0000_1010;
+        ppu.write_to_mask(show_background);
+        ppu.scanline = 100;
+        let mut frame = Frame::new();
+        crate::render::render_scanline(&ppu, &mut frame, 100);
+        assert_eq!(frame.pixel(50, 100), SYSTEM_PALETTE[0x16]);
+
+        // PPUADDR is moved to 0x3F01 mid-render and rendering is switched off and on
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x01);
+        ppu.write_to_mask(0);
+        ppu.write_to_mask(show_background);
+        assert_eq!(ppu.palette_colour(1), 0x0f);
+        ppu.scanline = 101;
+        crate::render::render_scanline(&ppu, &mut frame, 101);
+        assert_eq!(frame.pixel(50, 101), SYSTEM_PALETTE[0x0f]);
+
+        // In VBlank the same writes leave the palette alone
+        ppu.palette_table[1] = 0x16;
+        ppu.scanline = VBLANK_SCANLINE;
+        ppu.write_to_mask(0);
+        assert_eq!(ppu.palette_colour(1), 0x16);
+    }
+
+    // Test case for writing through PPUADDR/PPUDATA into nametable RAM
+    #[test]
+    fn test_ppu_vram_writes() {
+        let mut ppu = PPU::new(vec![0; 2048], Mirroring::Horizontal);
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66);
+        assert_eq!(ppu.vram[0x0305], 0x66);
+    }
+
+    // Test case for the one-read delay of PPUDATA reads
+    #[test]
+    fn test_ppu_vram_reads_are_buffered() {
+        let mut ppu = PPU::new(vec![0; 2048], Mirroring::Horizontal);
+        ppu.vram[0x0305] = 0x66;
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.read_data(); // Loads the buffer
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    // Test case for horizontal mirroring folding the second nametable onto the first
+    #[test]
+    fn test_horizontal_mirroring() {
+        let mut ppu = PPU::new(vec![0; 2048], Mirroring::Horizontal);
+        ppu.write_to_ppu_addr(0x24);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66);
+        assert_eq!(ppu.vram[0x0005], 0x66);
+    }
+
+    // Test case for 0x3F10 mirroring the universal background colour at 0x3F00
+    #[test]
+    fn test_palette_backdrop_mirror() {
+        let mut ppu = PPU::new(vec![0; 2048], Mirroring::Horizontal);
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.write_to_data(0x2c);
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x00);
+        assert_eq!(ppu.read_data(), 0x2c);
+        assert_eq!(ppu.palette_colour(0x10), 0x2c);
+        assert_eq!(ppu.palette_colour(0x11), 0); // Other sprite entries are separate
+    }
+
+    // Test case for enabling NMI while VBlank is already set raising an NMI
+    #[test]
+    fn test_nmi_enable_during_vblank() {
+        let mut ppu = PPU::new(vec![0; 2048], Mirroring::Horizontal);
+        for _ in 0..241 {
+            ppu.tick(341);
+        }
+        assert!(!ppu.poll_nmi());
+        ppu.write_to_ctrl(0b1000_0000);
+        assert!(ppu.poll_nmi());
+        ppu.write_to_ctrl(0b1000_0000); // No new edge
+        assert!(!ppu.poll_nmi());
+    }
+
+    // Test case for 0x3000-0x3EFF mirroring the nametables
+    #[test]
+    fn test_nametable_mirror_above_0x3000() {
+        let mut ppu = PPU::new(vec![0; 2048], Mirroring::Horizontal);
+        ppu.write_to_ppu_addr(0x30);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_data(0x5e);
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.read_data(); // Loads the buffer
+        assert_eq!(ppu.read_data(), 0x5e);
+    }
+
+    // Test case for palette RAM repeating every 32 bytes up to 0x3FFF
+    #[test]
+    fn test_palette_mirror_up_to_0x3fff() {
+        let mut ppu = PPU::new(vec![0; 2048], Mirroring::Horizontal);
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0xe5);
+        ppu.write_to_data(0x17);
+        assert_eq!(ppu.palette_colour(0x05), 0x17);
+    }
+
+    // Test case for VBlank being raised at scanline 241 and cleared on the pre-render line
+    #[test]
+    fn test_vblank_timing() {
+        let mut ppu = PPU::new(vec![0; 2048], Mirroring::Horizontal);
+        for _ in 0..240 {
+            assert!(!ppu.tick(341));
+        }
+        assert!(ppu.tick(341));
+        assert_eq!(ppu.scanline, 241);
+        assert_eq!(ppu.read_status() & 0x80, 0x80);
+        ppu.tick(341 * 21);
+        assert_eq!(ppu.scanline, 0);
+    }
+
+    // Test case for odd NTSC frames with background rendering being one dot shorter
+    #[test]
+    fn test_odd_frame_skips_a_dot() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_to_mask(0b0000_1000); // Show the background
+
+        // Dots from one VBlank to the next, each span holding one pre-render line
+        let mut dots_to_vblank = || {
+            let mut dots = 1;
+            while !ppu.tick(1) {
+                dots += 1;
+            }
+            dots
+        };
+        dots_to_vblank();
+        let even = dots_to_vblank();
+        let odd = dots_to_vblank();
+        assert_eq!(even, 262 * 341);
+        assert_eq!(odd, even - 1);
+    }
+
+    // Test case for evaluation keeping eight of ten sprites on a line and flagging overflow
+    #[test]
+    fn test_sprite_evaluation_overflow() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.oam_data.fill(0xff); // Every sprite below the picture
+        for sprite in 1..11 {
+            let x = sprite as u8 * 10;
+            ppu.oam_data[sprite * 4..sprite * 4 + 4].copy_from_slice(&[50, 0, 0, x]);
+        }
+
+        let found = ppu.evaluate_sprites(52);
+        assert_eq!(found.count, 8);
+        assert!(found.overflow);
+        assert!(!found.sprite_zero);
+        assert_eq!(found.data[..], ppu.oam_data[4..36]); // Sprites 1-8 in OAM order
+        assert_eq!(ppu.evaluate_sprites(49).count, 0);
+
+        // Overflow shows in PPUSTATUS once rendering reaches the line, until pre-render
+        ppu.write_to_mask(0b0001_0000);
+        ppu.tick(341 * 50);
+        assert_eq!(ppu.peek_status() & 0b0010_0000, 0b0010_0000);
+        assert_eq!(ppu.secondary_oam().count, 8);
+        while ppu.scanline != 261 {
+            ppu.tick(341);
+        }
+        assert_eq!(ppu.peek_status() & 0b0010_0000, 0);
+    }
+}
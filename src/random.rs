@@ -0,0 +1,34 @@
+// Source of the random bytes used for power-on state, injectable so runs can be
+// made reproducible
+pub trait RandomSource {
+    fn next_u8(&mut self) -> u8;
+}
+
+// Seedable xorshift64 generator, the default source
+pub struct XorShift {
+    state: u64, // Never zero, which would make every later state zero
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        XorShift { state: seed.max(1) }
+    }
+}
+
+impl RandomSource for XorShift {
+    fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 56) as u8
+    }
+}
+
+// Source returning the same byte every time, for tests
+pub struct FixedRandom(pub u8);
+
+impl RandomSource for FixedRandom {
+    fn next_u8(&mut self) -> u8 {
+        self.0
+    }
+}
@@ -0,0 +1,377 @@
+use crate::frame::Frame;
+use crate::palette::SYSTEM_PALETTE;
+use crate::ppu::{SecondaryOam, PPU};
+
+// Colours of one of the four background palettes for a tile of the given nametable
+fn bg_palette(ppu: &PPU, nametable: u16, tile_column: usize, tile_row: usize) -> [u8; 4] {
+    // Each attribute byte covers a 4x4 tile area, two bits per 2x2 quadrant
+    let attr_table_idx = tile_row / 4 * 8 + tile_column / 4;
+    let attr_byte = ppu.nametable_byte(nametable + 0x3c0 + attr_table_idx as u16);
+
+    let palette_idx = match (tile_column % 4 / 2, tile_row % 4 / 2) {
+        (0, 0) => attr_byte & 0b11,
+        (1, 0) => (attr_byte >> 2) & 0b11,
+        (0, 1) => (attr_byte >> 4) & 0b11,
+        (1, 1) => (attr_byte >> 6) & 0b11,
+        _ => unreachable!(),
+    };
+
+    let palette_start = 1 + palette_idx * 4;
+    [
+        ppu.palette_colour(0),
+        ppu.palette_colour(palette_start),
+        ppu.palette_colour(palette_start + 1),
+        ppu.palette_colour(palette_start + 2),
+    ]
+}
+
+// Colours of one of the four sprite palettes (entry 0 is transparent)
+fn sprite_palette(ppu: &PPU, palette_idx: u8) -> [u8; 4] {
+    let start = 0x11 + palette_idx * 4;
+    [
+        0,
+        ppu.palette_colour(start),
+        ppu.palette_colour(start + 1),
+        ppu.palette_colour(start + 2),
+    ]
+}
+
+// Two-bit colour index of pixel (x, y) within a pattern table tile
+fn tile_pixel(ppu: &PPU, bank: u16, tile_idx: u16, x: usize, y: usize) -> u8 {
+    let tile_start = bank + tile_idx * 16;
+    let upper = ppu.chr_byte(tile_start + y as u16);
+    let lower = ppu.chr_byte(tile_start + y as u16 + 8);
+    let shift = 7 - x;
+    (((lower >> shift) & 1) << 1) | ((upper >> shift) & 1)
+}
+
+// Look up an RGB colour from PPU palette RAM contents
+fn rgb(colour: u8) -> (u8, u8, u8) {
+    SYSTEM_PALETTE[(colour & 0x3f) as usize]
+}
+
+// Draw line y of the background, starting at the nametable selected by PPUCTRL and
+// offset by PPUSCROLL; the four nametables form a 512x480 plane that wraps around
+// Where PPUMASK hides the background, the backdrop colour shows instead
+// Returns which pixels of the line the background covers with a non-zero colour index
+fn render_background_line(ppu: &PPU, frame: &mut Frame, y: usize) -> [bool; Frame::WIDTH] {
+    let mut opaque = [false; Frame::WIDTH];
+    let bank = ppu.bknd_pattern_addr();
+    let backdrop = rgb(ppu.backdrop_colour());
+    let base = (ppu.nametable_addr() - 0x2000) / 0x400;
+    let (scroll_x, scroll_y) = ppu.scroll();
+
+    let plane_y = (y + scroll_y as usize + (base as usize >> 1) * 240) % 480;
+    for (screen_x, pixel_opaque) in opaque.iter_mut().enumerate() {
+        let visible = ppu.show_background() && (screen_x >= 8 || ppu.show_background_left());
+        if !visible {
+            frame.set_pixel(screen_x, y, backdrop);
+            continue;
+        }
+
+        let plane_x = (screen_x + scroll_x as usize + (base as usize & 1) * 256) % 512;
+        let nametable = 0x2000 + 0x400 * (plane_x / 256 + plane_y / 240 * 2) as u16;
+        let tile_column = plane_x % 256 / 8;
+        let tile_row = plane_y % 240 / 8;
+        let tile_idx = ppu.nametable_byte(nametable + (tile_row * 32 + tile_column) as u16);
+        let palette = bg_palette(ppu, nametable, tile_column, tile_row);
+        let value = tile_pixel(ppu, bank, tile_idx as u16, plane_x % 8, plane_y % 8);
+        *pixel_opaque = value != 0;
+        frame.set_pixel(screen_x, y, rgb(palette[value as usize]));
+    }
+    opaque
+}
+
+// Pattern table and tile index of row y (0-15) of a sprite
+// 8x8 sprites use the table from PPUCTRL; 8x16 sprites take it from bit 0 of the
+// tile index and stack the even tile above the odd tile after it
+fn sprite_tile(ppu: &PPU, tile_idx: u8, y: usize) -> (u16, u16) {
+    if ppu.sprite_size() == 16 {
+        let bank = (tile_idx as u16 & 1) * 0x1000;
+        let top = tile_idx as u16 & 0xfe;
+        (bank, top + (y / 8) as u16)
+    } else {
+        (ppu.sprt_pattern_addr(), tile_idx as u16)
+    }
+}
+
+// Draw the rows on line y of the sprites that evaluation found, lowest index on top
+// Each pixel takes the first opaque sprite pixel at it; a sprite with the priority bit
+// set then shows only where the background is transparent. Like the hardware, such a
+// sprite still hides the higher-index sprites beneath it
+fn render_sprites_line(
+    ppu: &PPU,
+    frame: &mut Frame,
+    y: usize,
+    sprites: &SecondaryOam,
+    background: &[bool; Frame::WIDTH],
+) {
+    if !ppu.show_sprites() {
+        return;
+    }
+    let height = ppu.sprite_size() as usize;
+    let mut covered = [false; Frame::WIDTH]; // Pixels already taken by a sprite
+
+    for sprite in sprites.data[..sprites.count * 4].chunks_exact(4) {
+        let tile_y = sprite[0] as usize;
+        let tile_idx = sprite[1];
+        let attributes = sprite[2];
+        let tile_x = sprite[3] as usize;
+
+        let flip_vertical = attributes & 0b1000_0000 != 0;
+        let flip_horizontal = attributes & 0b0100_0000 != 0;
+        let behind_background = attributes & 0b0010_0000 != 0;
+        let palette = sprite_palette(ppu, attributes & 0b11);
+
+        // Flipping an 8x16 sprite also swaps its two tiles
+        let row = if flip_vertical {
+            height - 1 - (y - tile_y)
+        } else {
+            y - tile_y
+        };
+        let (bank, tile) = sprite_tile(ppu, tile_idx, row);
+        for x in 0..8 {
+            let value = tile_pixel(ppu, bank, tile, x, row % 8);
+            if value == 0 {
+                continue; // Transparent
+            }
+            let px = if flip_horizontal { 7 - x } else { x };
+            let screen_x = tile_x + px;
+            if screen_x < 8 && !ppu.show_sprites_left() {
+                continue; // Clipped from the leftmost column
+            }
+            if screen_x >= Frame::WIDTH || covered[screen_x] {
+                continue;
+            }
+            covered[screen_x] = true;
+            if behind_background && background[screen_x] {
+                continue;
+            }
+            frame.set_pixel(screen_x, y, rgb(palette[value as usize]));
+        }
+    }
+}
+
+// Compose line y of the picture from the current PPU state and the sprites
+// evaluated as the line started
+pub fn render_scanline(ppu: &PPU, frame: &mut Frame, y: usize) {
+    let background = render_background_line(ppu, frame, y);
+    render_sprites_line(ppu, frame, y, ppu.secondary_oam(), &background);
+}
+
+// Compose the whole picture from the current PPU state, as if no register changed
+// during the frame
+pub fn render(ppu: &PPU, frame: &mut Frame) {
+    for y in 0..Frame::HEIGHT {
+        let background = render_background_line(ppu, frame, y);
+        render_sprites_line(ppu, frame, y, &ppu.evaluate_sprites(y), &background);
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::Mirroring;
+
+    // PPUMASK value enabling background and sprites everywhere
+    const SHOW_ALL: u8 = 0b0001_1110;
+
+    // Test case for a background tile being drawn with its palette colour
+    #[test]
+    fn test_render_background_tile() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_to_mask(SHOW_ALL);
+        ppu.chr_rom[16] = 0b1000_0000; // Tile 1, top-left pixel uses colour 1
+        ppu.vram[0] = 1; // Top-left tile of the first nametable
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x30;
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(0, 0), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.pixel(1, 0), SYSTEM_PALETTE[0x0f]);
+    }
+
+    // Test case for a sprite being drawn over the background
+    #[test]
+    fn test_render_sprite() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_to_mask(SHOW_ALL);
+        ppu.chr_rom[2 * 16] = 0b1000_0000; // Tile 2, top-left pixel uses colour 1
+        ppu.oam_data[0..4].copy_from_slice(&[10, 2, 0, 20]); // y, tile, attributes, x
+        ppu.palette_table[0x11] = 0x16;
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(20, 10), SYSTEM_PALETTE[0x16]);
+    }
+
+    // Test case for an 8x16 sprite drawing both halves from the selected pattern table
+    #[test]
+    fn test_render_sprite_8x16() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_to_mask(SHOW_ALL);
+        ppu.write_to_ctrl(0b0010_0000); // 8x16 sprites
+        ppu.chr_rom[0x1000 + 2 * 16] = 0b1000_0000; // Top tile: left pixel, colour 1
+        ppu.chr_rom[0x1000 + 3 * 16 + 8] = 0b0100_0000; // Bottom tile: second pixel, colour 2
+        ppu.chr_rom[2 * 16] = 0b1111_1111; // Same tile in the other table must not be used
+        ppu.oam_data[0..4].copy_from_slice(&[10, 0x03, 0, 20]); // Odd index: table 0x1000
+        ppu.palette_table[0x11] = 0x16;
+        ppu.palette_table[0x12] = 0x2a;
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(20, 10), SYSTEM_PALETTE[0x16]);
+        assert_ne!(frame.pixel(21, 10), SYSTEM_PALETTE[0x16]);
+        assert_eq!(frame.pixel(21, 18), SYSTEM_PALETTE[0x2a]);
+    }
+
+    // Test case for PPUMASK bit 3 hiding the background behind the backdrop colour
+    #[test]
+    fn test_background_disabled_shows_backdrop() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_to_mask(SHOW_ALL & !0b0000_1000);
+        ppu.chr_rom[16] = 0b1000_0000;
+        ppu.vram[1] = 1; // Second tile, so the pixel is outside the left column
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x30;
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(8, 0), SYSTEM_PALETTE[0x0f]);
+    }
+
+    // Test case for PPUMASK bit 4 hiding sprites
+    #[test]
+    fn test_sprites_disabled() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_to_mask(SHOW_ALL & !0b0001_0000);
+        ppu.chr_rom[2 * 16] = 0b1000_0000;
+        ppu.oam_data[0..4].copy_from_slice(&[10, 2, 0, 20]);
+        ppu.palette_table[0x11] = 0x16;
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_ne!(frame.pixel(20, 10), SYSTEM_PALETTE[0x16]);
+    }
+
+    // Test case for PPUMASK bits 1 and 2 clipping the leftmost 8 pixels
+    #[test]
+    fn test_left_column_clipping() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.chr_rom[16] = 0b1000_0000; // Tile 1: left pixel, colour 1
+        ppu.vram[0] = 1;
+        ppu.vram[1] = 1;
+        ppu.oam_data[0..4].copy_from_slice(&[20, 1, 0, 0]); // Sprite in the left column
+        ppu.oam_data[4..8].copy_from_slice(&[20, 1, 0, 8]); // Sprite just right of it
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x30;
+        ppu.palette_table[0x11] = 0x16;
+
+        ppu.write_to_mask(0b0001_1000); // Both layers on, left column clipped
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(0, 0), SYSTEM_PALETTE[0x0f]);
+        assert_eq!(frame.pixel(8, 0), SYSTEM_PALETTE[0x30]);
+        assert_ne!(frame.pixel(0, 20), SYSTEM_PALETTE[0x16]);
+        assert_eq!(frame.pixel(8, 20), SYSTEM_PALETTE[0x16]);
+
+        ppu.write_to_mask(SHOW_ALL);
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(0, 0), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.pixel(0, 20), SYSTEM_PALETTE[0x16]);
+    }
+
+    // Test case for PPUSCROLL shifting the background and wrapping into the next nametable
+    #[test]
+    fn test_scrolled_background() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Vertical);
+        ppu.write_to_mask(SHOW_ALL);
+        ppu.chr_rom[16] = 0b1000_0000; // Tile 1, top-left pixel uses colour 1
+        ppu.vram[2 * 32 + 1] = 1; // Tile (1, 2) of the first nametable
+        ppu.vram[0x400] = 1; // Top-left tile of the second nametable
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x30;
+        ppu.write_to_scroll(4);
+        ppu.write_to_scroll(10);
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(4, 6), SYSTEM_PALETTE[0x30]);
+        assert_eq!(frame.pixel(252, 0), SYSTEM_PALETTE[0x0f]);
+        assert_eq!(frame.pixel(252, 230), SYSTEM_PALETTE[0x30]); // Wrapped down to its mirror
+
+        ppu.write_to_scroll(0);
+        ppu.write_to_scroll(0);
+        ppu.write_to_ctrl(0b01); // Start from the second nametable
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(0, 0), SYSTEM_PALETTE[0x30]);
+    }
+
+    // Test case for only the first eight sprites on a line being drawn
+    #[test]
+    fn test_ninth_sprite_on_a_line_is_dropped() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_to_mask(SHOW_ALL);
+        ppu.chr_rom[2 * 16] = 0b1000_0000; // Tile 2, top-left pixel uses colour 1
+        ppu.oam_data.fill(0xff);
+        for sprite in 0..10 {
+            let x = 16 + sprite as u8 * 10;
+            ppu.oam_data[sprite * 4..sprite * 4 + 4].copy_from_slice(&[10, 2, 0, x]);
+        }
+        ppu.palette_table[0x11] = 0x16;
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(16 + 7 * 10, 10), SYSTEM_PALETTE[0x16]);
+        assert_ne!(frame.pixel(16 + 8 * 10, 10), SYSTEM_PALETTE[0x16]);
+        assert_ne!(frame.pixel(16 + 9 * 10, 10), SYSTEM_PALETTE[0x16]);
+    }
+
+    // Test case for PPUADDR in palette RAM with rendering off replacing the backdrop
+    #[test]
+    fn test_palette_address_overrides_backdrop() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_to_mask(0);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[5] = 0x16;
+        let mut frame = Frame::new();
+        render_scanline(&ppu, &mut frame, 0);
+
+        // Pointing PPUADDR at 0x3F05 part way down the picture recolours later lines
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x05);
+        render_scanline(&ppu, &mut frame, 1);
+        assert_eq!(frame.pixel(100, 0), SYSTEM_PALETTE[0x0f]);
+        assert_eq!(frame.pixel(100, 1), SYSTEM_PALETTE[0x16]);
+
+        // With the background enabled the backdrop comes from 0x3F00 again
+        ppu.write_to_mask(SHOW_ALL);
+        render_scanline(&ppu, &mut frame, 2);
+        assert_eq!(frame.pixel(100, 2), SYSTEM_PALETTE[0x0f]);
+    }
+
+    // Test case for a behind-background sprite showing only over transparent background
+    #[test]
+    fn test_sprite_priority_behind_background() {
+        let mut ppu = PPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_to_mask(SHOW_ALL);
+        ppu.chr_rom[16..24].fill(0xff); // Tile 1: solid colour 1
+        ppu.chr_rom[2 * 16] = 0xff; // Tile 2: top row in colour 1
+        ppu.vram[32 + 3] = 1; // Background tile covering x 24-31, y 8-15
+        ppu.oam_data.fill(0xff);
+        ppu.oam_data[0..4].copy_from_slice(&[10, 2, 0b0010_0000, 20]); // Behind, x 20-27
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x30;
+        ppu.palette_table[0x11] = 0x16;
+        let mut frame = Frame::new();
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(20, 10), SYSTEM_PALETTE[0x16]); // In front of the backdrop
+        assert_eq!(frame.pixel(24, 10), SYSTEM_PALETTE[0x30]); // Behind the background
+
+        // A front sprite underneath is still hidden wherever the behind sprite is opaque
+        ppu.oam_data[4..8].copy_from_slice(&[10, 2, 0b01, 20]);
+        ppu.palette_table[0x15] = 0x2a;
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(20, 10), SYSTEM_PALETTE[0x16]);
+        assert_eq!(frame.pixel(24, 10), SYSTEM_PALETTE[0x30]);
+
+        ppu.oam_data[2] = 0; // In front of the background
+        render(&ppu, &mut frame);
+        assert_eq!(frame.pixel(24, 10), SYSTEM_PALETTE[0x16]);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::error::EmuError;
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::RangeInclusive;
+
+// Save states start with this tag and a format version
+const STATE_TAG: [u8; 4] = *b"NESS";
+const STATE_VERSION: u8 = 2;
+
+// Fingerprint of a ROM image (64-bit FNV-1a), recorded in save slots so a slot is
+// only loaded back into the game it was saved from
+pub fn rom_hash(raw: &[u8]) -> u64 {
+    raw.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// Check a restored field against the values the emulator can run from, so a corrupt
+// state is rejected instead of panicking later
+pub fn in_range<T: PartialOrd + fmt::Display>(
+    field: &str,
+    value: T,
+    range: RangeInclusive<T>,
+) -> Result<T, EmuError> {
+    if range.contains(&value) {
+        Ok(value)
+    } else {
+        Err(EmuError::InvalidSaveState(format!(
+            "{} {} out of range",
+            field, value
+        )))
+    }
+}
+
+// Component whose state can be written out and read back
+// read_state only stores values that are in range for the component
+pub trait SaveState {
+    fn write_state(&self, writer: &mut StateWriter);
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmuError>;
+}
+
+// Little-endian byte buffer that a save state is written into
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    // Constructor to create a new writer, starting with the tag and version
+    pub fn new() -> Self {
+        let mut data = STATE_TAG.to_vec();
+        data.push(STATE_VERSION);
+        StateWriter { data }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // A block of bytes, preceded by its length
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    // The finished save state
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Reads values back in the order a StateWriter wrote them
+pub struct StateReader<'a> {
+    data: &'a [u8],  // The whole save state
+    position: usize, // Offset of the next unread byte
+}
+
+impl<'a> StateReader<'a> {
+    // Constructor to create a new reader, checking the tag and version
+    pub fn new(data: &'a [u8]) -> Result<Self, EmuError> {
+        let mut reader = StateReader { data, position: 0 };
+        if reader.take(4)? != STATE_TAG {
+            return Err(EmuError::InvalidSaveState(
+                "missing save state tag".to_string(),
+            ));
+        }
+        let version = reader.u8()?;
+        if version != STATE_VERSION {
+            return Err(EmuError::InvalidSaveState(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        Ok(reader)
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], EmuError> {
+        let end = self.position + count;
+        if end > self.data.len() {
+            return Err(EmuError::InvalidSaveState(
+                "unexpected end of data".to_string(),
+            ));
+        }
+        let slice = &self.data[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, EmuError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, EmuError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, EmuError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, EmuError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, EmuError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    // A length-prefixed block of bytes
+    pub fn bytes(&mut self) -> Result<Vec<u8>, EmuError> {
+        let length = self.u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    // A length-prefixed block of bytes into a fixed-size buffer, which must match its length
+    pub fn bytes_into(&mut self, buffer: &mut [u8]) -> Result<(), EmuError> {
+        let bytes = self.bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(EmuError::InvalidSaveState(format!(
+                "expected {} bytes, found {}",
+                buffer.len(),
+                bytes.len()
+            )));
+        }
+        buffer.copy_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::hardware::CPU;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    // Run one frame and hash the picture it produced
+    fn frame_hash(cpu: &mut CPU) -> u64 {
+        cpu.run_frame().unwrap();
+        let mut hasher = DefaultHasher::new();
+        cpu.bus.frame.data.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Test case for a loaded state replaying the same frames as the original run
+    #[test]
+    fn test_save_state_round_trip_is_deterministic() {
+        let mut cpu = CPU::new();
+        // Keep changing the backdrop colour so every frame looks different
+        cpu.load(assemble(&[
+            "LDA #$3F",
+            "STA $2006",
+            "LDA #$00",
+            "STA $2006",
+            "INC $10",
+            "LDA $10",
+            "STA $2007",
+            "JMP $8000",
+        ]));
+        cpu.reset();
+        for _ in 0..3 {
+            frame_hash(&mut cpu);
+        }
+
+        let state = cpu.save_state();
+        let expected: Vec<u64> = (0..3).map(|_| frame_hash(&mut cpu)).collect();
+
+        let mut restored = CPU::new();
+        restored.load_state(&state).unwrap();
+        let replayed: Vec<u64> = (0..3).map(|_| frame_hash(&mut restored)).collect();
+        assert_eq!(replayed, expected);
+        assert_eq!(restored.registers(), cpu.registers());
+    }
+
+    // Test case for a state saved on bank-switching cartridges restoring into a fresh CPU,
+    // with the same bank, picture and sound afterwards
+    #[test]
+    fn test_save_state_round_trip_on_banked_cartridge() {
+        // UxROM takes its bank number in one write, MMC1 five bits at a time
+        let uxrom = assemble(&["LDA #$02", "STA $8000"]);
+        let mut mmc1 = assemble(&["LDA #$02"]);
+        for _ in 0..5 {
+            mmc1.extend(assemble(&["STA $E000", "LSR"]));
+        }
+        for (flags_6, select_bank) in [(0x20, uxrom), (0x10, mmc1)] {
+            // A tone on pulse 1 and a counter, looping in the fixed bank at 0xC010
+            let looped = 0xC010 + select_bank.len() + 20;
+            let mut program = select_bank;
+            program.extend(assemble(&[
+                "LDA #$01",
+                "STA $4015",
+                "LDA #$BF",
+                "STA $4000",
+                "LDA #$40",
+                "STA $4002",
+                "LDA #$00",
+                "STA $4003",
+                "INC $10",
+                &format!("JMP ${:04X}", looped),
+            ]));
+            let mut raw = crate::cartridge::test::test_rom_bytes(flags_6, 0x00, 4, 1);
+            for bank in 0..4 {
+                raw[16 + bank * 0x4000] = bank as u8;
+            }
+            let fixed = 16 + 3 * 0x4000;
+            raw[fixed + 0x10..fixed + 0x10 + program.len()].copy_from_slice(&program);
+            raw[fixed + 0x3ffc..fixed + 0x3ffe].copy_from_slice(&[0x10, 0xc0]);
+
+            let mut cpu = CPU::new();
+            cpu.load_rom(&raw).unwrap();
+            frame_hash(&mut cpu);
+            cpu.bus.apu.clear_samples();
+            let state = cpu.save_state();
+            let expected: Vec<u64> = (0..2).map(|_| frame_hash(&mut cpu)).collect();
+
+            let mut restored = CPU::new();
+            restored.load_rom(&raw).unwrap();
+            restored.load_state(&state).unwrap();
+            let replayed: Vec<u64> = (0..2).map(|_| frame_hash(&mut restored)).collect();
+            assert_eq!(restored.bus.prg_bank(), 2);
+            assert_eq!(restored.peek(0x8000), 2);
+            assert_eq!(replayed, expected);
+            assert_eq!(restored.bus.apu.samples(), cpu.bus.apu.samples());
+            assert!(cpu.bus.apu.samples().iter().any(|&sample| sample > 0.0));
+            assert_eq!(restored.save_state(), cpu.save_state());
+        }
+    }
+
+    // Test case for rejecting data that is not a save state
+    #[test]
+    fn test_load_state_rejects_bad_data() {
+        let mut cpu = CPU::new();
+        assert!(matches!(
+            cpu.load_state(b"junk"),
+            Err(EmuError::InvalidSaveState(_))
+        ));
+        let state = cpu.save_state();
+        assert!(matches!(
+            cpu.load_state(&state[..state.len() - 1]),
+            Err(EmuError::InvalidSaveState(_))
+        ));
+    }
+
+    // Test case for truncated states and out-of-range fields failing to load, leaving a
+    // console that still runs
+    #[test]
+    fn test_load_state_rejects_corrupt_fields() {
+        let mut cpu = CPU::new();
+        let state = cpu.save_state();
+        for length in (0..state.len()).step_by(997) {
+            assert!(cpu.load_state(&state[..length]).is_err());
+        }
+
+        // Nametable RAM too short for the mirroring
+        cpu.bus.ppu.vram.truncate(0x100);
+        let state = cpu.save_state();
+        let mut restored = CPU::new();
+        assert!(matches!(
+            restored.load_state(&state),
+            Err(EmuError::InvalidSaveState(_))
+        ));
+        restored.load(assemble(&[
+            "LDA #$3F",
+            "STA $2006",
+            "STA $2006",
+            "STA $2007",
+        ]));
+        restored.reset();
+        restored.run_frame().unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use crate::error::EmuError;
+use crate::hardware::CPU;
+use crate::joypad::JoypadButton;
+
+// Emulator handle for browser builds (wasm32-unknown-unknown)
+// Every argument is a primitive and the picture and sound are handed out as slices or
+// raw pointers into linear memory, so JavaScript can read them without a copy. Nothing
+// here allocates per frame, blocks or spawns threads; the page drives run_frame() from
+// requestAnimationFrame
+pub struct WasmEmulator {
+    cpu: CPU,
+}
+
+impl WasmEmulator {
+    // Constructor with a blank cartridge, until load_rom() is called
+    pub fn new() -> Self {
+        WasmEmulator { cpu: CPU::new() }
+    }
+
+    // Mount an iNES image fetched by the page
+    pub fn load_rom(&mut self, raw: &[u8]) -> Result<(), EmuError> {
+        self.cpu.load_rom(raw)
+    }
+
+    // Emulate until the next frame is complete, returning false if the program stopped
+    pub fn run_frame(&mut self) -> bool {
+        self.cpu.run_frame().is_ok()
+    }
+
+    // Last rendered picture as packed RGB, Frame::WIDTH * Frame::HEIGHT * 3 bytes
+    pub fn frame(&self) -> &[u8] {
+        &self.cpu.bus.frame.data
+    }
+
+    pub fn frame_ptr(&self) -> *const u8 {
+        self.frame().as_ptr()
+    }
+
+    pub fn frame_len(&self) -> usize {
+        self.frame().len()
+    }
+
+    // Audio collected since the last clear_audio(), at apu::SAMPLE_RATE
+    pub fn audio(&self) -> &[f32] {
+        self.cpu.bus.apu.samples()
+    }
+
+    pub fn audio_ptr(&self) -> *const f32 {
+        self.audio().as_ptr()
+    }
+
+    pub fn audio_len(&self) -> usize {
+        self.audio().len()
+    }
+
+    // Drop the collected audio once the page has queued it
+    pub fn clear_audio(&mut self) {
+        self.cpu.bus.apu.clear_samples();
+    }
+
+    // Press or release a controller 1 button by its index in JoypadButton order (A = 0)
+    // Indexes past Right (7) are ignored
+    pub fn set_button(&mut self, index: u8, pressed: bool) {
+        if let Some(button) = JoypadButton::from_index(index) {
+            self.cpu
+                .bus
+                .joypad1
+                .set_button_pressed_status(button, pressed);
+        }
+    }
+}
+
+impl Default for WasmEmulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Unit test module
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::frame::Frame;
+
+    // Test case for the frame and audio accessors describing the emulator's buffers
+    #[test]
+    fn test_frame_and_audio_slices() {
+        let mut emulator = WasmEmulator::new();
+        emulator.cpu.load(assemble(&["JMP $8000"]));
+        emulator.cpu.reset();
+        assert!(emulator.run_frame());
+
+        assert_eq!(emulator.frame_len(), Frame::WIDTH * Frame::HEIGHT * 3);
+        assert_eq!(emulator.frame_ptr(), emulator.cpu.bus.frame.data.as_ptr());
+        assert!(emulator.audio_len() > 0);
+        assert_eq!(emulator.audio_ptr(), emulator.audio().as_ptr());
+        emulator.clear_audio();
+        assert_eq!(emulator.audio_len(), 0);
+    }
+
+    // Test case for set_button mapping indexes onto controller buttons
+    #[test]
+    fn test_set_button_by_index() {
+        let mut emulator = WasmEmulator::new();
+        emulator.set_button(3, true); // Start
+        emulator.set_button(8, true); // Out of range, ignored
+        emulator.cpu.bus.mem_write(0x4016, 1);
+        emulator.cpu.bus.mem_write(0x4016, 0);
+        let reads: Vec<u8> = (0..8)
+            .map(|_| emulator.cpu.bus.mem_read(0x4016) & 1)
+            .collect();
+        assert_eq!(reads, vec![0, 0, 0, 1, 0, 0, 0, 0]);
+    }
+}